@@ -0,0 +1,25 @@
+//! Repeats every message as a reply, answers `!ping`, and whispers back what
+//! follows `!whisper`.
+//!
+//! ```sh
+//! cargo run -p chat_bot --example echo -- ws://127.0.0.1:8080
+//! ```
+
+use chat_bot::Bot;
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "ws://127.0.0.1:8080".to_string());
+
+    Bot::new(url, "echo")
+        .on_message(|ctx, message| async move { ctx.reply(&message, &message.message) })
+        .on_command("ping", |ctx, message, _| async move { ctx.reply(&message, "pong") })
+        .on_command("whisper", |ctx, message, command| async move {
+            ctx.dm(&message.from, &command.rest)
+        })
+        .run()
+        .await;
+}
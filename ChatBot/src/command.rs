@@ -0,0 +1,96 @@
+//! Parsing of bot commands such as `!roll 2d6 "for luck"`.
+
+use std::str::FromStr;
+
+/// A command addressed to a bot: a name after the prefix, then arguments
+/// separated by spaces. Double quotes group words into one argument.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<String>,
+    /// Everything after the name, as typed.
+    pub rest: String,
+}
+
+impl Command {
+    /// Parses `text` if it starts with `prefix` directly followed by a name.
+    pub fn parse(text: &str, prefix: &str) -> Option<Self> {
+        let line = text.trim().strip_prefix(prefix)?;
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (line, ""),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        Some(Self {
+            name: name.to_lowercase(),
+            args: split_args(rest),
+            rest: rest.to_string(),
+        })
+    }
+
+    /// The argument at `index` converted to `T`, `None` when it is missing or
+    /// does not parse.
+    pub fn arg<T: FromStr>(&self, index: usize) -> Option<T> {
+        self.args.get(index)?.parse().ok()
+    }
+}
+
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    // Distinguishes `""` from no argument at all
+    let mut started = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    args.push(std::mem::take(&mut arg));
+                    started = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        args.push(arg);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_and_arguments() {
+        let command = Command::parse(r#"!Roll 2d6  "for luck" """#, "!").unwrap();
+        assert_eq!(command.name, "roll");
+        assert_eq!(command.args, ["2d6", "for luck", ""]);
+        assert_eq!(command.rest, r#"2d6  "for luck" """#);
+    }
+
+    #[test]
+    fn converts_arguments() {
+        let command = Command::parse("!add 2 three", "!").unwrap();
+        assert_eq!(command.arg::<i64>(0), Some(2));
+        assert_eq!(command.arg::<i64>(1), None);
+        assert_eq!(command.arg::<i64>(2), None);
+    }
+
+    #[test]
+    fn ignores_other_text() {
+        assert_eq!(Command::parse("hello !roll", "!"), None);
+        assert_eq!(Command::parse("! roll", "!"), None);
+        assert_eq!(Command::parse("!", "!"), None);
+    }
+}
@@ -0,0 +1,82 @@
+//! The handle handlers use to talk back to the chat.
+
+use std::sync::{Arc, Mutex};
+
+use chat_protocol::{ChatMessage, MessageData, MessageType, ReplyData, WebSocketMessage};
+use tokio::sync::mpsc;
+
+/// Sends frames on behalf of a bot. Cheap to clone and usable from any task;
+/// frames sent while the bot is reconnecting go out once it is back.
+#[derive(Clone)]
+pub struct Context {
+    pub(crate) name: Arc<Mutex<String>>,
+    pub(crate) outgoing: mpsc::UnboundedSender<WebSocketMessage>,
+}
+
+impl Context {
+    /// The name the bot is registered under, which changes after `/nick`.
+    pub fn name(&self) -> String {
+        self.name.lock().unwrap().clone()
+    }
+
+    /// Sends any frame.
+    pub fn send(&self, frame: WebSocketMessage) {
+        let _ = self.outgoing.send(frame);
+    }
+
+    /// Posts `text` to the chat. Slash commands work as they do for people.
+    pub fn say(&self, text: &str) {
+        self.send_message(MessageData {
+            text: text.to_string(),
+            ..Default::default()
+        });
+    }
+
+    /// Posts `text` quoting `message`, in its thread if it has one.
+    pub fn reply(&self, message: &ChatMessage, text: &str) {
+        let quoted = ReplyData {
+            id: message.id,
+            from: message.from.clone(),
+            message: message.message.clone(),
+            deleted: false,
+        };
+        self.send_message(MessageData {
+            text: text.to_string(),
+            reply_to: Some(serde_json::to_string(&quoted).unwrap()),
+            thread_id: message.thread_id,
+            ..Default::default()
+        });
+    }
+
+    /// Adds `emoji` to the reactions on `message`.
+    pub fn react(&self, message: &ChatMessage, emoji: &str) {
+        self.send_reaction(MessageType::React, message, emoji);
+    }
+
+    /// Takes back a reaction added with `react`.
+    pub fn unreact(&self, message: &ChatMessage, emoji: &str) {
+        self.send_reaction(MessageType::Unreact, message, emoji);
+    }
+
+    /// Sends `text` to `user` alone.
+    pub fn dm(&self, user: &str, text: &str) {
+        self.say(&format!("/msg {} {}", user, text));
+    }
+
+    fn send_message(&self, data: MessageData) {
+        self.send(frame(MessageType::Message, serde_json::to_string(&data).unwrap()));
+    }
+
+    fn send_reaction(&self, message_type: MessageType, message: &ChatMessage, emoji: &str) {
+        let data = serde_json::json!({ "id": message.id, "emoji": emoji });
+        self.send(frame(message_type, data.to_string()));
+    }
+}
+
+pub(crate) fn frame(message_type: MessageType, data: String) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type,
+        data: Some(data),
+        data_array: None,
+    }
+}
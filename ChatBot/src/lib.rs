@@ -0,0 +1,260 @@
+//! A small library for chat bots. A `Bot` connects to a server, registers,
+//! and hands what it receives to async handlers, which answer through a
+//! `Context`. Lost connections are retried with backoff and the bot
+//! registers again under the name it last had.
+//!
+//! ```no_run
+//! # async fn example() {
+//! chat_bot::Bot::new("ws://127.0.0.1:8080", "echo")
+//!     .on_message(|ctx, message| async move { ctx.reply(&message, &message.message) })
+//!     .on_command("ping", |ctx, message, _| async move { ctx.reply(&message, "pong") })
+//!     .run()
+//!     .await;
+//! # }
+//! ```
+
+mod command;
+mod context;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+pub use chat_protocol::{ChatMessage, MessageType, WebSocketMessage};
+pub use command::Command;
+pub use context::Context;
+
+use chat_protocol::RegisterData;
+use context::frame;
+
+/// First wait before reconnecting; it doubles up to `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Commands start with this unless `Bot::prefix` says otherwise. The slash
+/// is left to the server's own commands.
+const DEFAULT_PREFIX: &str = "!";
+
+type Handler<T> = Arc<dyn Fn(Context, T) -> BoxFuture<'static, ()> + Send + Sync>;
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+fn handler<T, F, Fut>(f: F) -> Handler<T>
+where
+    F: Fn(Context, T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Arc::new(move |ctx, value| Box::pin(f(ctx, value)))
+}
+
+#[derive(Default)]
+struct Handlers {
+    message: Vec<Handler<ChatMessage>>,
+    command: HashMap<String, Handler<(ChatMessage, Command)>>,
+    notice: Vec<Handler<String>>,
+    users: Vec<Handler<Vec<String>>>,
+}
+
+pub struct Bot {
+    url: String,
+    prefix: String,
+    secret: Option<String>,
+    handlers: Handlers,
+    context: Context,
+    outgoing: mpsc::UnboundedReceiver<WebSocketMessage>,
+}
+
+impl Bot {
+    /// A bot that will register as `name` on the server at `url`.
+    pub fn new(url: impl Into<String>, name: impl Into<String>) -> Self {
+        let (tx, outgoing) = mpsc::unbounded_channel();
+        Self {
+            url: url.into(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            secret: None,
+            handlers: Handlers::default(),
+            context: Context {
+                name: Arc::new(Mutex::new(name.into())),
+                outgoing: tx,
+            },
+            outgoing,
+        }
+    }
+
+    /// Sets what commands start with.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the server's moderator secret, for bots registering under a
+    /// moderator's name.
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// Calls `f` for every chat message from someone else, except commands
+    /// that have a handler of their own.
+    pub fn on_message<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Context, ChatMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.handlers.message.push(handler(f));
+        self
+    }
+
+    /// Calls `f` for messages holding the command `name`, such as `!roll`
+    /// for `roll`. Names are matched case-insensitively.
+    pub fn on_command<F, Fut>(mut self, name: &str, f: F) -> Self
+    where
+        F: Fn(Context, ChatMessage, Command) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let f = handler(move |ctx, (message, command)| f(ctx, message, command));
+        self.handlers.command.insert(name.to_lowercase(), f);
+        self
+    }
+
+    /// Calls `f` for notices from the server, such as command output and
+    /// direct messages.
+    pub fn on_notice<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Context, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.handlers.notice.push(handler(f));
+        self
+    }
+
+    /// Calls `f` with the user list each time it changes.
+    pub fn on_users<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Context, Vec<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.handlers.users.push(handler(f));
+        self
+    }
+
+    /// A handle for sending from outside the handlers, such as on a timer.
+    pub fn context(&self) -> Context {
+        self.context.clone()
+    }
+
+    /// Connects and handles frames for as long as the task runs, reconnecting
+    /// whenever the connection is lost.
+    pub async fn run(mut self) {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match connect_async(self.url.as_str()).await {
+                Ok((stream, _)) => {
+                    info!("Connected to {} as {}", self.url, self.context.name());
+                    backoff = INITIAL_BACKOFF;
+                    let reason = self.session(stream).await;
+                    warn!("Lost the connection to {}: {}", self.url, reason);
+                }
+                Err(e) => warn!("Cannot reach {}: {}", self.url, e),
+            }
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    // Registers, then relays frames both ways until the connection fails
+    async fn session(&mut self, stream: Stream) -> String {
+        let (mut sink, mut incoming) = stream.split();
+        let register = RegisterData {
+            name: self.context.name(),
+            secret: self.secret.clone(),
+        };
+        let register = frame(MessageType::Register, register.to_data());
+        if let Err(e) = sink.send(text(&register)).await {
+            return e.to_string();
+        }
+
+        loop {
+            tokio::select! {
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(frame) = serde_json::from_str::<WebSocketMessage>(&text) {
+                            self.dispatch(frame);
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => return "closed by the server".into(),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return e.to_string(),
+                },
+                // The bot holds a sender itself, so this never runs dry
+                Some(frame) = self.outgoing.recv() => {
+                    if let Err(e) = sink.send(text(&frame)).await {
+                        return e.to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    // Hands a frame to the handlers for it, each in a task of its own
+    fn dispatch(&self, frame: WebSocketMessage) {
+        let ctx = &self.context;
+        match frame.message_type {
+            MessageType::Register => {
+                if let Some(name) = frame.data {
+                    *ctx.name.lock().unwrap() = name;
+                }
+            }
+            MessageType::Users => {
+                let users = frame.data_array.unwrap_or_default();
+                for f in &self.handlers.users {
+                    tokio::spawn(f(ctx.clone(), users.clone()));
+                }
+            }
+            MessageType::Notice => {
+                let notice = frame.data.unwrap_or_default();
+                for f in &self.handlers.notice {
+                    tokio::spawn(f(ctx.clone(), notice.clone()));
+                }
+            }
+            MessageType::Message => {
+                let Some(message) = frame
+                    .data
+                    .and_then(|data| serde_json::from_str::<ChatMessage>(&data).ok())
+                else {
+                    return;
+                };
+                if message.from == ctx.name() {
+                    return;
+                }
+                let command = Command::parse(&message.message, &self.prefix).and_then(|command| {
+                    let f = self.handlers.command.get(&command.name)?;
+                    Some((f, command))
+                });
+                match command {
+                    Some((f, command)) => {
+                        tokio::spawn(f(ctx.clone(), (message, command)));
+                    }
+                    None => {
+                        for f in &self.handlers.message {
+                            tokio::spawn(f(ctx.clone(), message.clone()));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn text(frame: &WebSocketMessage) -> Message {
+    Message::Text(serde_json::to_string(frame).unwrap())
+}
@@ -0,0 +1,179 @@
+//! Bots talking to a server running in the test process.
+
+use chat_bot::{Bot, ChatMessage, Context};
+use rust_websocket_server::{serve, Config};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+
+/// How long to wait for something to arrive before the test fails. Covers a
+/// few reconnection attempts.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug)]
+enum Event {
+    Message(ChatMessage),
+    Notice(String),
+    Users(Vec<String>),
+}
+
+async fn start_server(listener: TcpListener) {
+    let config = Config {
+        http_addr: "127.0.0.1:0".to_string(),
+        ..Config::default()
+    };
+    tokio::spawn(serve(listener, config));
+}
+
+// A server on a port picked by the OS, returning its URL
+async fn server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    start_server(listener).await;
+    url
+}
+
+// A bot that does nothing but report what it receives
+fn observer(url: &str, name: &str) -> (Context, mpsc::UnboundedReceiver<Event>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (messages, notices, users) = (tx.clone(), tx.clone(), tx);
+    let bot = Bot::new(url, name)
+        .on_message(move |_, message| {
+            let _ = messages.send(Event::Message(message));
+            async {}
+        })
+        .on_notice(move |_, notice| {
+            let _ = notices.send(Event::Notice(notice));
+            async {}
+        })
+        .on_users(move |_, list| {
+            let _ = users.send(Event::Users(list));
+            async {}
+        });
+    let ctx = bot.context();
+    tokio::spawn(bot.run());
+    (ctx, rx)
+}
+
+fn spawn(bot: Bot) {
+    tokio::spawn(bot.run());
+}
+
+// Waits for an event `wanted` returns something for
+async fn expect<T>(
+    rx: &mut mpsc::UnboundedReceiver<Event>,
+    mut wanted: impl FnMut(Event) -> Option<T>,
+) -> T {
+    let wait = async {
+        while let Some(event) = rx.recv().await {
+            if let Some(found) = wanted(event) {
+                return found;
+            }
+        }
+        panic!("bot stopped");
+    };
+    timeout(EVENT_TIMEOUT, wait).await.expect("event never arrived")
+}
+
+async fn expect_users(rx: &mut mpsc::UnboundedReceiver<Event>, names: &[&str]) {
+    expect(rx, |event| match event {
+        Event::Users(users) if names.iter().all(|name| users.iter().any(|u| u == name)) => Some(()),
+        _ => None,
+    })
+    .await
+}
+
+async fn expect_message(rx: &mut mpsc::UnboundedReceiver<Event>, from: &str) -> ChatMessage {
+    expect(rx, |event| match event {
+        Event::Message(message) if message.from == from => Some(message),
+        _ => None,
+    })
+    .await
+}
+
+fn echo(url: &str) -> Bot {
+    Bot::new(url, "echo")
+        .on_message(|ctx, message| async move { ctx.reply(&message, &message.message) })
+        .on_command("add", |ctx, message, command| async move {
+            let sum = command.arg::<i64>(0).unwrap_or(0) + command.arg::<i64>(1).unwrap_or(0);
+            ctx.reply(&message, &sum.to_string())
+        })
+        .on_command("whisper", |ctx, message, command| async move {
+            ctx.dm(&message.from, &command.rest)
+        })
+}
+
+#[tokio::test]
+async fn replies_quote_the_message() {
+    let url = server().await;
+    spawn(echo(&url));
+    let (ann, mut events) = observer(&url, "ann");
+    expect_users(&mut events, &["ann", "echo"]).await;
+
+    ann.say("hello");
+    let reply = expect_message(&mut events, "echo").await;
+    assert_eq!(reply.message, "hello");
+    let quoted = reply.reply_to.expect("reply quotes nothing");
+    assert_eq!((quoted.from.as_str(), quoted.message.as_str()), ("ann", "hello"));
+}
+
+#[tokio::test]
+async fn commands_go_to_their_handler() {
+    let url = server().await;
+    spawn(echo(&url));
+    let (ann, mut events) = observer(&url, "ann");
+    expect_users(&mut events, &["ann", "echo"]).await;
+
+    ann.say("!ADD 2 3");
+    assert_eq!(expect_message(&mut events, "echo").await.message, "5");
+
+    // Commands without a handler are ordinary messages
+    ann.say("!nope");
+    assert_eq!(expect_message(&mut events, "echo").await.message, "!nope");
+}
+
+#[tokio::test]
+async fn direct_messages_reach_only_their_user() {
+    let url = server().await;
+    spawn(echo(&url));
+    let (ann, mut ann_events) = observer(&url, "ann");
+    let (_bob, mut bob_events) = observer(&url, "bob");
+    expect_users(&mut ann_events, &["ann", "bob", "echo"]).await;
+    expect_users(&mut bob_events, &["ann", "bob", "echo"]).await;
+
+    ann.say("!whisper psst");
+    let notice = expect(&mut ann_events, |event| match event {
+        Event::Notice(notice) if notice.contains("psst") => Some(notice),
+        _ => None,
+    })
+    .await;
+    assert_eq!(notice, "[echo -> you] psst");
+
+    // Bob sees everything after the whisper but not the whisper itself
+    ann.say("done");
+    let next = expect(&mut bob_events, |event| match event {
+        Event::Notice(notice) if notice.contains("psst") => Some(notice),
+        Event::Message(message) if message.message == "done" => Some(message.message),
+        _ => None,
+    })
+    .await;
+    assert_eq!(next, "done");
+}
+
+#[tokio::test]
+async fn reconnects_until_the_server_is_up() {
+    // Reserve a port, then leave it closed while the bot starts
+    let reserved = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = reserved.local_addr().unwrap();
+    drop(reserved);
+    let url = format!("ws://{}", address);
+    spawn(echo(&url));
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    start_server(TcpListener::bind(address).await.unwrap()).await;
+
+    let (ann, mut events) = observer(&url, "ann");
+    expect_users(&mut events, &["ann", "echo"]).await;
+    ann.say("anyone there?");
+    assert_eq!(expect_message(&mut events, "echo").await.message, "anyone there?");
+}
@@ -0,0 +1,164 @@
+//! Wire encodings of the protocol. Frames are JSON unless the client picks
+//! MessagePack or CBOR through `Sec-WebSocket-Protocol`; both ends build and
+//! read frames as JSON text and convert them at the socket.
+//!
+//! In the binary encodings the `data` of frames that carry JSON by contract
+//! (see `MessageType::has_json_data`) travels as a nested map or array, so
+//! payloads are compact too. Any other `data` stays a string as it was sent.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::MessageType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+/// Subprotocol names clients may offer, in no particular order.
+const PROTOCOLS: &[(&str, Encoding)] = &[
+    ("chat.json", Encoding::Json),
+    ("chat.msgpack", Encoding::MessagePack),
+    ("chat.cbor", Encoding::Cbor),
+];
+
+impl Encoding {
+    /// Picks the first subprotocol offered that is known, or `None` to
+    /// answer without one and speak JSON.
+    pub fn negotiate(offers: &str) -> Option<Self> {
+        offers.split(',').map(str::trim).find_map(|offer| {
+            PROTOCOLS
+                .iter()
+                .find(|(name, _)| *name == offer)
+                .map(|(_, encoding)| *encoding)
+        })
+    }
+
+    pub fn protocol(self) -> &'static str {
+        PROTOCOLS
+            .iter()
+            .find(|(_, encoding)| *encoding == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+
+    /// Re-encodes a JSON frame, `None` when it is not valid JSON.
+    pub fn encode(self, json: &str) -> Option<Vec<u8>> {
+        let mut frame: Value = serde_json::from_str(json).ok()?;
+        let json_data = frame
+            .get("messageType")
+            .and_then(|kind| MessageType::deserialize(kind).ok())
+            .is_some_and(MessageType::has_json_data);
+        if let Some(data) = frame.get_mut("data").filter(|_| json_data) {
+            let nested = data
+                .as_str()
+                .filter(|text| text.starts_with('{') || text.starts_with('['))
+                .and_then(|text| serde_json::from_str(text).ok());
+            if let Some(nested) = nested {
+                *data = nested;
+            }
+        }
+
+        match self {
+            Encoding::Json => Some(frame.to_string().into_bytes()),
+            Encoding::MessagePack => rmp_serde::to_vec(&frame).ok(),
+            Encoding::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(&frame, &mut bytes).ok()?;
+                Some(bytes)
+            }
+        }
+    }
+
+    /// Turns a frame received in this encoding back into JSON text.
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        let mut frame: Value = match self {
+            Encoding::Json => serde_json::from_slice(bytes).ok()?,
+            Encoding::MessagePack => rmp_serde::from_slice(bytes).ok()?,
+            Encoding::Cbor => ciborium::from_reader(bytes).ok()?,
+        };
+        if let Some(data) = frame.get_mut("data") {
+            if data.is_object() || data.is_array() {
+                *data = Value::String(data.to_string());
+            }
+        }
+        Some(frame.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: [Encoding; 2] = [Encoding::MessagePack, Encoding::Cbor];
+
+    fn round_trip(encoding: Encoding, json: &str) -> Value {
+        let bytes = encoding.encode(json).unwrap();
+        serde_json::from_str(&encoding.decode(&bytes).unwrap()).unwrap()
+    }
+
+    fn raw(encoding: Encoding, json: &str) -> Value {
+        let bytes = encoding.encode(json).unwrap();
+        match encoding {
+            Encoding::MessagePack => rmp_serde::from_slice(&bytes).unwrap(),
+            Encoding::Cbor => ciborium::from_reader(bytes.as_slice()).unwrap(),
+            Encoding::Json => serde_json::from_slice(&bytes).unwrap(),
+        }
+    }
+
+    #[test]
+    fn negotiates_the_first_known_protocol() {
+        assert_eq!(
+            Encoding::negotiate("chat.v2, chat.cbor, chat.msgpack"),
+            Some(Encoding::Cbor)
+        );
+        assert_eq!(Encoding::negotiate("chat.v2"), None);
+        assert_eq!(Encoding::MessagePack.protocol(), "chat.msgpack");
+    }
+
+    #[test]
+    fn text_data_is_left_as_sent() {
+        let topic = r#"{"messageType":"topic","data":"{\"b\": 1, \"a\": 2}"}"#;
+        let notice = r#"{"messageType":"notice","data":"[not json"}"#;
+        for encoding in BINARY {
+            for frame in [topic, notice] {
+                assert_eq!(
+                    round_trip(encoding, frame),
+                    serde_json::from_str::<Value>(frame).unwrap()
+                );
+                assert!(raw(encoding, frame)["data"].is_string());
+            }
+        }
+    }
+
+    #[test]
+    fn json_data_travels_nested() {
+        let data = r#"{"id":7,"from":"ann","message":"{\"b\": 1}","reactions":{"👍":["bob"]}}"#;
+        let frame = serde_json::json!({ "messageType": "message", "data": data }).to_string();
+        for encoding in BINARY {
+            assert_eq!(raw(encoding, &frame)["data"]["from"], "ann");
+
+            let back = round_trip(encoding, &frame);
+            assert_eq!(back["messageType"], "message");
+            let back: Value = serde_json::from_str(back["data"].as_str().unwrap()).unwrap();
+            assert_eq!(back, serde_json::from_str::<Value>(data).unwrap());
+        }
+    }
+
+    #[test]
+    fn frames_without_data_survive() {
+        let users = r#"{"messageType":"users","dataArray":["ann","bob"]}"#;
+        for encoding in BINARY {
+            assert_eq!(
+                round_trip(encoding, users),
+                serde_json::from_str::<Value>(users).unwrap()
+            );
+        }
+        assert_eq!(Encoding::Cbor.decode(b"\xff\x00"), None);
+        assert_eq!(Encoding::MessagePack.encode("not json"), None);
+    }
+}
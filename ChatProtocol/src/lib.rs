@@ -0,0 +1,191 @@
+//! Frames exchanged between chat clients and servers. Every frame is a
+//! `WebSocketMessage`; structured payloads travel as JSON text in `data`.
+
+use serde::{Deserialize, Serialize};
+
+mod encoding;
+
+pub use encoding::Encoding;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketMessage {
+    pub message_type: MessageType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_array: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageType {
+    Register,
+    Users,
+    Message,
+    Notice,
+    Topic,
+    Commands,
+    Typing,
+    Delivered,
+    Read,
+    Receipt,
+    ReadMarkers,
+    Status,
+    Presence,
+    Edit,
+    Delete,
+    Moderators,
+    React,
+    Unreact,
+    Reactions,
+    Thread,
+    ThreadUpdate,
+    Upload,
+    Uploaded,
+    UploadFailed,
+    Search,
+    SearchResults,
+    Context,
+    History,
+}
+
+impl MessageType {
+    /// Whether frames of this type carry JSON in `data`, one way or both.
+    /// Other frames carry plain text such as a name, an ID or a topic.
+    pub fn has_json_data(self) -> bool {
+        use MessageType::*;
+        matches!(
+            self,
+            Message
+                | Commands
+                | Receipt
+                | ReadMarkers
+                | Status
+                | Presence
+                | Edit
+                | React
+                | Unreact
+                | Reactions
+                | Thread
+                | ThreadUpdate
+                | Upload
+                | Uploaded
+                | Search
+                | SearchResults
+                | Context
+                | History
+        )
+    }
+}
+
+/// How a message's content is meant to be shown.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+    #[default]
+    Text,
+    /// The text is the URL of an image.
+    Image,
+    /// The message carries an uploaded file.
+    Attachment,
+    /// Written by the server; never accepted from clients.
+    System,
+}
+
+/// The message a reply quotes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplyData {
+    pub id: u64,
+    pub from: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deleted: bool,
+}
+
+/// A chat message as clients receive it. Fields only the Rust server sends
+/// are optional.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatMessage {
+    #[serde(default)]
+    pub id: u64,
+    pub from: String,
+    pub message: String,
+    #[serde(default)]
+    pub kind: ContentKind,
+    pub time: Option<i64>,
+    pub reply_to: Option<ReplyData>,
+    pub edited_at: Option<i64>,
+    #[serde(default)]
+    pub deleted: bool,
+    pub thread_id: Option<u64>,
+    #[serde(default)]
+    pub reply_count: usize,
+}
+
+/// Payload of a `register` frame. Without a secret it travels as the bare
+/// name; moderators add the server's moderator secret and send it as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterData {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl RegisterData {
+    /// Reads the data of a `register` frame, a bare name or JSON.
+    pub fn parse(data: &str) -> Self {
+        serde_json::from_str(data).unwrap_or_else(|_| Self {
+            name: data.to_string(),
+            secret: None,
+        })
+    }
+
+    /// The data to send in a `register` frame.
+    pub fn to_data(&self) -> String {
+        match self.secret {
+            Some(_) => serde_json::to_string(self).unwrap(),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Payload of a `message` frame sent by a client.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct MessageData {
+    pub text: String,
+    /// The quoted message as `ReplyData` JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<u64>,
+    /// ID of a file uploaded beforehand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<String>,
+    /// Kind the sender wants, derived from the content when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ContentKind>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_data_is_a_bare_name_without_a_secret() {
+        let plain = RegisterData::parse("ann");
+        assert_eq!(plain.name, "ann");
+        assert_eq!(plain.to_data(), "ann");
+
+        let moderator = RegisterData {
+            name: "ann".to_string(),
+            secret: Some("s3cret".to_string()),
+        };
+        assert_eq!(RegisterData::parse(&moderator.to_data()), moderator);
+        assert_eq!(RegisterData::parse("{not json").name, "{not json");
+    }
+}
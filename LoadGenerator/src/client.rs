@@ -0,0 +1,171 @@
+//! One simulated user: connects, registers, then sends chat messages at a
+//! fixed rate while timing every load message it receives.
+//!
+//! Load messages carry the time they were sent, in microseconds since the
+//! test started, so any client can tell how long one took to arrive.
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::sync::watch;
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::stats::{self, Stats};
+
+/// How long a client may take to connect and see itself in the user list.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Prefix of the messages sent by the load generator.
+const MARKER: &str = "load";
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Connecting,
+    Sending,
+    Draining,
+    Done,
+}
+
+pub struct Client {
+    pub url: String,
+    pub name: String,
+    /// Time between two messages, `None` for clients that only listen.
+    pub send_every: Option<Duration>,
+    /// Delay before the first message, so clients do not send in lockstep.
+    pub offset: Duration,
+    pub size: usize,
+    /// When the test started, the origin of the times in load messages.
+    pub start: Instant,
+}
+
+impl Client {
+    pub async fn run(self, stats: Arc<Stats>, mut phase: watch::Receiver<Phase>) {
+        let began = Instant::now();
+        let Ok(Some(stream)) = time::timeout(CONNECT_TIMEOUT, self.connect()).await else {
+            stats.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        stats.record_connect(began.elapsed());
+        let (mut outgoing, mut incoming) = stream.split();
+
+        let mut latency = stats::histogram();
+        let mut received = 0;
+        let mut sending = false;
+        let mut ticker = time::interval(Duration::from_secs(3600));
+        let mut seq = 0u64;
+
+        let survived = loop {
+            tokio::select! {
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Some(sent_at) = sent_at(&text) {
+                            let now = self.start.elapsed().as_micros() as u64;
+                            latency.saturating_record(now.saturating_sub(sent_at));
+                            received += 1;
+                            stats.received.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break false,
+                },
+                _ = ticker.tick(), if sending => {
+                    seq += 1;
+                    let text = self.load_message(seq);
+                    if outgoing.send(Message::Text(text)).await.is_err() {
+                        break false;
+                    }
+                    stats.sent.fetch_add(1, Ordering::Relaxed);
+                },
+                changed = phase.changed() => {
+                    if changed.is_err() {
+                        break true;
+                    }
+                    match *phase.borrow_and_update() {
+                        Phase::Sending => {
+                            if let Some(every) = self.send_every {
+                                ticker = time::interval_at(Instant::now() + self.offset, every);
+                                ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                                sending = true;
+                            }
+                        }
+                        Phase::Draining => sending = false,
+                        Phase::Done => break true,
+                        Phase::Connecting => {}
+                    }
+                },
+            }
+        };
+
+        if survived {
+            let _ = outgoing.close().await;
+        }
+        stats.finish_client(&latency, received, survived);
+    }
+
+    // Connects and registers, returning once the server lists the new user
+    async fn connect(&self) -> Option<Stream> {
+        let (mut stream, _) = connect_async(&self.url).await.ok()?;
+        let register = json!({ "messageType": "register", "data": self.name });
+        stream.send(Message::Text(register.to_string())).await.ok()?;
+
+        while let Some(Ok(message)) = stream.next().await {
+            let Message::Text(text) = message else {
+                continue;
+            };
+            let Ok(frame) = serde_json::from_str::<Value>(&text) else {
+                continue;
+            };
+            let listed = frame["messageType"] == "users"
+                && frame["dataArray"]
+                    .as_array()
+                    .is_some_and(|users| users.iter().any(|user| *user == *self.name));
+            if listed {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    // A message frame padded to `size` characters of text
+    fn load_message(&self, seq: u64) -> String {
+        let mut text = format!(
+            "{} {} {} {}",
+            MARKER,
+            self.start.elapsed().as_micros(),
+            self.name,
+            seq
+        );
+        if text.len() < self.size {
+            text.push(' ');
+            text.extend(std::iter::repeat_n('x', self.size - text.len()));
+        }
+        let data = json!({ "text": text });
+        json!({ "messageType": "message", "data": data.to_string() }).to_string()
+    }
+}
+
+// When a load message was sent, `None` for any other frame
+fn sent_at(frame: &str) -> Option<u64> {
+    // Skip parsing frames that cannot be load messages
+    if !frame.contains(MARKER) {
+        return None;
+    }
+    let frame: Value = serde_json::from_str(frame).ok()?;
+    if frame["messageType"] != "message" {
+        return None;
+    }
+    let message: Value = serde_json::from_str(frame["data"].as_str()?).ok()?;
+    let mut words = message["message"].as_str()?.split(' ');
+    if words.next()? != MARKER {
+        return None;
+    }
+    words.next()?.parse().ok()
+}
@@ -0,0 +1,124 @@
+//! Load generator for the chat server: opens many simulated clients that
+//! speak the chat protocol, has them send messages at a configured rate and
+//! reports connection success, end-to-end latency and dropped messages.
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::{self, Instant};
+
+mod client;
+mod stats;
+
+use client::{Client, Phase};
+use stats::Stats;
+
+#[derive(Parser)]
+#[command(about = "Measures a chat server under load")]
+struct Args {
+    /// Server to connect to
+    #[arg(long, default_value = "ws://127.0.0.1:8080")]
+    url: String,
+
+    /// Number of simulated clients
+    #[arg(short, long, default_value_t = 100)]
+    clients: u64,
+
+    /// Number of clients sending messages, the others only listen
+    /// [default: all of them]
+    #[arg(short, long)]
+    senders: Option<u64>,
+
+    /// New connections opened per second
+    #[arg(long, default_value_t = 200.0)]
+    connect_rate: f64,
+
+    /// Messages per second sent by each sender
+    #[arg(short, long, default_value_t = 1.0)]
+    rate: f64,
+
+    /// Seconds spent sending messages
+    #[arg(short, long, default_value_t = 30)]
+    duration: u64,
+
+    /// Seconds to wait for messages still in flight after sending stops
+    #[arg(long, default_value_t = 5)]
+    drain: u64,
+
+    /// Characters of text in each message
+    #[arg(long, default_value_t = 64)]
+    size: usize,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    assert!(args.connect_rate > 0.0, "--connect-rate must be positive");
+    assert!(args.rate > 0.0, "--rate must be positive");
+
+    let start = Instant::now();
+    let stats = Arc::new(Stats::new());
+    let (phase, phase_rx) = watch::channel(Phase::Connecting);
+    let senders = args.senders.unwrap_or(args.clients).min(args.clients);
+    let send_every = Duration::from_secs_f64(1.0 / args.rate);
+
+    // Open connections at the configured rate, spreading the senders'
+    // first messages over one sending interval
+    println!("Connecting {} clients to {}", args.clients, args.url);
+    let mut clients = JoinSet::new();
+    let mut pace = time::interval(Duration::from_secs_f64(1.0 / args.connect_rate));
+    let mut progress = time::interval(Duration::from_secs(1));
+    for index in 0..args.clients {
+        let client = Client {
+            url: args.url.clone(),
+            name: format!("user{}", index),
+            send_every: (index < senders).then_some(send_every),
+            offset: send_every.mul_f64(index as f64 / senders.max(1) as f64),
+            size: args.size,
+            start,
+        };
+        clients.spawn(client.run(stats.clone(), phase_rx.clone()));
+        tokio::select! {
+            _ = pace.tick() => {}
+            _ = progress.tick() => println!("{}", stats.progress(start.elapsed())),
+        }
+    }
+
+    // Wait for every client to be connected or to have given up
+    loop {
+        let done = stats.connected.load(Ordering::Relaxed) + stats.failed.load(Ordering::Relaxed);
+        if done >= args.clients {
+            break;
+        }
+        progress.tick().await;
+        println!("{}", stats.progress(start.elapsed()));
+    }
+
+    println!("Sending for {} seconds", args.duration);
+    let _ = phase.send(Phase::Sending);
+    let sending_since = Instant::now();
+    let sending_until = sending_since + Duration::from_secs(args.duration);
+    while Instant::now() < sending_until {
+        tokio::select! {
+            _ = progress.tick() => println!("{}", stats.progress(start.elapsed())),
+            _ = time::sleep_until(sending_until) => {}
+        }
+    }
+    let sending_for = sending_since.elapsed();
+
+    println!("Waiting {} seconds for messages in flight", args.drain);
+    let _ = phase.send(Phase::Draining);
+    time::sleep(Duration::from_secs(args.drain)).await;
+    let _ = phase.send(Phase::Done);
+    while clients.join_next().await.is_some() {}
+
+    println!();
+    println!("{}", stats.report(args.clients, sending_for));
+    if stats.connected.load(Ordering::Relaxed) == 0 {
+        std::process::exit(1);
+    }
+}
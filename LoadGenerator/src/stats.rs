@@ -0,0 +1,134 @@
+//! Counters shared by the simulated clients, and the report printed at the end.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+pub struct Stats {
+    pub connected: AtomicU64,
+    pub failed: AtomicU64,
+    // Clients whose connection broke after they had registered
+    pub lost: AtomicU64,
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+    // Clients still connected when the run ended and what they received,
+    // the only ones expected to have seen every message
+    survivors: AtomicU64,
+    survivor_received: AtomicU64,
+    // In microseconds
+    latency: Mutex<Histogram<u64>>,
+    connect_time: Mutex<Histogram<u64>>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            connected: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            lost: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            survivors: AtomicU64::new(0),
+            survivor_received: AtomicU64::new(0),
+            latency: Mutex::new(histogram()),
+            connect_time: Mutex::new(histogram()),
+        }
+    }
+
+    pub fn record_connect(&self, took: Duration) {
+        self.connect_time
+            .lock()
+            .unwrap()
+            .saturating_record(took.as_micros() as u64);
+        self.connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds up what one client saw once it is done.
+    pub fn finish_client(&self, latency: &Histogram<u64>, received: u64, survived: bool) {
+        self.latency.lock().unwrap().add(latency).unwrap();
+        if survived {
+            self.survivors.fetch_add(1, Ordering::Relaxed);
+            self.survivor_received.fetch_add(received, Ordering::Relaxed);
+        } else {
+            self.lost.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// One line for the progress printed while the test runs.
+    pub fn progress(&self, elapsed: Duration) -> String {
+        format!(
+            "[{:>4}s] {} connected, {} failed, {} lost, {} sent, {} received",
+            elapsed.as_secs(),
+            self.connected.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.lost.load(Ordering::Relaxed),
+            self.sent.load(Ordering::Relaxed),
+            self.received.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn report(&self, clients: u64, sending_for: Duration) -> String {
+        let connected = self.connected.load(Ordering::Relaxed);
+        let sent = self.sent.load(Ordering::Relaxed);
+        let survivors = self.survivors.load(Ordering::Relaxed);
+        let expected = sent * survivors;
+        let received = self.survivor_received.load(Ordering::Relaxed);
+        let dropped = expected.saturating_sub(received);
+
+        let mut lines = vec![
+            format!(
+                "Connections: {}/{} succeeded ({:.2}%), {} failed, {} lost during the run",
+                connected,
+                clients,
+                percent(connected, clients),
+                self.failed.load(Ordering::Relaxed),
+                self.lost.load(Ordering::Relaxed),
+            ),
+            format!("Connect time: {}", percentiles(&self.connect_time.lock().unwrap())),
+            format!(
+                "Messages: {} sent ({:.1}/s), {} of {} deliveries received, {} dropped ({:.3}%)",
+                sent,
+                sent as f64 / sending_for.as_secs_f64(),
+                received,
+                expected,
+                dropped,
+                percent(dropped, expected),
+            ),
+        ];
+        let latency = self.latency.lock().unwrap();
+        if latency.is_empty() {
+            lines.push("Latency: no messages arrived".to_string());
+        } else {
+            lines.push(format!("Latency: {}", percentiles(&latency)));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Histogram of microseconds up to an hour, with three significant digits.
+/// Longer times count as an hour.
+pub fn histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, 3_600_000_000, 3).unwrap()
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+fn percentiles(histogram: &Histogram<u64>) -> String {
+    let ms = |micros: u64| format!("{:.2} ms", micros as f64 / 1000.0);
+    format!(
+        "p50 {}, p90 {}, p99 {}, p99.9 {}, max {}",
+        ms(histogram.value_at_quantile(0.5)),
+        ms(histogram.value_at_quantile(0.9)),
+        ms(histogram.value_at_quantile(0.99)),
+        ms(histogram.value_at_quantile(0.999)),
+        ms(histogram.max()),
+    )
+}
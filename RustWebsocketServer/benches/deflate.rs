@@ -0,0 +1,166 @@
+//! Bandwidth saved by permessage-deflate on typical server frames, for each
+//! window size and with or without context takeover.
+//!
+//! Run with `cargo bench --bench deflate`.
+
+#![allow(dead_code)]
+
+#[path = "../src/deflate.rs"]
+mod deflate;
+
+use std::time::Instant;
+
+use deflate::{Codec, Negotiated};
+use serde_json::json;
+
+const NAMES: &[&str] = &[
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy",
+];
+
+const TEXTS: &[&str] = &[
+    "Morning! Is the deploy still scheduled for today?",
+    "Yes, right after the standup. @bob can you keep an eye on the dashboards?",
+    "Sure thing, I'll post here if the error rate moves.",
+    "The new search is great, found that thread from last month in seconds",
+    "```rust\nfn main() {\n    println!(\"hello\");\n}\n```",
+    "lunch?",
+    "Reviewed the PR, left a couple of comments about the error handling",
+    "https://example.com/screenshots/latency.png",
+];
+
+// A `users` frame listing `count` users
+fn user_list(count: usize) -> String {
+    let users: Vec<String> = (0..count)
+        .map(|i| format!("{}{}", NAMES[i % NAMES.len()], i))
+        .collect();
+    json!({ "messageType": "users", "dataArray": users }).to_string()
+}
+
+// A `history` frame with a page of `count` messages starting at `first`
+fn history_page(first: u64, count: u64) -> String {
+    let messages: Vec<_> = (first..first + count)
+        .map(|id| {
+            let i = id as usize;
+            json!({
+                "id": id,
+                "from": NAMES[i % NAMES.len()],
+                "message": TEXTS[(i * 7) % TEXTS.len()],
+                "kind": "text",
+                "time": 1_700_000_000_000u64 + id * 45_000,
+            })
+        })
+        .collect();
+    let page = json!({ "messages": messages, "has_more": first > 0 }).to_string();
+    json!({ "messageType": "history", "data": page }).to_string()
+}
+
+// A single `message` frame, far below any sensible threshold
+fn chat_message(id: u64) -> String {
+    let i = id as usize;
+    let message = json!({
+        "id": id,
+        "from": NAMES[i % NAMES.len()],
+        "message": TEXTS[i % TEXTS.len()],
+        "kind": "text",
+        "time": 1_700_000_000_000u64 + id * 45_000,
+    })
+    .to_string();
+    json!({ "messageType": "message", "data": message }).to_string()
+}
+
+// Compresses `frames` in order as one connection would, returning the bytes
+// sent and the time taken in microseconds. Every compressed frame is then
+// inflated the way the client would, to check it comes back unchanged.
+fn run(frames: &[String], window_bits: u8, no_context_takeover: bool, min_size: usize) -> (usize, u128) {
+    let negotiated = Negotiated {
+        server_no_context_takeover: no_context_takeover,
+        client_no_context_takeover: false,
+        server_max_window_bits: window_bits,
+        min_size,
+    };
+    let mut codec = Codec::new(negotiated);
+    let start = Instant::now();
+    let sent: Vec<Option<Vec<u8>>> = frames
+        .iter()
+        .map(|frame| {
+            (frame.len() >= min_size).then(|| codec.compress(frame.as_bytes()).unwrap())
+        })
+        .collect();
+    let micros = start.elapsed().as_micros();
+
+    // The client's decompressor mirrors the server's compressor
+    let mut client = Codec::new(Negotiated {
+        client_no_context_takeover: no_context_takeover,
+        ..negotiated
+    });
+    for (frame, deflated) in frames.iter().zip(&sent) {
+        if let Some(deflated) = deflated {
+            let inflated = client.decompress(deflated).unwrap();
+            assert_eq!(inflated, frame.as_bytes(), "frame did not survive compression");
+        }
+    }
+
+    let bytes = frames
+        .iter()
+        .zip(&sent)
+        .map(|(frame, deflated)| deflated.as_ref().map_or(frame.len(), Vec::len))
+        .sum();
+    (bytes, micros)
+}
+
+fn report(name: &str, frames: &[String]) {
+    let raw: usize = frames.iter().map(String::len).sum();
+    println!("\n{} ({} frames, {} bytes uncompressed)", name, frames.len(), raw);
+    println!("{:>6} {:>10} {:>10} {:>8} {:>10}", "window", "takeover", "bytes", "saved", "time");
+    for window_bits in [9, 12, 15] {
+        for no_context_takeover in [false, true] {
+            let (sent, micros) = run(frames, window_bits, no_context_takeover, 0);
+            println!(
+                "{:>6} {:>10} {:>10} {:>7.1}% {:>8}us",
+                window_bits,
+                if no_context_takeover { "no" } else { "yes" },
+                sent,
+                100.0 * (1.0 - sent as f64 / raw as f64),
+                micros
+            );
+        }
+    }
+}
+
+fn report_thresholds(frames: &[String]) {
+    let raw: usize = frames.iter().map(String::len).sum();
+    println!("\nMixed traffic by threshold ({} frames, {} bytes uncompressed)", frames.len(), raw);
+    println!("{:>8} {:>10} {:>8} {:>10}", "min size", "bytes", "saved", "time");
+    for min_size in [0, 64, 128, 256, 1024] {
+        let (sent, micros) = run(frames, 15, false, min_size);
+        println!(
+            "{:>8} {:>10} {:>7.1}% {:>8}us",
+            min_size,
+            sent,
+            100.0 * (1.0 - sent as f64 / raw as f64),
+            micros
+        );
+    }
+}
+
+fn main() {
+    // Everyone joining and leaving resends the whole list
+    let user_lists: Vec<String> = (1..=200).map(user_list).collect();
+    report("User lists of 1 to 200 users", &user_lists);
+
+    // Scrolling back through 1000 messages
+    let pages: Vec<String> = (0..20).rev().map(|page| history_page(page * 50, 50)).collect();
+    report("History pages of 50 messages", &pages);
+
+    // Chat as it happens: mostly single messages, now and then a user list
+    let mixed: Vec<String> = (0..1000)
+        .map(|id| {
+            if id % 50 == 0 {
+                user_list(40)
+            } else {
+                chat_message(id)
+            }
+        })
+        .collect();
+    report_thresholds(&mixed);
+}
@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::history::History;
+use crate::{send_frame, MessageType, ServerState, Tx, WebSocketMessage};
+
+/// Largest file accepted, in bytes.
+pub const MAX_ATTACHMENT_SIZE: u64 = 10 * 1024 * 1024;
+
+/// MIME types that may be uploaded. Images are checked against their content.
+const ALLOWED_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+];
+
+/// Longest file name kept, in characters.
+const MAX_NAME_CHARS: usize = 200;
+
+/// A stored file as carried by messages. `id` is the hex SHA-256 of the content,
+/// which is also its name on disk.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    pub url: String,
+}
+
+// Announces an upload; the content follows as binary frames
+#[derive(Debug, Deserialize)]
+struct UploadRequest {
+    name: String,
+    mime: String,
+    size: u64,
+}
+
+/// An upload in progress on one connection.
+pub struct Upload {
+    name: String,
+    mime: String,
+    size: u64,
+    content: Vec<u8>,
+}
+
+/// Directory uploads are stored in, from CHAT_UPLOAD_DIR.
+pub fn upload_dir() -> PathBuf {
+    std::env::var("CHAT_UPLOAD_DIR")
+        .unwrap_or_else(|_| "uploads".to_string())
+        .into()
+}
+
+/// Whether `id` looks like a content hash, so it is safe to use as a file name.
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+}
+
+/// Starts an upload from an `upload` frame, replacing any unfinished one.
+pub fn start_upload(tx: &Tx, data: &str) -> Option<Upload> {
+    let Ok(request) = serde_json::from_str::<UploadRequest>(data) else {
+        return None;
+    };
+
+    let name: String = request
+        .name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '/' | '\\' | '"'))
+        .take(MAX_NAME_CHARS)
+        .collect();
+    let name = name.trim();
+
+    let error = if name.is_empty() {
+        Some("The file needs a name".to_string())
+    } else if request.size == 0 {
+        Some("The file is empty".to_string())
+    } else if request.size > MAX_ATTACHMENT_SIZE {
+        Some(format!(
+            "Files can be at most {} MB",
+            MAX_ATTACHMENT_SIZE / 1024 / 1024
+        ))
+    } else if !ALLOWED_TYPES.contains(&request.mime.as_str()) {
+        Some(format!("Files of type {} are not allowed", request.mime))
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        upload_failed(tx, &error);
+        return None;
+    }
+
+    Some(Upload {
+        name: name.to_string(),
+        mime: request.mime,
+        size: request.size,
+        content: Vec::with_capacity(request.size as usize),
+    })
+}
+
+/// Appends a binary frame to the upload in progress, storing the file once all
+/// of it has arrived and returning its ID. The file counts as unsent until
+/// `forget_unsent` is called for it. Chunks without an upload in progress are
+/// dropped.
+pub async fn receive_chunk(
+    state: &ServerState,
+    tx: &Tx,
+    upload: &mut Option<Upload>,
+    chunk: &[u8],
+) -> Option<String> {
+    let current = upload.as_mut()?;
+    if current.content.len() as u64 + chunk.len() as u64 > current.size {
+        *upload = None;
+        upload_failed(tx, "The file is larger than announced");
+        return None;
+    }
+
+    current.content.extend_from_slice(chunk);
+    if (current.content.len() as u64) < current.size {
+        return None;
+    }
+
+    let finished = upload.take()?;
+    match store(state, finished).await {
+        Ok(attachment) => {
+            send_frame(
+                tx,
+                &WebSocketMessage {
+                    message_type: MessageType::Uploaded,
+                    data: Some(serde_json::to_string(&attachment).unwrap()),
+                    data_array: None,
+                },
+            );
+            Some(attachment.id)
+        }
+        Err(error) => {
+            upload_failed(tx, &error);
+            None
+        }
+    }
+}
+
+// Writes the file under its content hash and remembers its metadata
+async fn store(state: &ServerState, upload: Upload) -> Result<Attachment, String> {
+    let (width, height) = if upload.mime.starts_with("image/") {
+        let size = imagesize::blob_size(&upload.content)
+            .map_err(|_| "The file is not a valid image".to_string())?;
+        (Some(size.width as u32), Some(size.height as u32))
+    } else {
+        (None, None)
+    };
+
+    let id = format!("{:x}", Sha256::digest(&upload.content));
+    // Counted before the file is written so a release cannot remove it in between
+    *state.unsent.lock().unwrap().entry(id.clone()).or_default() += 1;
+    let dir = upload_dir();
+    let path = dir.join(&id);
+
+    // Identical content is only stored once
+    if tokio::fs::metadata(&path).await.is_err() {
+        let write = async {
+            tokio::fs::create_dir_all(&dir).await?;
+            tokio::fs::write(&path, &upload.content).await
+        };
+        if let Err(e) = write.await {
+            log::error!("Error storing upload {}: {}", id, e);
+            forget_unsent(state, &id);
+            return Err("The file could not be stored".to_string());
+        }
+    }
+
+    let attachment = Attachment {
+        url: format!("/files/{}", id),
+        id: id.clone(),
+        name: upload.name,
+        mime: upload.mime,
+        size: upload.size,
+        width,
+        height,
+    };
+    state
+        .attachments
+        .lock()
+        .unwrap()
+        .insert(id, attachment.clone());
+    Ok(attachment)
+}
+
+/// Stops counting one upload of file `id` as unsent, once the message carrying
+/// it is in the history or its uploader has left.
+pub fn forget_unsent(state: &ServerState, id: &str) {
+    let mut unsent = state.unsent.lock().unwrap();
+    if let Some(count) = unsent.get_mut(id) {
+        *count -= 1;
+        if *count == 0 {
+            unsent.remove(id);
+        }
+    }
+}
+
+/// Removes file `id` and its metadata once no message in `history` carries it
+/// any more, so deleting a message takes its file down too. Files someone has
+/// uploaded but not sent yet are kept.
+pub fn release(state: &ServerState, history: &History, id: &str) {
+    let in_use = history
+        .iter()
+        .any(|m| m.attachment.as_ref().is_some_and(|a| a.id == id))
+        || state.unsent.lock().unwrap().contains_key(id);
+    if in_use || !is_valid_id(id) {
+        return;
+    }
+
+    state.attachments.lock().unwrap().remove(id);
+    match std::fs::remove_file(upload_dir().join(id)) {
+        Ok(()) => log::info!("Removed upload {}", id),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::error!("Error removing upload {}: {}", id, e),
+    }
+}
+
+fn upload_failed(tx: &Tx, reason: &str) {
+    send_frame(
+        tx,
+        &WebSocketMessage {
+            message_type: MessageType::UploadFailed,
+            data: Some(reason.to_string()),
+            data_array: None,
+        },
+    );
+}
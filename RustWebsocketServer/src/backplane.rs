@@ -0,0 +1,358 @@
+//! Relays what one server instance broadcasts to the others, so users
+//! connected to different nodes behind a load balancer share one chat.
+//! Broadcast frames, frames for a single user, the user list and presence go
+//! through the backplane. Every node keeps a copy of the history, topic and
+//! read markers, updated from the frames it relays; typing state stays per
+//! node.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::{history, receipts};
+use crate::presence::{self, Presence, Status};
+use crate::redis::RedisBackplane;
+use crate::{
+    broadcast_local, send_user_list, MessageType, ServerState, SharedState, UserId,
+    WebSocketMessage,
+};
+
+/// Most nodes one chat can span. Message IDs are interleaved between nodes
+/// so they never clash.
+pub const MAX_NODES: u64 = 64;
+
+/// Nodes not heard from for this long are dropped along with their users.
+/// Every node announces itself on each connection check, every 5 seconds.
+const NODE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Channel the Redis backplane publishes on when `CHAT_BACKPLANE` names none.
+const DEFAULT_CHANNEL: &str = "chat";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    /// A frame for every user.
+    Broadcast { frame: String },
+    /// A frame for one user, delivered by the node they are connected to.
+    Direct { to: UserId, frame: String },
+    /// Users connected to the sending node and their presence, sent when
+    /// either changes and as a heartbeat.
+    State {
+        users: Vec<UserId>,
+        presence: HashMap<UserId, Presence>,
+    },
+}
+
+/// An event and the node it comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub node: u64,
+    pub event: Event,
+}
+
+/// Carries envelopes between nodes. Publishing never blocks; envelopes are
+/// delivered to every subscriber, the publishing node's included.
+pub trait Backplane: Send + Sync {
+    fn publish(&self, envelope: Envelope);
+
+    /// Envelopes published by any node from now on.
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<Envelope>;
+}
+
+/// Backplane for nodes running in the same process, as in tests.
+#[derive(Default)]
+pub struct InProcess {
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<Envelope>>>,
+}
+
+impl Backplane for InProcess {
+    fn publish(&self, envelope: Envelope) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(envelope.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<Envelope> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+/// What this node last heard from another one.
+pub struct RemoteNode {
+    users: Vec<UserId>,
+    presence: HashMap<UserId, Presence>,
+    last_heard: Instant,
+}
+
+/// The node index from `CHAT_NODE` and the backplane named by
+/// `CHAT_BACKPLANE`, such as `redis://127.0.0.1:6379/chat`. Without a
+/// backplane the server runs on its own.
+pub fn from_env() -> (u64, Option<Arc<dyn Backplane>>) {
+    let node = std::env::var("CHAT_NODE").ok().map(|node| {
+        node.trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|node| *node < MAX_NODES)
+            .expect("CHAT_NODE must be a number below 64")
+    });
+    let Ok(url) = std::env::var("CHAT_BACKPLANE") else {
+        return (node.unwrap_or(0), None);
+    };
+
+    let address = url
+        .strip_prefix("redis://")
+        .expect("CHAT_BACKPLANE must be a redis:// URL");
+    let (address, channel) = address
+        .split_once('/')
+        .unwrap_or((address, DEFAULT_CHANNEL));
+    if node.is_none() {
+        warn!("CHAT_NODE is not set; every node sharing a backplane needs its own");
+    }
+    info!(
+        "Relaying through Redis at {} on channel {}",
+        address, channel
+    );
+    let backplane = RedisBackplane::connect(address.to_string(), channel.to_string());
+    (node.unwrap_or(0), Some(Arc::new(backplane)))
+}
+
+/// Sends `event` to the other nodes, if there are any.
+pub fn publish(state: &ServerState, event: Event) {
+    if let Some(backplane) = &state.backplane {
+        backplane.publish(Envelope {
+            node: state.node,
+            event,
+        });
+    }
+}
+
+/// Tells the other nodes who is connected here and their presence.
+pub fn announce(state: &ServerState) {
+    if state.backplane.is_none() {
+        return;
+    }
+    let mut users: Vec<UserId> = state.peers.lock().unwrap().keys().cloned().collect();
+    users.sort();
+    let presence = {
+        let presence = state.presence.lock().unwrap();
+        users
+            .iter()
+            .filter_map(|user| Some((user.clone(), presence.get(user)?.clone())))
+            .collect()
+    };
+    publish(state, Event::State { users, presence });
+}
+
+/// Users connected to other nodes.
+pub fn remote_users(state: &ServerState) -> Vec<UserId> {
+    state
+        .remote
+        .lock()
+        .unwrap()
+        .values()
+        .flat_map(|node| node.users.iter().cloned())
+        .collect()
+}
+
+/// Presence of the users connected to other nodes. They only show up in a
+/// presence frame where this node does not know better.
+pub fn merge_presence(state: &ServerState, presence: &mut HashMap<UserId, Presence>) {
+    for node in state.remote.lock().unwrap().values() {
+        for (user, remote) in &node.presence {
+            let known_online = presence
+                .get(user)
+                .is_some_and(|local| local.status() != Status::Offline);
+            if !known_online {
+                presence.insert(user.clone(), remote.clone());
+            }
+        }
+    }
+}
+
+/// Applies envelopes from the other nodes until the backplane goes away.
+pub async fn run(state: SharedState, mut envelopes: mpsc::UnboundedReceiver<Envelope>) {
+    while let Some(envelope) = envelopes.recv().await {
+        if envelope.node != state.node {
+            apply(&state, envelope);
+        }
+    }
+    warn!("Backplane subscription ended");
+}
+
+fn apply(state: &ServerState, envelope: Envelope) {
+    match envelope.event {
+        Event::Broadcast { frame } => {
+            history::replicate(state, &frame);
+            replicate_topic(state, &frame);
+            // Local users get the read markers merged with this node's own
+            let frame = receipts::replicate(state, &frame).unwrap_or(frame);
+            broadcast_local(&state.peers, &frame);
+        }
+        Event::Direct { to, frame } => {
+            if let Some((tx, _)) = state.peers.lock().unwrap().get(&to) {
+                let _ = tx.send(Message::Text(frame));
+            }
+        }
+        Event::State { users, presence } => {
+            let (users_changed, presence_changed) = {
+                let mut remote = state.remote.lock().unwrap();
+                let previous = remote.insert(
+                    envelope.node,
+                    RemoteNode {
+                        users: users.clone(),
+                        presence: presence.clone(),
+                        last_heard: Instant::now(),
+                    },
+                );
+                match previous {
+                    Some(previous) => (previous.users != users, previous.presence != presence),
+                    None => {
+                        info!("Node {} joined the chat", envelope.node);
+                        (true, true)
+                    }
+                }
+            };
+            if users_changed {
+                send_user_list(state);
+            }
+            if presence_changed {
+                presence::send_presence(state);
+            }
+        }
+    }
+}
+
+// Keeps the topic in step so /topic and new users see the latest one
+fn replicate_topic(state: &ServerState, frame: &str) {
+    if let Ok(WebSocketMessage {
+        message_type: MessageType::Topic,
+        data,
+        ..
+    }) = serde_json::from_str(frame)
+    {
+        *state.topic.lock().unwrap() = data;
+    }
+}
+
+/// Announces this node and forgets nodes that stopped announcing themselves.
+pub fn heartbeat(state: &ServerState) {
+    if state.backplane.is_none() {
+        return;
+    }
+    announce(state);
+
+    let expired = {
+        let mut remote = state.remote.lock().unwrap();
+        let before = remote.len();
+        remote.retain(|node, remote| {
+            let alive = remote.last_heard.elapsed() < NODE_TIMEOUT;
+            if !alive {
+                warn!("Node {} went silent, dropping its users", node);
+            }
+            alive
+        });
+        remote.len() != before
+    };
+    if expired {
+        send_user_list(state);
+        presence::send_presence(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{broadcast_message, broadcast_user_list, send_to_user};
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    // A node on `hub` with its backplane subscription running
+    fn node(hub: &Arc<InProcess>, index: u64) -> SharedState {
+        let backplane: Arc<dyn Backplane> = hub.clone();
+        let state = SharedState::new(ServerState::new(index, Some(backplane.clone())));
+        tokio::spawn(run(state.clone(), backplane.subscribe()));
+        state
+    }
+
+    // Connects `user` to `state` as if they had registered
+    fn connect(state: &ServerState, user: &str) -> UnboundedReceiver<Message> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        state
+            .peers
+            .lock()
+            .unwrap()
+            .insert(user.to_string(), (tx, true));
+        broadcast_user_list(state);
+        rx
+    }
+
+    // Waits for a text frame matching `wanted`
+    async fn expect(rx: &mut UnboundedReceiver<Message>, wanted: impl Fn(&str) -> bool) -> String {
+        let wait = async {
+            while let Some(message) = rx.recv().await {
+                if let Message::Text(text) = message {
+                    if wanted(&text) {
+                        return text;
+                    }
+                }
+            }
+            panic!("connection closed");
+        };
+        tokio::time::timeout(Duration::from_secs(2), wait)
+            .await
+            .expect("frame never arrived")
+    }
+
+    #[tokio::test]
+    async fn broadcasts_reach_other_nodes() {
+        let hub = Arc::new(InProcess::default());
+        let (a, b) = (node(&hub, 0), node(&hub, 1));
+        let mut ann = connect(&a, "ann");
+        let mut bob = connect(&b, "bob");
+
+        broadcast_message(&a, "hello from a");
+        expect(&mut bob, |text| text == "hello from a").await;
+        expect(&mut ann, |text| text == "hello from a").await;
+    }
+
+    #[tokio::test]
+    async fn user_lists_span_nodes() {
+        let hub = Arc::new(InProcess::default());
+        let (a, b) = (node(&hub, 0), node(&hub, 1));
+        let _ann = connect(&a, "ann");
+        let mut bob = connect(&b, "bob");
+
+        expect(&mut bob, |text| {
+            text.contains(r#""messageType":"users""#)
+                && text.contains("ann")
+                && text.contains("bob")
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn direct_frames_find_their_user() {
+        let hub = Arc::new(InProcess::default());
+        let (a, b) = (node(&hub, 0), node(&hub, 1));
+        let mut ann = connect(&a, "ann");
+        let mut bob = connect(&b, "bob");
+        expect(&mut bob, |text| text.contains("ann")).await;
+
+        let frame = WebSocketMessage {
+            message_type: MessageType::Notice,
+            data: Some("psst".to_string()),
+            data_array: None,
+        };
+        assert!(send_to_user(&b, "ann", &frame));
+        assert!(!send_to_user(&b, "nobody", &frame));
+        expect(&mut ann, |text| text.contains("psst")).await;
+    }
+}
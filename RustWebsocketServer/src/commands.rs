@@ -0,0 +1,377 @@
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::{
+    backplane, broadcast_message, broadcast_user_list, integrations, presence,
+    reserved_for_moderator, send_frame, send_to_user, typing, MessageType, ServerState, Tx, UserId,
+    WebSocketMessage,
+};
+
+/// Outcome of a command: `Ok` text is sent privately to the issuer as a notice,
+/// `Err` text is sent privately as an error notice.
+type CommandResult = Result<Option<String>, String>;
+
+/// Everything a command handler may touch while it runs.
+pub struct CommandContext<'a> {
+    pub state: &'a ServerState,
+    pub user_id: &'a mut UserId,
+    pub tx: &'a Tx,
+}
+
+/// A slash command. New commands only need an entry in `COMMANDS`.
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+    handler: fn(&mut CommandContext, &str) -> CommandResult,
+}
+
+// Command metadata as sent to clients for autocomplete
+#[derive(Debug, Serialize)]
+struct CommandInfo {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "nick",
+        usage: "/nick <name>",
+        description: "Change your display name",
+        handler: nick,
+    },
+    Command {
+        name: "me",
+        usage: "/me <action>",
+        description: "Describe an action in the third person",
+        handler: me,
+    },
+    Command {
+        name: "topic",
+        usage: "/topic [text]",
+        description: "Show or set the chat topic",
+        handler: topic,
+    },
+    Command {
+        name: "msg",
+        usage: "/msg <user> <text>",
+        description: "Send a private message to one user",
+        handler: msg,
+    },
+    Command {
+        name: "who",
+        usage: "/who",
+        description: "List connected users",
+        handler: who,
+    },
+    Command {
+        name: "webhooks",
+        usage: "/webhooks",
+        description: "Show recent webhook deliveries (moderators only)",
+        handler: webhooks,
+    },
+    Command {
+        name: "hook",
+        usage: "/hook [add <name> [per minute] | revoke <name>]",
+        description: "List, add or revoke incoming webhooks (moderators only)",
+        handler: hook,
+    },
+    Command {
+        name: "help",
+        usage: "/help",
+        description: "List available commands",
+        handler: help,
+    },
+];
+
+/// Runs `text` as a command if it starts with `/`. Returns `false` when the text
+/// should be treated as a regular chat message instead. A leading `//` escapes
+/// the slash and is handled by the caller.
+pub fn dispatch(ctx: &mut CommandContext, text: &str) -> bool {
+    let Some(line) = text.strip_prefix('/') else {
+        return false;
+    };
+    if line.starts_with('/') {
+        return false;
+    }
+
+    let (name, args) = match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, ""),
+    };
+
+    let result = match COMMANDS.iter().find(|c| c.name.eq_ignore_ascii_case(name)) {
+        Some(command) => (command.handler)(ctx, args),
+        None => Err(format!(
+            "Unknown command /{}. Type /help for a list of commands.",
+            name
+        )),
+    };
+
+    match result {
+        Ok(Some(reply)) | Err(reply) => send_frame(ctx.tx, &notice(&reply)),
+        Ok(None) => {}
+    }
+    true
+}
+
+/// Frame listing every command, sent to clients when they register.
+pub fn commands_frame() -> WebSocketMessage {
+    let commands: Vec<CommandInfo> = COMMANDS
+        .iter()
+        .map(|c| CommandInfo {
+            name: c.name,
+            usage: c.usage,
+            description: c.description,
+        })
+        .collect();
+
+    WebSocketMessage {
+        message_type: MessageType::Commands,
+        data: Some(serde_json::to_string(&commands).unwrap()),
+        data_array: None,
+    }
+}
+
+pub fn notice(text: &str) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type: MessageType::Notice,
+        data: Some(text.to_string()),
+        data_array: None,
+    }
+}
+
+fn broadcast_notice(ctx: &CommandContext, text: &str) {
+    let json = serde_json::to_string(&notice(text)).unwrap();
+    broadcast_message(ctx.state, &json);
+}
+
+fn nick(ctx: &mut CommandContext, args: &str) -> CommandResult {
+    if args.is_empty() || args.contains(char::is_whitespace) {
+        return Err("Usage: /nick <name> (no spaces)".into());
+    }
+    if args == ctx.user_id.as_str() {
+        return Ok(Some(format!("You are already known as {}", args)));
+    }
+    // Moderators prove who they are when registering, not when renaming
+    if reserved_for_moderator(ctx.state, args, None) {
+        return Err(format!("{} is a moderator's name", args));
+    }
+
+    {
+        let mut peers = ctx.state.peers.lock().unwrap();
+        let remote = backplane::remote_users(ctx.state);
+        if peers.contains_key(args) || remote.iter().any(|user| user == args) {
+            return Err(format!("The name {} is already taken", args));
+        }
+        if let Some(peer) = peers.remove(ctx.user_id.as_str()) {
+            peers.insert(args.to_string(), peer);
+        }
+    }
+
+    typing::set_typing(ctx.state, ctx.user_id, false);
+    let old = std::mem::replace(ctx.user_id, args.to_string());
+
+    // Tell the client its new name before anyone else hears about it
+    send_frame(
+        ctx.tx,
+        &WebSocketMessage {
+            message_type: MessageType::Register,
+            data: Some(ctx.user_id.clone()),
+            data_array: None,
+        },
+    );
+    broadcast_user_list(ctx.state);
+    presence::rename(ctx.state, &old, ctx.user_id);
+    broadcast_notice(ctx, &format!("{} is now known as {}", old, ctx.user_id));
+    Ok(None)
+}
+
+fn me(ctx: &mut CommandContext, args: &str) -> CommandResult {
+    if args.is_empty() {
+        return Err("Usage: /me <action>".into());
+    }
+    broadcast_notice(ctx, &format!("* {} {}", ctx.user_id, args));
+    Ok(None)
+}
+
+fn topic(ctx: &mut CommandContext, args: &str) -> CommandResult {
+    if args.is_empty() {
+        return Ok(Some(match ctx.state.topic.lock().unwrap().as_deref() {
+            Some(topic) => format!("The topic is: {}", topic),
+            None => "No topic is set".to_string(),
+        }));
+    }
+
+    *ctx.state.topic.lock().unwrap() = Some(args.to_string());
+
+    let json = serde_json::to_string(&WebSocketMessage {
+        message_type: MessageType::Topic,
+        data: Some(args.to_string()),
+        data_array: None,
+    })
+    .unwrap();
+    broadcast_message(ctx.state, &json);
+    broadcast_notice(ctx, &format!("{} set the topic to: {}", ctx.user_id, args));
+    Ok(None)
+}
+
+fn msg(ctx: &mut CommandContext, args: &str) -> CommandResult {
+    let Some((to, text)) = args.split_once(char::is_whitespace) else {
+        return Err("Usage: /msg <user> <text>".into());
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Usage: /msg <user> <text>".into());
+    }
+    if !send_to_user(ctx.state, to, &notice(&format!("[{} -> you] {}", ctx.user_id, text))) {
+        return Err(format!("{} is not online", to));
+    }
+    Ok(Some(format!("[you -> {}] {}", to, text)))
+}
+
+fn who(ctx: &mut CommandContext, _args: &str) -> CommandResult {
+    let mut users: Vec<String> = ctx.state.peers.lock().unwrap().keys().cloned().collect();
+    users.extend(backplane::remote_users(ctx.state));
+    users.sort();
+    Ok(Some(format!(
+        "{} user(s) online: {}",
+        users.len(),
+        users.join(", ")
+    )))
+}
+
+fn webhooks(ctx: &mut CommandContext, _args: &str) -> CommandResult {
+    if !ctx.state.moderators.contains(ctx.user_id.as_str()) {
+        return Err("Only moderators can see webhook deliveries".into());
+    }
+    if ctx.state.webhooks.is_empty() {
+        return Ok(Some("No webhooks are configured".into()));
+    }
+    let deliveries = ctx.state.webhooks.deliveries();
+    if deliveries.is_empty() {
+        return Ok(Some("No webhook deliveries yet".into()));
+    }
+    let lines: Vec<String> = deliveries
+        .iter()
+        .map(|d| {
+            let time = DateTime::from_timestamp_millis(d.time as i64)
+                .map(|time| time.format("%H:%M:%S").to_string())
+                .unwrap_or_default();
+            let outcome = match &d.error {
+                None => format!("delivered ({})", d.status.unwrap_or_default()),
+                Some(e) => format!("failed: {}", e),
+            };
+            format!(
+                "{} #{} {:?} to {}: {} after {} attempt(s)",
+                time, d.id, d.event, d.url, outcome, d.attempts
+            )
+        })
+        .collect();
+    Ok(Some(format!("Recent webhook deliveries:\n{}", lines.join("\n"))))
+}
+
+fn hook(ctx: &mut CommandContext, args: &str) -> CommandResult {
+    if !ctx.state.moderators.contains(ctx.user_id.as_str()) {
+        return Err("Only moderators can manage incoming webhooks".into());
+    }
+    let integrations = &ctx.state.integrations;
+    let args: Vec<&str> = args.split_whitespace().collect();
+    match args.as_slice() {
+        [] | ["list"] => {
+            let list = integrations.list();
+            if list.is_empty() {
+                return Ok(Some("No incoming webhooks".into()));
+            }
+            let lines: Vec<String> = list
+                .iter()
+                .map(|i| {
+                    // Enough of the token to tell them apart, not to use it
+                    let token: String = i.token.chars().take(6).collect();
+                    let limit = i.rate_limit.unwrap_or(integrations::DEFAULT_RATE_LIMIT);
+                    format!("{}: /hooks/{}… ({} per minute)", i.name, token, limit)
+                })
+                .collect();
+            Ok(Some(format!("Incoming webhooks:\n{}", lines.join("\n"))))
+        }
+        ["add", name, rest @ ..] => {
+            let rate_limit = match rest {
+                [] => None,
+                [limit] => match limit.parse() {
+                    Ok(0) | Err(_) => return Err("The rate limit must be a positive number".into()),
+                    Ok(limit) => Some(limit),
+                },
+                _ => return Err("Usage: /hook add <name> [per minute]".into()),
+            };
+            match integrations.add(name, rate_limit) {
+                Some(integration) => Ok(Some(format!(
+                    "{} can now post to /hooks/{} on the HTTP server",
+                    integration.name, integration.token
+                ))),
+                None => Err(format!("There is already an incoming webhook named {}", name)),
+            }
+        }
+        ["revoke", name] => match integrations.revoke(name) {
+            true => Ok(Some(format!("Revoked the token of {}", name))),
+            false => Err(format!("There is no incoming webhook named {}", name)),
+        },
+        _ => Err("Usage: /hook [add <name> [per minute] | revoke <name>]".into()),
+    }
+}
+
+fn help(_ctx: &mut CommandContext, _args: &str) -> CommandResult {
+    let lines: Vec<String> = COMMANDS
+        .iter()
+        .map(|c| format!("{} - {}", c.usage, c.description))
+        .collect();
+    Ok(Some(format!("Available commands:\n{}", lines.join("\n"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    // Runs `text` as ann and returns whether it was a command and what she was told
+    fn run(text: &str) -> (bool, Vec<String>) {
+        let state = ServerState::new(1, None);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut user_id = "ann".to_string();
+        let mut ctx = CommandContext {
+            state: &state,
+            user_id: &mut user_id,
+            tx: &tx,
+        };
+        let handled = dispatch(&mut ctx, text);
+        let mut told = Vec::new();
+        while let Ok(frame) = rx.try_recv() {
+            let frame: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            assert_eq!(frame["messageType"], "notice");
+            told.push(frame["data"].as_str().unwrap().to_string());
+        }
+        (handled, told)
+    }
+
+    #[test]
+    fn unknown_commands_are_answered_not_sent() {
+        let (handled, told) = run("/frobnicate now");
+        assert!(handled);
+        assert_eq!(told, ["Unknown command /frobnicate. Type /help for a list of commands."]);
+    }
+
+    #[test]
+    fn a_double_slash_and_plain_text_are_messages() {
+        assert_eq!(run("//frobnicate"), (false, Vec::new()));
+        assert_eq!(run("// not a command"), (false, Vec::new()));
+        assert_eq!(run("hello /help"), (false, Vec::new()));
+    }
+
+    #[test]
+    fn command_names_ignore_case() {
+        let (handled, told) = run("/HeLp");
+        assert!(handled);
+        assert!(told[0].contains("/nick <name>"), "{:?}", told);
+    }
+}
@@ -0,0 +1,86 @@
+pub use chat_protocol::ContentKind;
+
+/// File extensions of links that are shown as images.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "avif", "svg"];
+
+/// Picks the kind of a message. The sender may ask for text or an image, which
+/// is honoured when the text fits; otherwise the kind is derived from the content.
+pub fn resolve_kind(requested: Option<ContentKind>, text: &str, has_attachment: bool) -> ContentKind {
+    if has_attachment {
+        return ContentKind::Attachment;
+    }
+    match requested {
+        Some(ContentKind::Text) => ContentKind::Text,
+        Some(ContentKind::Image) if is_url(text) => ContentKind::Image,
+        _ if is_image_url(text) => ContentKind::Image,
+        _ => ContentKind::Text,
+    }
+}
+
+fn is_url(text: &str) -> bool {
+    let text = text.trim();
+    (text.starts_with("https://") || text.starts_with("http://"))
+        && !text.contains(char::is_whitespace)
+}
+
+// A single link whose path ends in an image extension, ignoring query and fragment
+fn is_image_url(text: &str) -> bool {
+    if !is_url(text) {
+        return false;
+    }
+    let path = text.trim().split(['?', '#']).next().unwrap_or_default();
+    let Some((rest, extension)) = path.rsplit_once('.') else {
+        return false;
+    };
+    // The dot must be in the path, not in the host name
+    rest.split_once("://").is_some_and(|(_, host_and_path)| host_and_path.contains('/'))
+        && IMAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attachments_win_over_everything() {
+        let kind = resolve_kind(Some(ContentKind::Image), "https://a.org/cat.png", true);
+        assert_eq!(kind, ContentKind::Attachment);
+        assert_eq!(resolve_kind(None, "", true), ContentKind::Attachment);
+    }
+
+    #[test]
+    fn requested_kinds_are_honoured_when_they_fit() {
+        let link = "https://a.org/cat.png";
+        assert_eq!(resolve_kind(Some(ContentKind::Text), link, false), ContentKind::Text);
+        let page = "https://a.org/cat";
+        assert_eq!(resolve_kind(Some(ContentKind::Image), page, false), ContentKind::Image);
+        let words = "not a link";
+        assert_eq!(resolve_kind(Some(ContentKind::Image), words, false), ContentKind::Text);
+    }
+
+    #[test]
+    fn image_links_are_detected() {
+        for text in [
+            "https://a.org/cat.png",
+            " http://a.org/x/cat.JPEG ",
+            "https://a.org/cat.webp?size=2#top",
+        ] {
+            assert_eq!(resolve_kind(None, text, false), ContentKind::Image, "{}", text);
+        }
+    }
+
+    #[test]
+    fn other_text_stays_text() {
+        for text in [
+            "hello",
+            "cat.png",
+            "https://a.org/cat.txt",
+            "https://cat.png",
+            "https://a.org/page?file=cat.png",
+            "look https://a.org/cat.png",
+            "ftp://a.org/cat.png",
+        ] {
+            assert_eq!(resolve_kind(None, text, false), ContentKind::Text, "{}", text);
+        }
+    }
+}
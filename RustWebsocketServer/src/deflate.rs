@@ -0,0 +1,770 @@
+//! The permessage-deflate WebSocket extension (RFC 7692). tungstenite does
+//! not implement it, so `DeflateStream` sits between the socket and the
+//! WebSocket: it passes the HTTP handshake through untouched, then inflates
+//! compressed client frames and deflates large server frames on the fly.
+//!
+//! This file only depends on std, flate2, tokio and log so the compression
+//! benchmark can include it on its own.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use log::warn;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Name of the extension in `Sec-WebSocket-Extensions`.
+pub const EXTENSION: &str = "permessage-deflate";
+
+/// Largest message inflated, matching tungstenite's own message size limit.
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// Trailer every compressed message ends with, removed on the wire.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Compression settings, from `CHAT_DEFLATE`, `CHAT_DEFLATE_WINDOW_BITS` and
+/// `CHAT_DEFLATE_MIN_SIZE`.
+#[derive(Debug, Clone, Copy)]
+pub struct DeflateConfig {
+    pub enabled: bool,
+    /// Base-2 logarithm of the server's compression window, 9 to 15. Smaller
+    /// windows use less memory per connection but compress worse.
+    pub window_bits: u8,
+    /// Messages shorter than this many bytes are sent uncompressed.
+    pub min_size: usize,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_bits: 15,
+            min_size: 128,
+        }
+    }
+}
+
+impl DeflateConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let enabled = std::env::var("CHAT_DEFLATE")
+            .map(|value| !matches!(value.trim(), "0" | "off" | "false"))
+            .unwrap_or(default.enabled);
+        let window_bits = std::env::var("CHAT_DEFLATE_WINDOW_BITS")
+            .ok()
+            .and_then(|value| value.trim().parse::<u8>().ok())
+            .map(|bits| bits.clamp(9, 15))
+            .unwrap_or(default.window_bits);
+        let min_size = std::env::var("CHAT_DEFLATE_MIN_SIZE")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(default.min_size);
+        Self {
+            enabled,
+            window_bits,
+            min_size,
+        }
+    }
+}
+
+/// Parameters agreed with a client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Negotiated {
+    /// The server resets its compressor after every message.
+    pub server_no_context_takeover: bool,
+    /// The client resets its compressor after every message.
+    pub client_no_context_takeover: bool,
+    pub server_max_window_bits: u8,
+    pub min_size: usize,
+}
+
+/// Outcome of the handshake, set once the response is ready: `None` inside
+/// when the client did not offer the extension or no offer was acceptable.
+pub type Extension = Arc<OnceLock<Option<Negotiated>>>;
+
+/// Picks the first acceptable permessage-deflate offer out of the
+/// `Sec-WebSocket-Extensions` values sent by a client. Returns the agreed
+/// parameters and the value to answer with, or `None` to go uncompressed.
+pub fn negotiate(config: &DeflateConfig, offers: &str) -> Option<(Negotiated, String)> {
+    if !config.enabled {
+        return None;
+    }
+    offers
+        .split(',')
+        .find_map(|offer| accept_offer(config, offer))
+}
+
+fn accept_offer(config: &DeflateConfig, offer: &str) -> Option<(Negotiated, String)> {
+    let mut params = offer.split(';').map(str::trim);
+    if params.next()? != EXTENSION {
+        return None;
+    }
+
+    let mut negotiated = Negotiated {
+        server_no_context_takeover: false,
+        client_no_context_takeover: false,
+        server_max_window_bits: config.window_bits,
+        min_size: config.min_size,
+    };
+    let mut response = EXTENSION.to_string();
+    let mut seen = Vec::new();
+    for param in params.filter(|param| !param.is_empty()) {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (param, None),
+        };
+        // Offers repeating a parameter must be declined
+        if seen.contains(&name) {
+            return None;
+        }
+        seen.push(name);
+
+        match (name, value) {
+            ("server_no_context_takeover", None) => {
+                negotiated.server_no_context_takeover = true;
+                response.push_str("; server_no_context_takeover");
+            }
+            ("client_no_context_takeover", None) => {
+                negotiated.client_no_context_takeover = true;
+                response.push_str("; client_no_context_takeover");
+            }
+            ("server_max_window_bits", Some(bits)) => {
+                // zlib cannot compress with an 8 bit window
+                let bits = window_bits(bits).filter(|bits| *bits >= 9)?;
+                negotiated.server_max_window_bits = bits.min(config.window_bits);
+                response.push_str(&format!(
+                    "; server_max_window_bits={}",
+                    negotiated.server_max_window_bits
+                ));
+            }
+            // Messages are always inflated with the largest window, so the
+            // client may use whatever window it likes
+            ("client_max_window_bits", None) => {}
+            ("client_max_window_bits", Some(bits)) => {
+                window_bits(bits)?;
+            }
+            _ => return None,
+        }
+    }
+    Some((negotiated, response))
+}
+
+fn window_bits(value: &str) -> Option<u8> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|bits| (8..=15).contains(bits))
+}
+
+/// Compression state of one connection.
+pub struct Codec {
+    negotiated: Negotiated,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl Codec {
+    pub fn new(negotiated: Negotiated) -> Self {
+        Self {
+            negotiated,
+            compress: Compress::new_with_window_bits(
+                Compression::default(),
+                false,
+                negotiated.server_max_window_bits,
+            ),
+            decompress: Decompress::new_with_window_bits(false, 15),
+        }
+    }
+
+    /// Compresses one message payload as it is sent on the wire.
+    pub fn compress(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(payload.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if output.capacity() - output.len() < 64 {
+                output.reserve(output.capacity().max(64));
+            }
+            self.compress
+                .compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            // The flush is complete once the output stops filling up
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&TRAILER) {
+            output.truncate(output.len() - TRAILER.len());
+        }
+        // zlib writes nothing when flushing an empty message after another
+        // one; it still has to be sent as an empty block (RFC 7692 7.2.3.6)
+        if output.is_empty() {
+            output.push(0x00);
+        }
+        if self.negotiated.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(output)
+    }
+
+    /// Inflates one compressed message payload received from the client.
+    pub fn decompress(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let mut input = Vec::with_capacity(payload.len() + TRAILER.len());
+        input.extend_from_slice(payload);
+        input.extend_from_slice(&TRAILER);
+
+        let mut output = Vec::with_capacity(payload.len() * 4 + 64);
+        let start = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if output.capacity() - output.len() < 64 {
+                output.reserve(output.capacity().max(64));
+            }
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if output.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed message is too large",
+                ));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let done = consumed == input.len() && output.len() < output.capacity();
+            if done || status == Status::StreamEnd {
+                break;
+            }
+            if status == Status::BufError && output.len() < output.capacity() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated compressed message",
+                ));
+            }
+        }
+
+        if self.negotiated.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+}
+
+// Header of a WebSocket frame
+struct FrameHeader {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    header_len: usize,
+    payload_len: usize,
+}
+
+impl FrameHeader {
+    fn parse(buf: &[u8]) -> io::Result<Option<Self>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let masked = buf[1] & 0x80 != 0;
+        let (mut header_len, payload_len) = match buf[1] & 0x7f {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => (4, u16::from_be_bytes([buf[2], buf[3]]) as u64),
+            127 if buf.len() < 10 => return Ok(None),
+            127 => (10, u64::from_be_bytes(buf[2..10].try_into().unwrap())),
+            len => (2, len as u64),
+        };
+        if payload_len > MAX_MESSAGE_SIZE as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame is too large"));
+        }
+
+        let mask = if masked {
+            let Some(key) = buf.get(header_len..header_len + 4) else {
+                return Ok(None);
+            };
+            header_len += 4;
+            Some(key.try_into().unwrap())
+        } else {
+            None
+        };
+        Ok(Some(Self {
+            fin: buf[0] & 0x80 != 0,
+            rsv1: buf[0] & 0x40 != 0,
+            opcode: buf[0] & 0x0f,
+            mask,
+            header_len,
+            payload_len: payload_len as usize,
+        }))
+    }
+
+    fn is_control(&self) -> bool {
+        self.opcode & 0x08 != 0
+    }
+}
+
+// Writes a single final frame carrying `payload`, masked with a zero key
+// when `masked` so the payload bytes stay as they are
+fn write_frame(out: &mut Vec<u8>, rsv1: bool, opcode: u8, masked: bool, payload: &[u8]) {
+    out.push(0x80 | if rsv1 { 0x40 } else { 0 } | opcode);
+    let mask_bit = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => out.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        out.extend_from_slice(&[0; 4]);
+    }
+    out.extend_from_slice(payload);
+}
+
+fn unmask(payload: &mut [u8], mask: Option<[u8; 4]>) {
+    if let Some(mask) = mask {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+}
+
+fn head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    // HTTP upgrade request or response still going through
+    Handshake,
+    // Frames are rewritten
+    Frames,
+    // No extension was agreed, bytes go through untouched
+    Passthrough,
+}
+
+/// Server side of a connection that may have permessage-deflate enabled.
+/// The handshake callback decides through `extension`; until then, and for
+/// good when the extension was not agreed, bytes pass through untouched.
+pub struct DeflateStream<S> {
+    inner: S,
+    extension: Extension,
+    codec: Option<Codec>,
+    read_phase: Phase,
+    // Bytes read from the socket and not processed yet
+    read_raw: Vec<u8>,
+    // Processed bytes waiting for the WebSocket to read them
+    read_ready: Vec<u8>,
+    // Opcode and payload of a compressed message spanning several frames
+    message: Option<(u8, Vec<u8>)>,
+    write_phase: Phase,
+    // Bytes written by the WebSocket and not processed yet
+    write_raw: Vec<u8>,
+    // Processed bytes waiting to go out on the socket
+    write_ready: Vec<u8>,
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S, extension: Extension) -> Self {
+        Self {
+            inner,
+            extension,
+            codec: None,
+            read_phase: Phase::Handshake,
+            read_raw: Vec::new(),
+            read_ready: Vec::new(),
+            message: None,
+            write_phase: Phase::Handshake,
+            write_raw: Vec::new(),
+            write_ready: Vec::new(),
+        }
+    }
+
+    // Phase following the handshake, creating the codec when compressing
+    fn after_handshake(&mut self) -> Phase {
+        match self.extension.get() {
+            Some(Some(negotiated)) => {
+                self.codec.get_or_insert_with(|| Codec::new(*negotiated));
+                Phase::Frames
+            }
+            _ => Phase::Passthrough,
+        }
+    }
+
+    // Moves complete client frames from `read_raw` to `read_ready`,
+    // inflating compressed messages into plain frames
+    fn process_read(&mut self) -> io::Result<()> {
+        let mut start = 0;
+        while let Some(header) = FrameHeader::parse(&self.read_raw[start..])? {
+            let end = start + header.header_len + header.payload_len;
+            if self.read_raw.len() < end {
+                break;
+            }
+            let frame = start..end;
+            start = end;
+
+            let compressed = header.rsv1 && matches!(header.opcode, 1 | 2);
+            let continued = header.opcode == 0 && self.message.is_some();
+            if header.is_control() || !(compressed || continued) {
+                self.read_ready.extend_from_slice(&self.read_raw[frame]);
+                continue;
+            }
+
+            let mut payload = self.read_raw[frame.start + header.header_len..frame.end].to_vec();
+            unmask(&mut payload, header.mask);
+            let (opcode, data) = self.message.get_or_insert((header.opcode, Vec::new()));
+            let opcode = *opcode;
+            data.extend_from_slice(&payload);
+            if data.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "message is too large"));
+            }
+            if header.fin {
+                let (_, data) = self.message.take().unwrap();
+                let codec = self.codec.as_mut().unwrap();
+                let inflated = codec.decompress(&data)?;
+                write_frame(&mut self.read_ready, false, opcode, true, &inflated);
+            }
+        }
+        self.read_raw.drain(..start);
+        Ok(())
+    }
+
+    // Moves complete server frames from `write_raw` to `write_ready`,
+    // compressing whole data messages over the size threshold
+    fn process_write(&mut self) -> io::Result<()> {
+        let mut start = 0;
+        while let Some(header) = FrameHeader::parse(&self.write_raw[start..])? {
+            let end = start + header.header_len + header.payload_len;
+            if self.write_raw.len() < end {
+                break;
+            }
+            let payload = &self.write_raw[start + header.header_len..end];
+            let codec = self.codec.as_mut().unwrap();
+            let compress = header.fin
+                && !header.rsv1
+                && matches!(header.opcode, 1 | 2)
+                && !payload.is_empty()
+                && payload.len() >= codec.negotiated.min_size;
+            if compress {
+                let deflated = codec.compress(payload)?;
+                write_frame(&mut self.write_ready, true, header.opcode, false, &deflated);
+            } else {
+                self.write_ready.extend_from_slice(&self.write_raw[start..end]);
+            }
+            start = end;
+        }
+        self.write_raw.drain(..start);
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    // Writes out as much of `write_ready` as the socket takes
+    fn poll_write_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_ready.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.write_ready) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => {
+                    self.write_ready.drain(..n);
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.read_ready.is_empty() {
+                let n = this.read_ready.len().min(buf.remaining());
+                buf.put_slice(&this.read_ready[..n]);
+                this.read_ready.drain(..n);
+                return Poll::Ready(Ok(()));
+            }
+
+            match this.read_phase {
+                Phase::Handshake => {
+                    // Only hand over the request, the callback has to run
+                    // before any frame is looked at
+                    if let Some(end) = head_end(&this.read_raw) {
+                        this.read_ready = this.read_raw.drain(..end).collect();
+                        this.read_phase = Phase::Frames;
+                        continue;
+                    }
+                }
+                Phase::Frames => {
+                    if this.codec.is_none() && this.after_handshake() == Phase::Passthrough {
+                        this.read_phase = Phase::Passthrough;
+                        continue;
+                    }
+                    this.process_read()?;
+                    if !this.read_ready.is_empty() {
+                        continue;
+                    }
+                }
+                Phase::Passthrough if this.read_raw.is_empty() => {
+                    return Pin::new(&mut this.inner).poll_read(cx, buf);
+                }
+                Phase::Passthrough => {
+                    this.read_ready = std::mem::take(&mut this.read_raw);
+                    continue;
+                }
+            }
+
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(())) if chunk.filled().is_empty() => {
+                    if !this.read_raw.is_empty() || this.message.is_some() {
+                        warn!("Connection closed in the middle of a frame");
+                    }
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Ready(Ok(())) => this.read_raw.extend_from_slice(chunk.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write_phase == Phase::Passthrough && this.write_ready.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        // Hold back new data while a lot is still waiting to go out
+        if this.poll_write_ready(cx)?.is_pending() && this.write_ready.len() >= 64 * 1024 {
+            return Poll::Pending;
+        }
+
+        this.write_raw.extend_from_slice(buf);
+        if this.write_phase == Phase::Handshake {
+            if let Some(end) = head_end(&this.write_raw) {
+                this.write_ready.extend(this.write_raw.drain(..end));
+                this.write_phase = this.after_handshake();
+            }
+        }
+        match this.write_phase {
+            Phase::Handshake => {}
+            Phase::Frames => this.process_write()?,
+            Phase::Passthrough => this.write_ready.append(&mut this.write_raw),
+        }
+        // Try to get the bytes going, flushing finishes the job
+        let _ = this.poll_write_ready(cx)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_write_ready(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_write_ready(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(offers: &str) -> Option<(Negotiated, String)> {
+        negotiate(&DeflateConfig::default(), offers)
+    }
+
+    #[test]
+    fn accepts_plain_offers() {
+        let (negotiated, response) = offer("permessage-deflate; client_max_window_bits").unwrap();
+        assert_eq!(response, "permessage-deflate");
+        assert_eq!(negotiated.server_max_window_bits, 15);
+        assert!(!negotiated.server_no_context_takeover);
+        assert!(!negotiated.client_no_context_takeover);
+
+        let disabled = DeflateConfig {
+            enabled: false,
+            ..DeflateConfig::default()
+        };
+        assert_eq!(negotiate(&disabled, "permessage-deflate"), None);
+        assert_eq!(offer("x-webkit-deflate-frame"), None);
+    }
+
+    #[test]
+    fn agrees_on_window_bits_and_context_takeover() {
+        let (negotiated, response) = offer(
+            "permessage-deflate; server_max_window_bits=\"10\"; server_no_context_takeover; \
+             client_no_context_takeover; client_max_window_bits=12",
+        )
+        .unwrap();
+        assert_eq!(
+            response,
+            "permessage-deflate; server_max_window_bits=10; server_no_context_takeover; \
+             client_no_context_takeover"
+        );
+        assert_eq!(negotiated.server_max_window_bits, 10);
+        assert!(negotiated.server_no_context_takeover);
+        assert!(negotiated.client_no_context_takeover);
+
+        // The server never uses a larger window than configured
+        let small = DeflateConfig {
+            window_bits: 12,
+            ..DeflateConfig::default()
+        };
+        let (negotiated, response) =
+            negotiate(&small, "permessage-deflate; server_max_window_bits=15").unwrap();
+        assert_eq!(negotiated.server_max_window_bits, 12);
+        assert_eq!(response, "permessage-deflate; server_max_window_bits=12");
+    }
+
+    #[test]
+    fn declines_bad_offers_and_falls_back_to_the_next() {
+        for bad in [
+            "permessage-deflate; server_max_window_bits=8",
+            "permessage-deflate; server_max_window_bits",
+            "permessage-deflate; client_max_window_bits=16",
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+            "permessage-deflate; server_no_context_takeover=1",
+            "permessage-deflate; unknown_param",
+        ] {
+            assert_eq!(offer(bad), None, "{}", bad);
+        }
+
+        let (negotiated, response) = offer(
+            "permessage-deflate; server_max_window_bits=8, permessage-deflate; server_max_window_bits=9",
+        )
+        .unwrap();
+        assert_eq!(negotiated.server_max_window_bits, 9);
+        assert_eq!(response, "permessage-deflate; server_max_window_bits=9");
+    }
+
+    #[test]
+    fn parses_frame_headers_of_every_length() {
+        let short = FrameHeader::parse(&[0x81, 0x05, b'h']).unwrap().unwrap();
+        assert!(short.fin && !short.rsv1 && short.mask.is_none());
+        assert_eq!((short.opcode, short.header_len, short.payload_len), (1, 2, 5));
+
+        let medium = FrameHeader::parse(&[0x02, 126, 0x01, 0x00]).unwrap().unwrap();
+        assert!(!medium.fin);
+        assert_eq!((medium.opcode, medium.header_len, medium.payload_len), (2, 4, 256));
+
+        let long = FrameHeader::parse(&[0x80, 127, 0, 0, 0, 0, 0, 1, 0, 0]).unwrap().unwrap();
+        assert_eq!((long.opcode, long.header_len, long.payload_len), (0, 10, 65536));
+
+        // Headers are only parsed once all of them has arrived
+        for partial in [&[0x81][..], &[0x82, 126, 0x01], &[0x82, 127, 0, 0, 0, 0]] {
+            assert!(FrameHeader::parse(partial).unwrap().is_none());
+        }
+
+        let too_large = [0x82, 127, 0, 0, 0x01, 0, 0, 0, 0, 0];
+        assert!(FrameHeader::parse(&too_large).is_err());
+    }
+
+    #[test]
+    fn parses_masks_and_unmasks() {
+        let key = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![0xc1, 0x85];
+        frame.extend_from_slice(&key);
+        frame.extend(b"Hello".iter().zip(key.iter().cycle()).map(|(b, k)| b ^ k));
+
+        assert!(FrameHeader::parse(&frame[..4]).unwrap().is_none());
+        let header = FrameHeader::parse(&frame).unwrap().unwrap();
+        assert!(header.fin && header.rsv1);
+        assert_eq!(header.mask, Some(key));
+        assert_eq!((header.header_len, header.payload_len), (6, 5));
+
+        let mut payload = frame[header.header_len..].to_vec();
+        unmask(&mut payload, header.mask);
+        assert_eq!(payload, b"Hello");
+    }
+
+    #[test]
+    fn written_frames_parse_back() {
+        for len in [0, 125, 126, 65535, 65536] {
+            let payload = vec![7; len];
+            let mut frame = Vec::new();
+            write_frame(&mut frame, true, 2, true, &payload);
+            let header = FrameHeader::parse(&frame).unwrap().unwrap();
+            assert!(header.fin && header.rsv1);
+            assert_eq!(header.mask, Some([0; 4]));
+            assert_eq!(header.payload_len, len);
+            assert_eq!(frame.len(), header.header_len + len);
+        }
+    }
+
+    fn negotiated(no_context_takeover: bool, window_bits: u8) -> Negotiated {
+        Negotiated {
+            server_no_context_takeover: no_context_takeover,
+            client_no_context_takeover: no_context_takeover,
+            server_max_window_bits: window_bits,
+            min_size: 0,
+        }
+    }
+
+    #[test]
+    fn compressed_messages_inflate_to_the_original() {
+        let messages: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!(r#"{{"messageType":"message","data":"hello {}"}}"#, i).repeat(i + 1))
+            .map(String::into_bytes)
+            .chain([Vec::new(), (0..=255).collect(), vec![b'x'; 200_000]])
+            .collect();
+
+        for no_context_takeover in [false, true] {
+            for window_bits in [9, 15] {
+                let agreed = negotiated(no_context_takeover, window_bits);
+                let (mut server, mut client) = (Codec::new(agreed), Codec::new(agreed));
+                for message in &messages {
+                    let deflated = server.compress(message).unwrap();
+                    assert!(!deflated.ends_with(&TRAILER));
+                    assert_eq!(&client.decompress(&deflated).unwrap(), message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn context_takeover_shrinks_repeated_messages() {
+        let message = br#"{"messageType":"users","dataArray":["alice","bob","carol"]}"#;
+        let mut codec = Codec::new(negotiated(false, 15));
+        let first = codec.compress(message).unwrap().len();
+        assert!(codec.compress(message).unwrap().len() < first);
+
+        let mut codec = Codec::new(negotiated(true, 15));
+        let first = codec.compress(message).unwrap().len();
+        assert_eq!(codec.compress(message).unwrap().len(), first);
+    }
+
+    #[test]
+    fn refuses_corrupt_input() {
+        let mut codec = Codec::new(negotiated(false, 15));
+        assert!(codec.decompress(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
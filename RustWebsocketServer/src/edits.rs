@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attachments, broadcast_message, commands::notice, content, mentions, now_millis, send_frame, threads,
+    MessageType, ServerState, Tx, WebSocketMessage,
+};
+
+/// A previous text of an edited message.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageVersion {
+    message: String,
+    time: u64,
+}
+
+// Edit requested by a client
+#[derive(Debug, Deserialize)]
+struct EditRequest {
+    id: u64,
+    text: String,
+}
+
+/// Applies an `edit` frame: only the author may change a message, and the
+/// replaced text is kept in the message's edit history.
+pub fn edit_message(state: &ServerState, user_id: &str, tx: &Tx, data: &str) {
+    let Ok(request) = serde_json::from_str::<EditRequest>(data) else {
+        return;
+    };
+    let text = request.text.trim();
+    if text.is_empty() {
+        send_frame(tx, &notice("A message cannot be edited to be empty"));
+        return;
+    }
+
+    let known_users = mentions::known_users(state);
+    let json = {
+        let mut history = state.history.lock().unwrap();
+        let Some(message) = history.get_mut(request.id) else {
+            send_frame(tx, &notice("That message no longer exists"));
+            return;
+        };
+        if message.from != user_id {
+            send_frame(tx, &notice("You can only edit your own messages"));
+            return;
+        }
+        if message.deleted {
+            send_frame(tx, &notice("That message was deleted"));
+            return;
+        }
+        if message.message == text {
+            return;
+        }
+
+        let previous = std::mem::replace(&mut message.message, text.to_string());
+        message.edits.push(MessageVersion {
+            message: previous,
+            time: message.edited_at.unwrap_or(message.time),
+        });
+        message.edited_at = Some(now_millis());
+        message.mentions = mentions::parse_mentions(&message.message, &known_users);
+        // An edit keeps the kind unless the new text no longer fits it
+        message.kind = content::resolve_kind(
+            Some(message.kind),
+            &message.message,
+            message.attachment.is_some(),
+        );
+
+        let json = serde_json::to_string(&WebSocketMessage {
+            message_type: MessageType::Edit,
+            data: Some(serde_json::to_string(message).unwrap()),
+            data_array: None,
+        })
+        .unwrap();
+        history.reindex(request.id);
+        json
+    };
+
+    broadcast_message(state, &json);
+}
+
+/// Applies a `delete` frame from the author or a moderator. The content and
+/// its edit history are dropped, leaving a tombstone with the same ID, and
+/// quotes of the message in replies are cleared too. Deleting a thread reply
+/// takes it off its root's reply count.
+pub fn delete_message(state: &ServerState, user_id: &str, tx: &Tx, id: u64) {
+    let thread_id = {
+        let mut history = state.history.lock().unwrap();
+        let Some(message) = history.get_mut(id) else {
+            send_frame(tx, &notice("That message no longer exists"));
+            return;
+        };
+        if message.from != user_id && !state.moderators.contains(user_id) {
+            send_frame(tx, &notice("You can only delete your own messages"));
+            return;
+        }
+        let attachment = message.attachment.clone();
+        let thread_id = message.thread_id;
+        if !history.tombstone(id) {
+            return;
+        }
+        if let Some(attachment) = attachment {
+            attachments::release(state, &history, &attachment.id);
+        }
+        thread_id
+    };
+
+    let json = serde_json::to_string(&WebSocketMessage {
+        message_type: MessageType::Delete,
+        data: Some(id.to_string()),
+        data_array: None,
+    })
+    .unwrap();
+    broadcast_message(state, &json);
+    if let Some(root_id) = thread_id {
+        threads::update_reply_count(state, root_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::ChatMessage;
+
+    // A state holding one message from ann, and its ID
+    fn with_message(text: &str) -> (ServerState, u64) {
+        let state = ServerState::new(1, None);
+        let message = ChatMessage::text(&state, "ann", text.to_string());
+        let id = state.history.lock().unwrap().push(message).id;
+        (state, id)
+    }
+
+    // Sends an edit from `user_id` and returns what they were told, if anything
+    fn edit(state: &ServerState, user_id: &str, id: u64, text: &str) -> Option<String> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let data = serde_json::json!({ "id": id, "text": text }).to_string();
+        edit_message(state, user_id, &tx, &data);
+        rx.try_recv().ok().map(|frame| frame.to_text().unwrap().to_string())
+    }
+
+    fn text_of(state: &ServerState, id: u64) -> (String, usize) {
+        let history = state.history.lock().unwrap();
+        let message = history.get(id).unwrap();
+        (message.message.clone(), message.edits.len())
+    }
+
+    #[test]
+    fn only_the_author_may_edit() {
+        let (state, id) = with_message("hi");
+        let answer = edit(&state, "bob", id, "bye").unwrap();
+        assert!(answer.contains("You can only edit your own messages"), "{}", answer);
+        assert_eq!(text_of(&state, id), ("hi".to_string(), 0));
+
+        edit(&state, "ann", id, "bye");
+        assert_eq!(text_of(&state, id), ("bye".to_string(), 1));
+    }
+
+    #[test]
+    fn edited_text_is_trimmed() {
+        let (state, id) = with_message("hi");
+        edit(&state, "ann", id, "  bye \n");
+        assert_eq!(text_of(&state, id), ("bye".to_string(), 1));
+
+        // Only whitespace around the text is no change at all
+        assert_eq!(edit(&state, "ann", id, "bye  "), None);
+        assert_eq!(text_of(&state, id), ("bye".to_string(), 1));
+
+        let answer = edit(&state, "ann", id, " \t ").unwrap();
+        assert!(answer.contains("cannot be edited to be empty"), "{}", answer);
+        assert_eq!(text_of(&state, id), ("bye".to_string(), 1));
+    }
+
+    #[test]
+    fn deleted_and_missing_messages_stay_as_they_are() {
+        let (state, id) = with_message("hi");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        delete_message(&state, "ann", &tx, id);
+        let answer = edit(&state, "ann", id, "bye").unwrap();
+        assert!(answer.contains("That message was deleted"), "{}", answer);
+
+        let answer = edit(&state, "ann", id + 100, "bye").unwrap();
+        assert!(answer.contains("That message no longer exists"), "{}", answer);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachments;
+use crate::search::SearchIndex;
+use crate::{send_frame, ChatMessage, MessageType, ServerState, Tx, UserId, WebSocketMessage};
+
+/// Messages sent per page when the client does not say.
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Most messages sent in a single page.
+const MAX_PAGE_SIZE: usize = 200;
+
+// Page of history asked for by a client
+#[derive(Debug, Deserialize, Default)]
+struct PageRequest {
+    before: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct Page<'a> {
+    messages: Vec<&'a ChatMessage>,
+    has_more: bool,
+}
+
+/// Every chat message sent since the server started, ordered by ID.
+pub struct History {
+    messages: Vec<ChatMessage>,
+    next_id: u64,
+    // Distance between consecutive IDs, more than one when nodes share IDs
+    id_step: u64,
+    index: SearchIndex,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::for_node(0, 1)
+    }
+
+    /// History of node `node` out of `nodes`, whose IDs never clash with
+    /// those handed out by the other nodes.
+    pub fn for_node(node: u64, nodes: u64) -> Self {
+        Self {
+            messages: Vec::new(),
+            next_id: node + 1,
+            id_step: nodes,
+            index: SearchIndex::default(),
+        }
+    }
+
+    /// Assigns the next message ID to `message` and stores it.
+    pub fn push(&mut self, mut message: ChatMessage) -> &ChatMessage {
+        message.id = self.next_id;
+        self.next_id += self.id_step;
+        self.index.update(&message);
+        // Later than anything seen so far, unless a copy from another node
+        // is still on its way
+        let i = self.messages.partition_point(|m| m.id < message.id);
+        self.messages.insert(i, message);
+        &self.messages[i]
+    }
+
+    /// Stores a message another node handed out the ID for, replacing the
+    /// copy kept so far. IDs handed out here from then on are higher, so they
+    /// stay in the order messages were sent.
+    pub fn upsert(&mut self, message: ChatMessage) {
+        if message.id >= self.next_id {
+            let steps = (message.id - self.next_id) / self.id_step + 1;
+            self.next_id += steps * self.id_step;
+        }
+        self.index.update(&message);
+        match self.messages.binary_search_by_key(&message.id, |m| m.id) {
+            Ok(i) => self.messages[i] = message,
+            Err(i) => self.messages.insert(i, message),
+        }
+    }
+
+    /// Drops the content of message `id`, leaving a tombstone with the same
+    /// ID, and clears quotes of it in replies. Returns false when there was
+    /// nothing left to delete.
+    pub fn tombstone(&mut self, id: u64) -> bool {
+        let Some(message) = self.get_mut(id).filter(|m| !m.deleted) else {
+            return false;
+        };
+        message.deleted = true;
+        message.message.clear();
+        message.edits.clear();
+        message.reactions.clear();
+        message.mentions.clear();
+        message.attachment = None;
+        self.reindex(id);
+
+        for reply in self.messages.iter_mut().filter_map(|m| m.reply_to.as_mut()) {
+            if reply.id == id {
+                reply.deleted = true;
+                reply.message.clear();
+            }
+        }
+        true
+    }
+
+    pub fn get(&self, id: u64) -> Option<&ChatMessage> {
+        self.index_of(id).map(|i| &self.messages[i])
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut ChatMessage> {
+        self.index_of(id).map(|i| &mut self.messages[i])
+    }
+
+    /// Replies in the thread rooted at `root_id`, oldest first.
+    pub fn thread(&self, root_id: u64) -> impl Iterator<Item = &ChatMessage> {
+        self.messages
+            .iter()
+            .filter(move |m| m.thread_id == Some(root_id))
+    }
+
+    /// Up to `limit` top-level messages older than `before` (or the newest ones
+    /// without it), oldest first, and whether older ones remain. Thread replies
+    /// are left out; they are loaded with their thread.
+    pub fn page(&self, before: Option<u64>, limit: usize) -> (Vec<&ChatMessage>, bool) {
+        let mut older = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| m.thread_id.is_none() && before.is_none_or(|before| m.id < before));
+        let mut page: Vec<&ChatMessage> = older.by_ref().take(limit).collect();
+        let has_more = older.next().is_some();
+        page.reverse();
+        (page, has_more)
+    }
+
+    /// Up to `count` messages on each side of message `id`, with the message itself.
+    pub fn around(&self, id: u64, count: usize) -> Option<&[ChatMessage]> {
+        let i = self.index_of(id)?;
+        let end = (i + count + 1).min(self.messages.len());
+        Some(&self.messages[i.saturating_sub(count)..end])
+    }
+
+    /// Updates the search index after message `id` was edited or deleted.
+    pub fn reindex(&mut self, id: u64) {
+        if let Some(i) = self.index_of(id) {
+            self.index.update(&self.messages[i]);
+        }
+    }
+
+    pub fn search_index(&self) -> &SearchIndex {
+        &self.index
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.messages.iter()
+    }
+
+    fn index_of(&self, id: u64) -> Option<usize> {
+        self.messages.binary_search_by_key(&id, |m| m.id).ok()
+    }
+}
+
+// Reaction or reply count update as broadcast for a message
+#[derive(Debug, Deserialize)]
+struct MessageUpdate {
+    id: u64,
+    #[serde(default)]
+    reactions: BTreeMap<String, Vec<UserId>>,
+    #[serde(default)]
+    reply_count: usize,
+}
+
+/// Brings the local copy of the history up to date with a frame another
+/// node broadcast, so every node can page, search and react to all messages.
+pub fn replicate(state: &ServerState, frame: &str) {
+    let Ok(frame) = serde_json::from_str::<WebSocketMessage>(frame) else {
+        return;
+    };
+    let data = frame.data.unwrap_or_default();
+    let mut history = state.history.lock().unwrap();
+    match frame.message_type {
+        MessageType::Message | MessageType::Edit => {
+            if let Ok(message) = serde_json::from_str::<ChatMessage>(&data) {
+                history.upsert(message);
+            }
+        }
+        MessageType::Delete => {
+            let Ok(id) = data.parse() else {
+                return;
+            };
+            let attachment = history.get(id).and_then(|m| m.attachment.clone());
+            if history.tombstone(id) {
+                if let Some(attachment) = attachment {
+                    attachments::release(state, &history, &attachment.id);
+                }
+            }
+        }
+        MessageType::Reactions => {
+            let Ok(update) = serde_json::from_str::<MessageUpdate>(&data) else {
+                return;
+            };
+            if let Some(message) = history.get_mut(update.id) {
+                message.reactions = update.reactions;
+            }
+        }
+        MessageType::ThreadUpdate => {
+            let Ok(update) = serde_json::from_str::<MessageUpdate>(&data) else {
+                return;
+            };
+            if let Some(message) = history.get_mut(update.id) {
+                message.reply_count = update.reply_count;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Answers a `history` frame with a page of older messages.
+pub fn send_page(state: &ServerState, tx: &Tx, data: &str) {
+    let request: PageRequest = serde_json::from_str(data).unwrap_or_default();
+    let limit = request
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let history = state.history.lock().unwrap();
+    let (messages, has_more) = history.page(request.before, limit);
+    send_frame(
+        tx,
+        &WebSocketMessage {
+            message_type: MessageType::History,
+            data: Some(serde_json::to_string(&Page { messages, has_more }).unwrap()),
+            data_array: None,
+        },
+    );
+}
@@ -0,0 +1,253 @@
+//! Minimal HTTP/1.1 server for what does not fit the WebSocket: serving
+//! uploaded files and receiving incoming webhooks. Each connection handles a
+//! single request.
+
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::{attachments, integrations, SharedState};
+
+/// Largest request head (request line and headers) accepted, in bytes.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// How long a client has to send its whole request, head and body.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Accepts HTTP connections on `addr` until the listener fails.
+pub async fn serve(state: SharedState, addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind HTTP server to {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("HTTP server listening on: {}", addr);
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            handle_connection(state, stream).await;
+        });
+    }
+}
+
+async fn handle_connection(state: SharedState, stream: TcpStream) {
+    let mut stream = BufReader::new(stream);
+    let response = match read_request_in_time(&mut stream).await {
+        Ok(request) => route(&state, request).await,
+        Err(response) => response,
+    };
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason(response.status));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+
+    let stream = stream.get_mut();
+    if let Err(e) = async {
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&response.body).await?;
+        stream.shutdown().await
+    }
+    .await
+    {
+        error!("Error writing HTTP response: {}", e);
+    }
+}
+
+// Reads a request, answering 408 to clients that stall partway through so
+// they do not hold their socket forever
+async fn read_request_in_time(
+    stream: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Request, Response> {
+    timeout(REQUEST_TIMEOUT, read_request(stream))
+        .await
+        .unwrap_or_else(|_| Err(Response::text(408, "Request timeout")))
+}
+
+async fn read_request(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<Request, Response> {
+    let bad_request = || Response::text(400, "Bad request");
+
+    let mut head_size = 0;
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let read = stream.read_line(&mut line).await.map_err(|_| bad_request())?;
+        head_size += read;
+        if read == 0 || head_size > MAX_HEAD_SIZE {
+            return Err(bad_request());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut request_line = lines.first().ok_or_else(bad_request)?.split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(bad_request());
+    };
+    // The query string is not used by any route
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let headers: Vec<(String, String)> = lines[1..]
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let length = match headers.iter().find(|(name, _)| name == "content-length") {
+        Some((_, length)) => length.parse::<usize>().map_err(|_| bad_request())?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(Response::text(413, "Payload too large"));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.map_err(|_| bad_request())?;
+
+    Ok(Request {
+        method: method.to_string(),
+        path,
+        headers,
+        body,
+    })
+}
+
+async fn route(state: &SharedState, request: Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["files", id]) => serve_file(state, id).await,
+        (_, ["files", _]) => Response::text(405, "Method not allowed"),
+        ("POST", ["hooks", token]) => integrations::receive(state, token, &request),
+        (_, ["hooks", _]) => Response::text(405, "Method not allowed"),
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+async fn serve_file(state: &SharedState, id: &str) -> Response {
+    if !attachments::is_valid_id(id) {
+        return Response::text(404, "Not found");
+    }
+    let Ok(content) = tokio::fs::read(attachments::upload_dir().join(id)).await else {
+        return Response::text(404, "Not found");
+    };
+
+    // Metadata only lives in memory, files from before a restart are served as raw bytes
+    let attachment = state.attachments.lock().unwrap().get(id).cloned();
+    let (mime, disposition) = match attachment {
+        Some(a) if a.mime.starts_with("image/") => (a.mime, format!("inline; filename=\"{}\"", a.name)),
+        Some(a) => (a.mime, format!("attachment; filename=\"{}\"", a.name)),
+        None => ("application/octet-stream".to_string(), "attachment".to_string()),
+    };
+
+    Response::new(200, &mime, content)
+        .header("Content-Disposition", disposition)
+        .header("X-Content-Type-Options", "nosniff")
+        // Files are addressed by their content, so they never change
+        .header("Cache-Control", "public, max-age=31536000, immutable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_head_and_body() {
+        let raw = "POST /hooks/t0k3n?x=1 HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi";
+        let request = read_request(&mut raw.as_bytes()).await.ok().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/hooks/t0k3n"));
+        assert_eq!(request.header("content-type"), Some("text/plain"));
+        assert_eq!(request.body, b"hi");
+    }
+
+    #[tokio::test]
+    async fn refuses_truncated_and_oversized_requests() {
+        let status = |raw: &'static str| async move {
+            read_request(&mut raw.as_bytes()).await.err().unwrap().status
+        };
+        assert_eq!(status("GET /files/x HTTP/1.1\r\n").await, 400);
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhi").await, 400);
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: 999999\r\n\r\n").await, 413);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_requests_time_out() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut server = BufReader::new(server);
+        client.write_all(b"GET /files/x HTTP/1.1\r\n").await.unwrap();
+
+        // The client keeps the connection open without finishing its request
+        let refused = read_request_in_time(&mut server).await.err().unwrap();
+        assert_eq!(refused.status, 408);
+        drop(client);
+    }
+}
@@ -0,0 +1,355 @@
+//! Incoming webhooks: scripts and CI post to `/hooks/<token>` on the HTTP
+//! server and the text shows up in the chat as a message from the
+//! integration the token belongs to.
+//!
+//! Bodies are JSON like `{"text": "Build passed"}`, or anything Slack's
+//! incoming webhooks accept: `attachments` are added below the text, links
+//! written `<url|label>` are spelled out, and form posts with a `payload`
+//! field work too. Each token may post a limited number of messages per
+//! minute, and moderators add and revoke tokens with `/hook`.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::http::{Request, Response};
+use crate::{post_message, ChatMessage, ServerState};
+
+/// Messages an integration may post per minute unless it says otherwise.
+pub const DEFAULT_RATE_LIMIT: u32 = 30;
+
+/// Window the rate limit is counted over.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// An integration allowed to post messages, as stored in the
+/// `CHAT_INCOMING_HOOKS` file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Integration {
+    /// Who the messages are from.
+    pub name: String,
+    pub token: String,
+    /// Messages accepted per minute, `DEFAULT_RATE_LIMIT` when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u32>,
+}
+
+struct Hook {
+    integration: Integration,
+    // When the messages accepted within the last `RATE_WINDOW` arrived
+    recent: VecDeque<Instant>,
+}
+
+/// Integrations by token, kept in step with the file they came from.
+#[derive(Default)]
+pub struct Integrations {
+    hooks: Mutex<Vec<Hook>>,
+    file: Option<PathBuf>,
+}
+
+impl Integrations {
+    pub fn new(integrations: Vec<Integration>, file: Option<PathBuf>) -> Self {
+        let hooks = integrations
+            .into_iter()
+            .map(|integration| Hook {
+                integration,
+                recent: VecDeque::new(),
+            })
+            .collect();
+        Self {
+            hooks: Mutex::new(hooks),
+            file,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Integration> {
+        let hooks = self.hooks.lock().unwrap();
+        hooks.iter().map(|hook| hook.integration.clone()).collect()
+    }
+
+    /// Adds an integration named `name` with a new token, `None` when the
+    /// name is taken.
+    pub fn add(&self, name: &str, rate_limit: Option<u32>) -> Option<Integration> {
+        let mut hooks = self.hooks.lock().unwrap();
+        if hooks.iter().any(|hook| hook.integration.name == name) {
+            return None;
+        }
+        let integration = Integration {
+            name: name.to_string(),
+            token: new_token(),
+            rate_limit,
+        };
+        hooks.push(Hook {
+            integration: integration.clone(),
+            recent: VecDeque::new(),
+        });
+        self.save(&hooks);
+        Some(integration)
+    }
+
+    /// Revokes the token of the integration named `name`, returning false
+    /// when there is none.
+    pub fn revoke(&self, name: &str) -> bool {
+        let mut hooks = self.hooks.lock().unwrap();
+        let before = hooks.len();
+        hooks.retain(|hook| hook.integration.name != name);
+        if hooks.len() == before {
+            return false;
+        }
+        self.save(&hooks);
+        true
+    }
+
+    // Checks the token and its rate limit, returning who to post as or the
+    // response to give instead
+    fn admit(&self, token: &str) -> Result<String, Response> {
+        let mut hooks = self.hooks.lock().unwrap();
+        let Some(hook) = hooks.iter_mut().find(|hook| hook.integration.token == token) else {
+            return Err(Response::text(404, "no_service"));
+        };
+
+        let now = Instant::now();
+        while hook
+            .recent
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= RATE_WINDOW)
+        {
+            hook.recent.pop_front();
+        }
+        let limit = hook.integration.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT);
+        if hook.recent.len() >= limit as usize {
+            let oldest = hook.recent.front().copied().unwrap_or(now);
+            let wait = RATE_WINDOW - now.duration_since(oldest);
+            return Err(Response::text(429, "rate_limited")
+                .header("Retry-After", (wait.as_secs() + 1).to_string()));
+        }
+        hook.recent.push_back(now);
+        Ok(hook.integration.name.clone())
+    }
+
+    // Writes the integrations back to their file so revoked tokens stay
+    // revoked across restarts
+    fn save(&self, hooks: &[Hook]) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let integrations: Vec<&Integration> = hooks.iter().map(|hook| &hook.integration).collect();
+        let json = serde_json::to_string_pretty(&integrations).unwrap();
+        if let Err(e) = std::fs::write(file, json) {
+            error!("Cannot save incoming webhooks to {}: {}", file.display(), e);
+        }
+    }
+}
+
+/// Integrations in the JSON file named by `CHAT_INCOMING_HOOKS`, such as
+/// `[{"name": "ci", "token": "...", "rate_limit": 10}]`, and the file itself.
+/// The file is created when the first integration is added.
+pub fn from_env() -> (Vec<Integration>, Option<PathBuf>) {
+    let Ok(path) = std::env::var("CHAT_INCOMING_HOOKS") else {
+        return (Vec::new(), None);
+    };
+    let integrations: Vec<Integration> = match std::fs::read_to_string(&path) {
+        Ok(file) => serde_json::from_str(&file).unwrap_or_else(|e| {
+            panic!("CHAT_INCOMING_HOOKS file {} is not a list of hooks: {}", path, e)
+        }),
+        Err(_) => Vec::new(),
+    };
+    if let Some(hook) = integrations.iter().find(|hook| hook.rate_limit == Some(0)) {
+        panic!(
+            "CHAT_INCOMING_HOOKS file {} gives {} a rate limit of 0",
+            path, hook.name
+        );
+    }
+    (integrations, Some(PathBuf::from(path)))
+}
+
+// 32 hex digits from the OS's random source
+fn new_token() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("no random source");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// What Slack-style payloads may carry; only the fields shown in the chat
+#[derive(Debug, Deserialize)]
+struct Payload {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Attachment {
+    pretext: Option<String>,
+    title: Option<String>,
+    title_link: Option<String>,
+    text: Option<String>,
+    fallback: Option<String>,
+}
+
+/// Handles a POST to `/hooks/<token>`, broadcasting its text as a message
+/// from the token's integration.
+pub fn receive(state: &ServerState, token: &str, request: &Request) -> Response {
+    let name = match state.integrations.admit(token) {
+        Ok(name) => name,
+        Err(response) => return response,
+    };
+    let Some(text) = payload_text(request) else {
+        return Response::text(400, "invalid_payload");
+    };
+    if text.trim().is_empty() {
+        return Response::text(400, "no_text");
+    }
+
+    let message = ChatMessage::text(state, &name, text);
+    post_message(state, message);
+    info!("Integration {} posted a message", name);
+    Response::text(200, "ok")
+}
+
+// The text of a JSON or form-encoded payload, `None` when it cannot be read
+fn payload_text(request: &Request) -> Option<String> {
+    let form = request
+        .header("content-type")
+        .is_some_and(|kind| kind.starts_with("application/x-www-form-urlencoded"));
+    let json = if form {
+        let body = std::str::from_utf8(&request.body).ok()?;
+        body.split('&')
+            .filter_map(|field| field.split_once('='))
+            .find(|(name, _)| *name == "payload")
+            .map(|(_, value)| percent_decode(value))??
+    } else {
+        String::from_utf8(request.body.clone()).ok()?
+    };
+    let payload: Payload = serde_json::from_str(&json).ok()?;
+
+    let mut lines = vec![payload.text];
+    for attachment in payload.attachments {
+        lines.extend(attachment.pretext);
+        match (attachment.title, attachment.title_link) {
+            (Some(title), Some(link)) => lines.push(format!("{} ({})", title, link)),
+            (Some(title), None) => lines.push(title),
+            _ => {}
+        }
+        lines.extend(attachment.text.or(attachment.fallback));
+    }
+    lines.retain(|line| !line.is_empty());
+    Some(unslack(&lines.join("\n")))
+}
+
+// Decodes a form value: `+` is a space and `%XX` a byte
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Turns Slack's markup into plain text: `<url|label>` becomes
+/// `label (url)`, `<!here>` becomes `@here`, and `&lt;`, `&gt;` and `&amp;`
+/// are unescaped.
+pub fn unslack(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>').map(|close| open + close) else {
+            break;
+        };
+        out.push_str(&rest[..open]);
+        let inner = &rest[open + 1..close];
+        let (target, label) = match inner.split_once('|') {
+            Some((target, label)) => (target, Some(label)),
+            None => (inner, None),
+        };
+        if let Some(special) = target.strip_prefix('!').or_else(|| target.strip_prefix('@')) {
+            out.push('@');
+            out.push_str(label.unwrap_or(special));
+        } else {
+            match label {
+                Some(label) => out.push_str(&format!("{} ({})", label, target)),
+                None => out.push_str(target),
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    out.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spells_out_slack_markup() {
+        assert_eq!(
+            unslack("<!here> build <https://ci/12|#12> passed &lt;3 <https://ci>"),
+            "@here build #12 (https://ci/12) passed <3 https://ci"
+        );
+        assert_eq!(unslack("a < b"), "a < b");
+    }
+
+    #[test]
+    fn decodes_form_values() {
+        assert_eq!(
+            percent_decode("%7B%22text%22%3A+%22hi%22%7D").as_deref(),
+            Some(r#"{"text": "hi"}"#)
+        );
+        assert_eq!(percent_decode("%7"), None);
+    }
+
+    #[test]
+    fn limits_each_token() {
+        let integrations = Integrations::new(
+            vec![Integration {
+                name: "ci".to_string(),
+                token: "t0k3n".to_string(),
+                rate_limit: Some(2),
+            }],
+            None,
+        );
+        assert_eq!(integrations.admit("t0k3n").ok().as_deref(), Some("ci"));
+        assert!(integrations.admit("t0k3n").is_ok());
+        assert_eq!(integrations.admit("t0k3n").unwrap_err().status, 429);
+        assert_eq!(integrations.admit("other").unwrap_err().status, 404);
+
+        assert!(integrations.revoke("ci"));
+        assert_eq!(integrations.admit("t0k3n").unwrap_err().status, 404);
+        assert!(!integrations.revoke("ci"));
+    }
+
+    #[test]
+    fn a_limit_of_zero_refuses_without_panicking() {
+        let integrations = Integrations::new(
+            vec![
+                Integration {
+                    name: "muted".to_string(),
+                    token: "z3r0".to_string(),
+                    rate_limit: Some(0),
+                },
+                Integration {
+                    name: "ci".to_string(),
+                    token: "t0k3n".to_string(),
+                    rate_limit: None,
+                },
+            ],
+            None,
+        );
+        assert_eq!(integrations.admit("z3r0").unwrap_err().status, 429);
+        assert_eq!(integrations.admit("t0k3n").ok().as_deref(), Some("ci"));
+    }
+}
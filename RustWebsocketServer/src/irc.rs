@@ -0,0 +1,588 @@
+//! IRC gateway: IRC clients connect to the optional IRC port and join the
+//! chat as `#chat`. Their users go into the same peer map as WebSocket users
+//! and their messages through the same broadcast, so both kinds of clients
+//! see each other in the user list and talk in one room.
+//!
+//! Each connection registers a peer whose frames are turned into IRC lines:
+//! messages become PRIVMSG, notices NOTICE (private messages PRIVMSG to the
+//! user), and changes to the user list JOIN and QUIT. NICK, USER, JOIN,
+//! PART, PRIVMSG, NAMES, PING, PONG and QUIT are understood, and PASS
+//! carries the moderator secret; anything else gets `421 Unknown command`.
+
+use std::net::SocketAddr;
+
+use log::{error, info};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::commands::{self, CommandContext};
+use crate::{
+    backplane, connect_user, disconnect_user, post_message, presence, send_to_user, typing,
+    ChatMessage, MessageType, ServerState, SharedState, UserId, WebSocketMessage,
+};
+
+/// Name the gateway uses as the source of its own replies.
+const SERVER_NAME: &str = "chat";
+
+/// The one channel, standing for the whole chat.
+const CHANNEL: &str = "#chat";
+
+/// Longest line accepted from a client, in bytes. RFC 1459 allows 512;
+/// IRCv3 tags can make lines longer.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Most bytes of message text sent in one PRIVMSG, leaving room for the
+/// prefix and command within the 512 bytes of a line.
+const MAX_TEXT: usize = 400;
+
+/// A line from a client, split into its command and parameters. Tags and
+/// the prefix are dropped.
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl Line {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ')?.1;
+        }
+        if rest.starts_with(':') {
+            rest = rest.split_once(' ')?.1;
+        }
+
+        let (rest, trailing) = match rest.split_once(" :") {
+            Some((rest, trailing)) => (rest, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut words = rest.split(' ').filter(|word| !word.is_empty());
+        let command = words.next()?.to_ascii_uppercase();
+        let mut params: Vec<String> = words.map(str::to_string).collect();
+        if let Some(trailing) = trailing {
+            params.push(trailing.to_string());
+        }
+        Some(Self { command, params })
+    }
+}
+
+/// Accepts IRC connections on `addr` until the listener fails.
+pub async fn serve(state: SharedState, addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind IRC gateway to {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("IRC gateway listening on: {}", addr);
+
+    while let Ok((stream, addr)) = listener.accept().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            handle_connection(state, stream, addr).await;
+        });
+    }
+}
+
+/// The name a chat user goes by on IRC, where spaces and the characters
+/// that delimit a prefix cannot appear.
+pub fn irc_nick(user: &str) -> String {
+    user.chars()
+        .map(|c| match c {
+            c if c.is_whitespace() => '_',
+            ':' | '!' | '@' | ',' | '*' | '?' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn valid_nick(nick: &str) -> bool {
+    !nick.is_empty()
+        && nick.len() <= 30
+        && !nick.starts_with(['#', '&', '$', ':'])
+        && irc_nick(nick) == nick
+}
+
+// One IRC connection and what it knows about the chat
+struct Session<'a> {
+    state: &'a ServerState,
+    writer: OwnedWriteHalf,
+    tx: mpsc::UnboundedSender<Message>,
+    // Registered name, empty until NICK and USER have both arrived
+    user_id: UserId,
+    nick: Option<String>,
+    got_user: bool,
+    // Moderator secret given with PASS before registering
+    password: Option<String>,
+    joined: bool,
+    // Users last listed to the client, to turn new lists into JOIN and QUIT
+    users: Vec<UserId>,
+}
+
+async fn handle_connection(state: SharedState, stream: TcpStream, addr: SocketAddr) {
+    info!("IRC connection from: {}", addr);
+    let (reader, writer) = stream.into_split();
+    let (tx, mut frames) = mpsc::unbounded_channel();
+
+    // Lines are read in a task of their own so reads are never cut short
+    let (line_tx, mut lines) = mpsc::unbounded_channel();
+    let read_task = tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let mut line = Vec::new();
+            match (&mut reader).take(MAX_LINE).read_until(b'\n', &mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&line).into_owned();
+                    if line_tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let mut session = Session {
+        state: &state,
+        writer,
+        tx,
+        user_id: UserId::new(),
+        nick: None,
+        got_user: false,
+        password: None,
+        joined: false,
+        users: Vec::new(),
+    };
+    loop {
+        let result = tokio::select! {
+            line = lines.recv() => match line {
+                Some(line) => session.handle_line(&line).await,
+                None => break,
+            },
+            Some(frame) = frames.recv() => session.handle_frame(frame).await,
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                error!("Error writing to IRC client {}: {}", addr, e);
+                break;
+            }
+        }
+    }
+
+    read_task.abort();
+    if !session.user_id.is_empty() {
+        disconnect_user(&state, &session.user_id);
+    }
+    info!("IRC connection closed for: {}", addr);
+}
+
+impl Session<'_> {
+    // Handles a line from the client, returning false once it quits
+    async fn handle_line(&mut self, line: &str) -> std::io::Result<bool> {
+        let Some(Line { command, params }) = Line::parse(line) else {
+            return Ok(true);
+        };
+
+        // Any line, including pongs, proves the connection is alive
+        if let Some(peer) = self.state.peers.lock().unwrap().get_mut(&self.user_id) {
+            peer.1 = true;
+        }
+
+        let registered = !self.user_id.is_empty();
+        match (command.as_str(), params.as_slice()) {
+            ("QUIT", _) => {
+                self.send("ERROR :Closing link").await?;
+                return Ok(false);
+            }
+            ("PING", [token, ..]) => {
+                self.send(&format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, token))
+                    .await?
+            }
+            ("PONG", _) => {}
+            ("CAP", [sub, ..]) if sub.eq_ignore_ascii_case("LS") => {
+                self.send(&format!(":{} CAP * LS :", SERVER_NAME)).await?
+            }
+            ("PASS", [password, ..]) if !registered => self.password = Some(password.clone()),
+            ("CAP", _) | ("PASS", _) => {}
+            ("NICK", [nick, ..]) => self.nick(nick).await?,
+            ("USER", [_, _, _, _]) if !registered => {
+                self.got_user = true;
+                self.try_register().await?;
+            }
+            (_, _) if !registered => self.reply("451", ":You have not registered").await?,
+            ("USER", _) => self.reply("462", ":You may not reregister").await?,
+            ("JOIN", [channels, ..]) => {
+                for channel in channels.split(',') {
+                    if channel.eq_ignore_ascii_case(CHANNEL) {
+                        self.join().await?;
+                    } else {
+                        self.reply("403", &format!("{} :No such channel", channel))
+                            .await?;
+                    }
+                }
+            }
+            ("PART", [channels, ..]) => {
+                if channels.split(',').any(|c| c.eq_ignore_ascii_case(CHANNEL)) && self.joined {
+                    self.joined = false;
+                    let line = format!(":{} PART {}", self.prefix(&self.user_id), CHANNEL);
+                    self.send(&line).await?;
+                }
+            }
+            ("NAMES", _) => self.names().await?,
+            ("PRIVMSG", [target, text]) => self.privmsg(target, text).await?,
+            ("PRIVMSG", _) => self.reply("412", ":No text to send").await?,
+            ("NICK" | "JOIN" | "PART", _) => {
+                let text = format!("{} :Not enough parameters", command);
+                self.reply("461", &text).await?
+            }
+            _ => {
+                let text = format!("{} :Unknown command", command);
+                self.reply("421", &text).await?
+            }
+        }
+        Ok(true)
+    }
+
+    async fn nick(&mut self, nick: &str) -> std::io::Result<()> {
+        if !valid_nick(nick) {
+            let text = format!("{} :Erroneous nickname", nick);
+            return self.reply("432", &text).await;
+        }
+        if nick == self.user_id {
+            return Ok(());
+        }
+        if is_taken(self.state, nick) {
+            let text = format!("{} :Nickname is already in use", nick);
+            return self.reply("433", &text).await;
+        }
+        if self.user_id.is_empty() {
+            self.nick = Some(nick.to_string());
+            return self.try_register().await;
+        }
+
+        // Renaming goes through /nick like it does for WebSocket users
+        let old = self.user_id.clone();
+        self.run_command(&format!("/nick {}", nick));
+        if self.user_id != old {
+            let line = format!(":{} NICK :{}", self.prefix(&old), self.user_id);
+            self.send(&line).await?;
+        }
+        Ok(())
+    }
+
+    // Joins the chat once both NICK and USER were given
+    async fn try_register(&mut self) -> std::io::Result<()> {
+        let Some(nick) = self.nick.clone().filter(|_| self.got_user) else {
+            return Ok(());
+        };
+        // Someone may have taken the name since NICK
+        if is_taken(self.state, &nick) {
+            self.nick = None;
+            let text = format!("{} :Nickname is already in use", nick);
+            return self.reply("433", &text).await;
+        }
+
+        if let Err(e) = connect_user(self.state, &nick, self.password.as_deref(), &self.tx) {
+            self.nick = None;
+            return self.reply("464", &format!(":{}", e)).await;
+        }
+        self.user_id = nick;
+        info!("{} joined over IRC", self.user_id);
+
+        let welcome = format!(":Welcome to the chat, {}", self.user_id);
+        self.reply("001", &welcome).await?;
+        let host = format!(":Your host is {}, a gateway to the chat", SERVER_NAME);
+        self.reply("002", &host).await?;
+        self.reply("003", ":This server was created for chatting").await?;
+        self.reply("004", &format!("{} 1.0 o o", SERVER_NAME)).await?;
+        self.reply("422", ":MOTD File is missing").await?;
+
+        // There is only one room, so everyone is in it from the start
+        self.join().await
+    }
+
+    async fn join(&mut self) -> std::io::Result<()> {
+        if self.joined {
+            return Ok(());
+        }
+        self.joined = true;
+        let line = format!(":{} JOIN {}", self.prefix(&self.user_id), CHANNEL);
+        self.send(&line).await?;
+        let topic = self.state.topic.lock().unwrap().clone();
+        match topic {
+            Some(topic) => self.reply("332", &format!("{} :{}", CHANNEL, topic)).await?,
+            None => self.reply("331", &format!("{} :No topic is set", CHANNEL)).await?,
+        }
+        self.names().await
+    }
+
+    async fn names(&mut self) -> std::io::Result<()> {
+        self.users = chat_users(self.state);
+        let nicks: Vec<String> = self.users.iter().map(|user| irc_nick(user)).collect();
+        // Several lines keep each one short
+        for chunk in nicks.chunks(20) {
+            let text = format!("= {} :{}", CHANNEL, chunk.join(" "));
+            self.reply("353", &text).await?;
+        }
+        self.reply("366", &format!("{} :End of /NAMES list", CHANNEL))
+            .await
+    }
+
+    async fn privmsg(&mut self, target: &str, text: &str) -> std::io::Result<()> {
+        // CTCP ACTION is what clients send for /me
+        if let Some(action) = text
+            .strip_prefix("\u{1}ACTION ")
+            .map(|action| action.trim_end_matches('\u{1}'))
+        {
+            self.run_command(&format!("/me {}", action));
+            return Ok(());
+        }
+        // Other CTCP requests, such as VERSION, are not answered
+        if text.starts_with('\u{1}') {
+            return Ok(());
+        }
+
+        if target.eq_ignore_ascii_case(CHANNEL) {
+            // Slash commands work as they do from YewChat
+            if self.run_command(text) {
+                return Ok(());
+            }
+            typing::set_typing(self.state, &self.user_id, false);
+            presence::touch(self.state, &self.user_id);
+            // A leading "//" sends a literal slash
+            let text = if text.starts_with("//") { &text[1..] } else { text };
+            let message = ChatMessage::text(self.state, &self.user_id, text.to_string());
+            post_message(self.state, message);
+            return Ok(());
+        }
+
+        // Anything else is a private message, like /msg
+        let user = chat_users(self.state).into_iter().find(|user| irc_nick(user) == target);
+        let notice = commands::notice(&format!("[{} -> you] {}", self.user_id, text));
+        match user {
+            Some(user) if send_to_user(self.state, &user, &notice) => Ok(()),
+            _ => {
+                let text = format!("{} :No such nick/channel", target);
+                self.reply("401", &text).await
+            }
+        }
+    }
+
+    // Runs `text` as a chat command, returning false when it is not one.
+    // What the command answers arrives as frames like for anyone else.
+    fn run_command(&mut self, text: &str) -> bool {
+        let mut ctx = CommandContext {
+            state: self.state,
+            user_id: &mut self.user_id,
+            tx: &self.tx,
+        };
+        commands::dispatch(&mut ctx, text)
+    }
+
+    // Turns a frame meant for a WebSocket client into IRC lines
+    async fn handle_frame(&mut self, frame: Message) -> std::io::Result<bool> {
+        let text = match frame {
+            Message::Text(text) => text,
+            Message::Ping(_) => {
+                self.send(&format!("PING :{}", SERVER_NAME)).await?;
+                return Ok(true);
+            }
+            Message::Close(_) => return Ok(false),
+            _ => return Ok(true),
+        };
+        let Ok(frame) = serde_json::from_str::<WebSocketMessage>(&text) else {
+            return Ok(true);
+        };
+
+        let data = frame.data.unwrap_or_default();
+        match frame.message_type {
+            MessageType::Message if self.joined => {
+                let Ok(message) = serde_json::from_str::<ChatMessage>(&data) else {
+                    return Ok(true);
+                };
+                // IRC clients show what they send themselves
+                if message.from != self.user_id {
+                    self.relay_message(&message).await?;
+                }
+            }
+            MessageType::Notice => self.relay_notice(&data).await?,
+            MessageType::Topic if self.joined => {
+                let line = format!(":{} TOPIC {} :{}", SERVER_NAME, CHANNEL, data);
+                self.send(&line).await?;
+            }
+            MessageType::Users if self.joined => {
+                let users = frame.data_array.unwrap_or_default();
+                self.relay_users(users).await?;
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    async fn relay_message(&mut self, message: &ChatMessage) -> std::io::Result<()> {
+        if message.deleted {
+            return Ok(());
+        }
+        let mut text = message.message.clone();
+        if let Some(reply) = &message.reply_to {
+            text = format!("{}: {}", irc_nick(&reply.from), text);
+        }
+        if let Some(attachment) = &message.attachment {
+            text = format!("{} [file: {}]", text, attachment.name).trim().to_string();
+        }
+        let prefix = self.prefix(&message.from);
+        for chunk in text.lines().flat_map(|line| split_text(line, MAX_TEXT)) {
+            self.send(&format!(":{} PRIVMSG {} :{}", prefix, CHANNEL, chunk))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn relay_notice(&mut self, notice: &str) -> std::io::Result<()> {
+        // Private messages look like "[ann -> you] text"
+        let private = notice
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(" -> you] "));
+        if let Some((from, text)) = private {
+            let (prefix, nick) = (self.prefix(from), irc_nick(&self.user_id));
+            for chunk in split_text(text, MAX_TEXT) {
+                self.send(&format!(":{} PRIVMSG {} :{}", prefix, nick, chunk))
+                    .await?;
+            }
+            return Ok(());
+        }
+
+        let nick = if self.user_id.is_empty() {
+            "*".to_string()
+        } else {
+            irc_nick(&self.user_id)
+        };
+        for line in notice.lines() {
+            for chunk in split_text(line, MAX_TEXT) {
+                self.send(&format!(":{} NOTICE {} :{}", SERVER_NAME, nick, chunk))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn relay_users(&mut self, users: Vec<UserId>) -> std::io::Result<()> {
+        let previous = std::mem::replace(&mut self.users, users);
+        let joined: Vec<UserId> = self
+            .users
+            .iter()
+            .filter(|user| !previous.contains(user) && **user != self.user_id)
+            .cloned()
+            .collect();
+        let left: Vec<UserId> = previous
+            .iter()
+            .filter(|user| !self.users.contains(user) && **user != self.user_id)
+            .cloned()
+            .collect();
+        for user in joined {
+            let line = format!(":{} JOIN {}", self.prefix(&user), CHANNEL);
+            self.send(&line).await?;
+        }
+        for user in left {
+            let line = format!(":{} QUIT :Left the chat", self.prefix(&user));
+            self.send(&line).await?;
+        }
+        Ok(())
+    }
+
+    // `nick!user@host` for a chat user
+    fn prefix(&self, user: &str) -> String {
+        let nick = irc_nick(user);
+        format!("{}!{}@{}", nick, nick, SERVER_NAME)
+    }
+
+    // Sends a numeric reply addressed to the client
+    async fn reply(&mut self, numeric: &str, text: &str) -> std::io::Result<()> {
+        let nick = match (&self.user_id, &self.nick) {
+            (user, _) if !user.is_empty() => irc_nick(user),
+            (_, Some(nick)) => nick.clone(),
+            _ => "*".to_string(),
+        };
+        self.send(&format!(":{} {} {} {}", SERVER_NAME, numeric, nick, text))
+            .await
+    }
+
+    async fn send(&mut self, line: &str) -> std::io::Result<()> {
+        // A stray line break would let the rest pass for another command
+        let line = line.replace(['\r', '\n'], " ");
+        self.writer.write_all(format!("{}\r\n", line).as_bytes()).await
+    }
+}
+
+// Everyone connected to any node, as in the user list
+fn chat_users(state: &ServerState) -> Vec<UserId> {
+    let mut users: Vec<UserId> = state.peers.lock().unwrap().keys().cloned().collect();
+    users.extend(backplane::remote_users(state));
+    users.sort();
+    users.dedup();
+    users
+}
+
+fn is_taken(state: &ServerState, nick: &str) -> bool {
+    chat_users(state).iter().any(|user| user == nick || irc_nick(user) == nick)
+}
+
+// Splits `text` into pieces of at most `max` bytes, on character boundaries
+fn split_text(text: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(
+            Line::parse("@time=x :ann!a@h privmsg #chat :hello there\r\n"),
+            Some(Line {
+                command: "PRIVMSG".to_string(),
+                params: vec!["#chat".to_string(), "hello there".to_string()],
+            })
+        );
+        assert_eq!(
+            Line::parse("USER ann 0 * :Ann A").unwrap().params,
+            ["ann", "0", "*", "Ann A"]
+        );
+        assert_eq!(Line::parse(""), None);
+    }
+
+    #[test]
+    fn maps_names_to_nicks() {
+        assert_eq!(irc_nick("Ann Lee"), "Ann_Lee");
+        assert!(valid_nick("ann"));
+        assert!(!valid_nick("#ann"));
+        assert!(!valid_nick("ann lee"));
+    }
+
+    #[test]
+    fn splits_long_text_on_char_boundaries() {
+        assert_eq!(split_text("ééé", 3), ["é", "é", "é"]);
+        assert_eq!(split_text("", 3), [""]);
+    }
+}
@@ -0,0 +1,848 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::{accept_hdr_async, tungstenite::protocol::Message};
+
+mod attachments;
+mod backplane;
+mod commands;
+mod content;
+mod deflate;
+mod edits;
+mod history;
+mod http;
+mod integrations;
+mod irc;
+mod mentions;
+mod presence;
+mod reactions;
+mod redis;
+mod search;
+mod receipts;
+mod threads;
+mod typing;
+mod webhooks;
+
+use attachments::Attachment;
+use chat_protocol::{
+    Encoding, MessageData, MessageType, RegisterData, ReplyData, WebSocketMessage,
+};
+pub use backplane::{Backplane, Envelope, Event, InProcess};
+use backplane::RemoteNode;
+use commands::CommandContext;
+use content::ContentKind;
+pub use deflate::DeflateConfig;
+use deflate::{DeflateStream, Extension};
+use edits::MessageVersion;
+use history::History;
+pub use integrations::Integration;
+use integrations::Integrations;
+use mentions::Mention;
+use presence::Presence;
+use receipts::Receipts;
+pub use webhooks::{Webhook, WebhookEvent};
+use webhooks::Webhooks;
+
+pub type UserId = String;
+type Tx = mpsc::UnboundedSender<Message>;
+type PeerMap = Arc<Mutex<HashMap<UserId, (Tx, bool)>>>;
+
+// State shared by every connection
+struct ServerState {
+    peers: PeerMap,
+    topic: Mutex<Option<String>>,
+    typing: Mutex<HashMap<UserId, Instant>>,
+    history: Mutex<History>,
+    receipts: Mutex<Receipts>,
+    presence: Mutex<HashMap<UserId, Presence>>,
+    // Users allowed to delete anyone's messages, from CHAT_MODERATORS
+    moderators: HashSet<UserId>,
+    // What a client must send to register under a moderator's name
+    moderator_secret: Option<String>,
+    // Metadata of uploaded files by content hash
+    attachments: Mutex<HashMap<String, Attachment>>,
+    // Uploads stored but not sent in a message yet, counted by content hash
+    unsent: Mutex<HashMap<String, usize>>,
+    // permessage-deflate settings offered to clients
+    deflate: DeflateConfig,
+    // Index of this node among those sharing the backplane
+    node: u64,
+    backplane: Option<Arc<dyn Backplane>>,
+    // Other nodes on the backplane by index
+    remote: Mutex<HashMap<u64, RemoteNode>>,
+    // Outgoing webhooks and their recent deliveries
+    webhooks: Webhooks,
+    // Tokens allowed to post through incoming webhooks
+    integrations: Integrations,
+}
+
+impl ServerState {
+    fn new(node: u64, backplane: Option<Arc<dyn Backplane>>) -> Self {
+        // Nodes sharing a backplane take turns handing out message IDs
+        let history = match backplane {
+            Some(_) => History::for_node(node, backplane::MAX_NODES),
+            None => History::new(),
+        };
+        Self {
+            peers: PeerMap::new(Mutex::new(HashMap::new())),
+            topic: Mutex::new(None),
+            typing: Mutex::new(HashMap::new()),
+            history: Mutex::new(history),
+            receipts: Mutex::new(Receipts::default()),
+            presence: Mutex::new(HashMap::new()),
+            moderators: HashSet::new(),
+            moderator_secret: None,
+            attachments: Mutex::new(HashMap::new()),
+            unsent: Mutex::new(HashMap::new()),
+            deflate: DeflateConfig::default(),
+            node,
+            backplane,
+            remote: Mutex::new(HashMap::new()),
+            webhooks: Webhooks::default(),
+            integrations: Integrations::default(),
+        }
+    }
+}
+
+type SharedState = Arc<ServerState>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ChatMessage {
+    id: u64,
+    from: String,
+    message: String,
+    #[serde(default)]
+    kind: ContentKind,
+    time: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<ReplyData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edited_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    edits: Vec<MessageVersion>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    deleted: bool,
+    // Users behind each emoji reaction, in the order they reacted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    reactions: BTreeMap<String, Vec<UserId>>,
+    // Root message of the thread this message was posted in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_id: Option<u64>,
+    // Number of replies, only ever set on thread roots
+    #[serde(default, skip_serializing_if = "is_zero")]
+    reply_count: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mentions: Vec<Mention>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attachment: Option<Attachment>,
+}
+
+impl ChatMessage {
+    // A plain message from `from`, mentions included, as posted from outside
+    // a WebSocket; the ID is assigned by the history
+    fn text(state: &ServerState, from: &str, text: String) -> Self {
+        Self {
+            id: 0,
+            from: from.to_string(),
+            kind: content::resolve_kind(None, &text, false),
+            mentions: mentions::parse_mentions(&text, &mentions::known_users(state)),
+            message: text,
+            time: now_millis(),
+            reply_to: None,
+            edited_at: None,
+            edits: Vec::new(),
+            deleted: false,
+            reactions: BTreeMap::new(),
+            thread_id: None,
+            reply_count: 0,
+            attachment: None,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+async fn handle_connection(
+    state: SharedState,
+    raw_stream: TcpStream,
+    addr: SocketAddr,
+) {
+    info!("Incoming connection from: {}", addr);
+
+    // Agree on permessage-deflate and the encoding while answering the
+    // handshake, clients offering neither get plain JSON frames
+    let extension = Extension::default();
+    let encoding = OnceLock::new();
+    // The error type is tungstenite's to pick
+    #[allow(clippy::result_large_err)]
+    let negotiate = {
+        let extension = extension.clone();
+        let config = state.deflate;
+        let encoding = &encoding;
+        move |request: &Request, mut response: Response| {
+            let offers = request
+                .headers()
+                .get_all("Sec-WebSocket-Extensions")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(",");
+            let agreed = deflate::negotiate(&config, &offers);
+            if let Some((_, header)) = &agreed {
+                response
+                    .headers_mut()
+                    .insert("Sec-WebSocket-Extensions", header.parse().unwrap());
+            }
+            let _ = extension.set(agreed.map(|(negotiated, _)| negotiated));
+
+            let protocols = request
+                .headers()
+                .get_all("Sec-WebSocket-Protocol")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(",");
+            if let Some(picked) = Encoding::negotiate(&protocols) {
+                response
+                    .headers_mut()
+                    .insert("Sec-WebSocket-Protocol", picked.protocol().parse().unwrap());
+                let _ = encoding.set(picked);
+            }
+            Ok(response)
+        }
+    };
+
+    let stream = DeflateStream::new(raw_stream, extension.clone());
+    let ws_stream = match accept_hdr_async(stream, negotiate).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            error!("Error during WebSocket handshake: {}", e);
+            return;
+        }
+    };
+
+    info!("WebSocket connection established with: {}", addr);
+    if let Some(Some(negotiated)) = extension.get() {
+        info!(
+            "Compressing frames to {} with a {} bit window",
+            addr, negotiated.server_max_window_bits
+        );
+    }
+    let encoding = encoding.get().copied().unwrap_or_default();
+    if encoding != Encoding::Json {
+        info!("Speaking {} with {}", encoding.protocol(), addr);
+    }
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (mut outgoing, mut incoming) = ws_stream.split();
+
+    // Forward messages received on the mpsc channel to the WebSocket
+    let forward_task = tokio::spawn(async move {
+        let mut rx = rx;
+        while let Some(message) = rx.recv().await {
+            if let Err(e) = outgoing.send(encode_outgoing(encoding, message)).await {
+                error!("Error sending message: {}", e);
+                break;
+            }
+        }
+    });
+
+    // Process incoming WebSocket messages
+    let mut user_id = String::new();
+    let mut upload = None;
+    // Files this connection uploaded and has not sent in a message yet
+    let mut unsent: Vec<String> = Vec::new();
+    let peer_map = &state.peers;
+
+    while let Some(result) = incoming.next().await {
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Error receiving message: {}", e);
+                break;
+            }
+        };
+
+        // Any frame, including pongs, proves the connection is alive
+        if let Some(peer) = peer_map.lock().unwrap().get_mut(&user_id) {
+            peer.1 = true;
+        }
+
+        // Binary frames are file chunks while an upload is going on, and
+        // otherwise protocol frames if the client picked a binary encoding
+        let msg = match msg {
+            Message::Binary(chunk) if upload.is_some() || encoding == Encoding::Json => {
+                if !user_id.is_empty() {
+                    if let Some(id) = attachments::receive_chunk(&state, &tx, &mut upload, &chunk).await {
+                        unsent.push(id);
+                    }
+                }
+                continue;
+            }
+            Message::Binary(bytes) => match encoding.decode(&bytes) {
+                Some(text) => Message::Text(text),
+                None => {
+                    error!("Undecodable {} frame from {}", encoding.protocol(), addr);
+                    continue;
+                }
+            },
+            msg => msg,
+        };
+
+        if let Message::Text(text) = msg {
+            if let Ok(ws_msg) = serde_json::from_str::<WebSocketMessage>(&text) {
+                match ws_msg.message_type {
+                    MessageType::Register => {
+                        // Renaming goes through /nick, which keeps the peer map in step
+                        if !user_id.is_empty() {
+                            let refusal = format!("You are already registered as {}", user_id);
+                            send_frame(&tx, &commands::notice(&refusal));
+                            continue;
+                        }
+                        if let Some(data) = ws_msg.data {
+                            let request = RegisterData::parse(&data);
+                            
+                            // Add user to the peer map and tell everyone
+                            let secret = request.secret.as_deref();
+                            if let Err(e) = connect_user(&state, &request.name, secret, &tx) {
+                                send_frame(&tx, &commands::notice(&e));
+                                continue;
+                            }
+                            user_id = request.name;
+
+                            // Let the new client know the commands, read markers and the current topic
+                            send_frame(&tx, &commands::commands_frame());
+                            send_frame(&tx, &receipts::read_markers_frame(&state));
+                            send_frame(&tx, &WebSocketMessage {
+                                message_type: MessageType::Moderators,
+                                data: None,
+                                data_array: Some(state.moderators.iter().cloned().collect()),
+                            });
+                            if let Some(topic) = state.topic.lock().unwrap().clone() {
+                                send_frame(&tx, &WebSocketMessage {
+                                    message_type: MessageType::Topic,
+                                    data: Some(topic),
+                                    data_array: None,
+                                });
+                            }
+                        }
+                    }
+                    MessageType::Message if !user_id.is_empty() => {
+                        if let Some(data) = ws_msg.data {
+                            if let Ok(mut msg_data) = serde_json::from_str::<MessageData>(&data) {
+                                // Slash commands are answered instead of broadcast
+                                let mut ctx = CommandContext {
+                                    state: &state,
+                                    user_id: &mut user_id,
+                                    tx: &tx,
+                                };
+                                if commands::dispatch(&mut ctx, &msg_data.text) {
+                                    continue;
+                                }
+
+                                // Sending a message ends the typing indicator
+                                typing::set_typing(&state, &user_id, false);
+                                presence::touch(&state, &user_id);
+
+                                // A leading "//" sends a literal slash
+                                if msg_data.text.starts_with("//") {
+                                    msg_data.text.remove(0);
+                                }
+                                // Surrounding whitespace is dropped, as it is from edits
+                                msg_data.text = msg_data.text.trim().to_string();
+
+                                // Process the message
+                                let mut reply_data = None;
+                                
+                                // Parse reply data if present, preferring the stored original
+                                if let Some(reply_json) = msg_data.reply_to {
+                                    if let Ok(reply) = serde_json::from_str::<ReplyData>(&reply_json) {
+                                        reply_data = Some(match state.history.lock().unwrap().get(reply.id) {
+                                            Some(original) => ReplyData {
+                                                id: original.id,
+                                                from: original.from.clone(),
+                                                message: original.message.clone(),
+                                                deleted: original.deleted,
+                                            },
+                                            None => reply,
+                                        });
+                                    }
+                                }
+                                
+                                // Replies posted in a thread always point at its root
+                                let thread_id = msg_data.thread_id.and_then(|id| {
+                                    threads::resolve_root(&state.history.lock().unwrap(), id)
+                                });
+                                
+                                let attachment = msg_data.attachment.and_then(|id| {
+                                    state.attachments.lock().unwrap().get(&id).cloned()
+                                });
+                                if msg_data.text.is_empty() && attachment.is_none() {
+                                    continue;
+                                }
+                                
+                                let kind = content::resolve_kind(
+                                    msg_data.kind,
+                                    &msg_data.text,
+                                    attachment.is_some(),
+                                );
+                                
+                                let mentions = mentions::parse_mentions(
+                                    &msg_data.text,
+                                    &mentions::known_users(&state),
+                                );
+                                
+                                let attachment_id = attachment.as_ref().map(|a| a.id.clone());
+
+                                // Create chat message, the ID is assigned by the history
+                                let chat_msg = ChatMessage {
+                                    id: 0,
+                                    from: user_id.clone(),
+                                    message: msg_data.text,
+                                    kind,
+                                    time: now_millis(),
+                                    reply_to: reply_data,
+                                    edited_at: None,
+                                    edits: Vec::new(),
+                                    deleted: false,
+                                    reactions: BTreeMap::new(),
+                                    thread_id,
+                                    reply_count: 0,
+                                    mentions,
+                                    attachment,
+                                };
+                                
+                                // Store and broadcast the message to all clients
+                                post_message(&state, chat_msg);
+
+                                // The file is now kept alive by the message carrying it
+                                let sent = attachment_id
+                                    .and_then(|id| unsent.iter().position(|u| *u == id));
+                                if let Some(index) = sent {
+                                    attachments::forget_unsent(&state, &unsent.swap_remove(index));
+                                }
+                                if let Some(root_id) = thread_id {
+                                    threads::update_reply_count(&state, root_id);
+                                }
+                            }
+                        }
+                    }
+                    MessageType::Typing if !user_id.is_empty() => {
+                        let typing = ws_msg.data.as_deref() == Some("start");
+                        typing::set_typing(&state, &user_id, typing);
+                        presence::touch(&state, &user_id);
+                    }
+                    MessageType::Delivered if !user_id.is_empty() => {
+                        if let Some(id) = ws_msg.data.and_then(|d| d.parse().ok()) {
+                            receipts::mark_delivered(&state, &user_id, id);
+                        }
+                    }
+                    MessageType::Read if !user_id.is_empty() => {
+                        if let Some(id) = ws_msg.data.and_then(|d| d.parse().ok()) {
+                            receipts::mark_read(&state, &user_id, id);
+                        }
+                        presence::touch(&state, &user_id);
+                    }
+                    MessageType::Edit if !user_id.is_empty() => {
+                        if let Some(data) = ws_msg.data {
+                            edits::edit_message(&state, &user_id, &tx, &data);
+                        }
+                        presence::touch(&state, &user_id);
+                    }
+                    MessageType::Delete if !user_id.is_empty() => {
+                        if let Some(id) = ws_msg.data.and_then(|d| d.parse().ok()) {
+                            edits::delete_message(&state, &user_id, &tx, id);
+                        }
+                    }
+                    MessageType::React | MessageType::Unreact if !user_id.is_empty() => {
+                        if let Some(data) = ws_msg.data {
+                            let add = matches!(ws_msg.message_type, MessageType::React);
+                            reactions::set_reaction(&state, &user_id, &tx, &data, add);
+                        }
+                        presence::touch(&state, &user_id);
+                    }
+                    MessageType::Thread if !user_id.is_empty() => {
+                        if let Some(id) = ws_msg.data.and_then(|d| d.parse().ok()) {
+                            threads::send_thread(&state, &tx, id);
+                        }
+                    }
+                    MessageType::Upload if !user_id.is_empty() => {
+                        if let Some(data) = ws_msg.data {
+                            upload = attachments::start_upload(&tx, &data);
+                        }
+                        presence::touch(&state, &user_id);
+                    }
+                    MessageType::History if !user_id.is_empty() => {
+                        history::send_page(&state, &tx, ws_msg.data.as_deref().unwrap_or("{}"));
+                    }
+                    MessageType::Search if !user_id.is_empty() => {
+                        if let Some(data) = ws_msg.data {
+                            search::search(&state, &tx, &data);
+                        }
+                    }
+                    MessageType::Context if !user_id.is_empty() => {
+                        if let Some(id) = ws_msg.data.and_then(|d| d.parse().ok()) {
+                            search::send_context(&state, &tx, id);
+                        }
+                    }
+                    MessageType::Status if !user_id.is_empty() => {
+                        if let Some(data) = ws_msg.data {
+                            presence::set_status(&state, &user_id, &data);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // User disconnected, remove from peer map
+    disconnect_user(&state, &user_id);
+    for id in unsent {
+        attachments::forget_unsent(&state, &id);
+    }
+    
+    // Cancel the forward task when the connection is closed
+    forward_task.abort();
+    info!("Connection closed for: {}", addr);
+}
+
+// Adds a registered user to the peer map and broadcasts the user list and
+// their presence
+fn connect_user(
+    state: &ServerState,
+    user_id: &str,
+    secret: Option<&str>,
+    tx: &Tx,
+) -> Result<(), String> {
+    if user_id.trim().is_empty() {
+        return Err("Pick a name to chat under".to_string());
+    }
+    if reserved_for_moderator(state, user_id, secret) {
+        return Err(format!(
+            "{} is a moderator; sign in with the moderator secret to use that name",
+            user_id
+        ));
+    }
+    {
+        let mut peers = state.peers.lock().unwrap();
+        let remote = backplane::remote_users(state);
+        if peers.contains_key(user_id) || remote.iter().any(|user| user == user_id) {
+            return Err(format!("The name {} is already taken", user_id));
+        }
+        peers.insert(user_id.to_string(), (tx.clone(), true));
+    }
+    broadcast_user_list(state);
+    presence::connect(state, user_id);
+    webhooks::presence_changed(state, WebhookEvent::Join, user_id);
+    Ok(())
+}
+
+// Whether `name` belongs to a moderator and `secret` does not prove the
+// client is them. Moderator names cannot be used at all without a secret
+// configured.
+fn reserved_for_moderator(state: &ServerState, name: &str, secret: Option<&str>) -> bool {
+    state.moderators.contains(name)
+        && (secret.is_none() || secret != state.moderator_secret.as_deref())
+}
+
+// Converts a JSON text frame on its way to a client that picked a binary
+// encoding; other frames go out as they are
+fn encode_outgoing(encoding: Encoding, message: Message) -> Message {
+    match message {
+        Message::Text(json) if encoding != Encoding::Json => match encoding.encode(&json) {
+            Some(bytes) => Message::Binary(bytes),
+            None => Message::Text(json),
+        },
+        message => message,
+    }
+}
+
+// Removes a user whose connection closed, unless the connection check
+// already did, and tells everyone
+fn disconnect_user(state: &ServerState, user_id: &str) {
+    let removed = state.peers.lock().unwrap().remove(user_id).is_some();
+    broadcast_user_list(state);
+    typing::set_typing(state, user_id, false);
+    presence::disconnect(state, user_id);
+    if removed {
+        webhooks::presence_changed(state, WebhookEvent::Leave, user_id);
+    }
+}
+
+// Stores a new chat message, broadcasts it to all clients and tells the
+// webhooks about it
+fn post_message(state: &ServerState, message: ChatMessage) {
+    let message = state.history.lock().unwrap().push(message).clone();
+    let message_json = serde_json::to_string(&WebSocketMessage {
+        message_type: MessageType::Message,
+        data: Some(serde_json::to_string(&message).unwrap()),
+        data_array: None,
+    })
+    .unwrap();
+
+    broadcast_message(state, &message_json);
+    webhooks::message_posted(state, &message);
+}
+
+// Sends a frame to everyone on every node
+fn broadcast_message(state: &ServerState, message: &str) {
+    broadcast_local(&state.peers, message);
+    backplane::publish(
+        state,
+        backplane::Event::Broadcast {
+            frame: message.to_string(),
+        },
+    );
+}
+
+// Sends a frame to the users connected to this node
+fn broadcast_local(peer_map: &PeerMap, message: &str) {
+    let peers = peer_map.lock().unwrap();
+    
+    for (_, (tx, _)) in peers.iter() {
+        if let Err(e) = tx.send(Message::Text(message.to_string())) {
+            error!("Error broadcasting message: {}", e);
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn send_frame(tx: &Tx, frame: &WebSocketMessage) {
+    let json = serde_json::to_string(frame).unwrap();
+    if let Err(e) = tx.send(Message::Text(json)) {
+        error!("Error sending message: {}", e);
+    }
+}
+
+// Sends `frame` to `user_id` on whichever node they are connected to,
+// returning false when they are not connected anywhere
+fn send_to_user(state: &ServerState, user_id: &str, frame: &WebSocketMessage) -> bool {
+    if let Some((tx, _)) = state.peers.lock().unwrap().get(user_id) {
+        send_frame(tx, frame);
+        return true;
+    }
+    if !backplane::remote_users(state).iter().any(|user| user == user_id) {
+        return false;
+    }
+    backplane::publish(
+        state,
+        backplane::Event::Direct {
+            to: user_id.to_string(),
+            frame: serde_json::to_string(frame).unwrap(),
+        },
+    );
+    true
+}
+
+// Tells the other nodes and every local user who is connected
+fn broadcast_user_list(state: &ServerState) {
+    backplane::announce(state);
+    send_user_list(state);
+}
+
+// Sends the users connected to any node to the users on this one
+fn send_user_list(state: &ServerState) {
+    let remote_users = backplane::remote_users(state);
+    let peers = state.peers.lock().unwrap();
+    let mut user_list: Vec<String> = peers.keys().cloned().collect();
+    user_list.extend(remote_users.into_iter().filter(|user| !peers.contains_key(user)));
+    
+    let users_message = WebSocketMessage {
+        message_type: MessageType::Users,
+        data: None,
+        data_array: Some(user_list),
+    };
+    
+    let json = serde_json::to_string(&users_message).unwrap();
+    
+    for (_, (tx, _)) in peers.iter() {
+        if let Err(e) = tx.send(Message::Text(json.clone())) {
+            error!("Error broadcasting user list: {}", e);
+        }
+    }
+}
+
+async fn check_connections(state: SharedState) {
+    // Timeout for checking connections
+    let interval = Duration::from_secs(5);
+    let mut interval_stream = time::interval(interval);
+    let peer_map = &state.peers;
+    
+    loop {
+        interval_stream.tick().await;
+        let mut peers = peer_map.lock().unwrap();
+        
+        // Check which connections are still alive
+        let peers_to_remove: Vec<String> = peers
+            .iter()
+            .filter(|(_, (_, is_alive))| !*is_alive)
+            .map(|(id, _)| id.clone())
+            .collect();
+        
+        // Remove disconnected peers
+        for id in &peers_to_remove {
+            peers.remove(id);
+        }
+        
+        // Mark all connections as not alive for next check and ping them,
+        // the pong (or any other frame) marks them alive again
+        for (_, (tx, is_alive)) in peers.iter_mut() {
+            *is_alive = false;
+            let _ = tx.send(Message::Ping(Vec::new()));
+        }
+        
+        // Drop the lock before broadcasting
+        drop(peers);
+        
+        // If users changed, broadcast new user list
+        if !peers_to_remove.is_empty() {
+            broadcast_user_list(&state);
+            for id in &peers_to_remove {
+                presence::disconnect(&state, id);
+                webhooks::presence_changed(&state, WebhookEvent::Leave, id);
+            }
+        }
+        
+        backplane::heartbeat(&state);
+    }
+}
+
+/// What a server starts with, apart from the address it listens on.
+pub struct Config {
+    /// Users allowed to delete anyone's messages.
+    pub moderators: HashSet<UserId>,
+    /// Sent by moderators when registering, to prove who they are.
+    pub moderator_secret: Option<String>,
+    /// Where uploaded files are served from.
+    pub http_addr: String,
+    pub deflate: DeflateConfig,
+    /// Index of this node among those sharing `backplane`.
+    pub node: u64,
+    pub backplane: Option<Arc<dyn Backplane>>,
+    /// Where to POST chat events.
+    pub webhooks: Vec<Webhook>,
+    /// Integrations allowed to post through incoming webhooks.
+    pub integrations: Vec<Integration>,
+    /// File integrations added or revoked with `/hook` are saved to.
+    pub integrations_file: Option<PathBuf>,
+    /// Where IRC clients connect, if anywhere.
+    pub irc_addr: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            moderators: HashSet::new(),
+            moderator_secret: None,
+            http_addr: "127.0.0.1:8081".to_string(),
+            deflate: DeflateConfig::default(),
+            node: 0,
+            backplane: None,
+            webhooks: Vec::new(),
+            integrations: Vec::new(),
+            integrations_file: None,
+            irc_addr: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `CHAT_MODERATORS`, `CHAT_MODERATOR_SECRET`, `CHAT_HTTP_ADDR`,
+    /// the compression settings, the backplane, `CHAT_WEBHOOKS`,
+    /// `CHAT_INCOMING_HOOKS` and `CHAT_IRC_ADDR` from the environment.
+    pub fn from_env() -> Self {
+        let (node, backplane) = backplane::from_env();
+        let (integrations, integrations_file) = integrations::from_env();
+        Self {
+            moderators: std::env::var("CHAT_MODERATORS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            moderator_secret: std::env::var("CHAT_MODERATOR_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            http_addr: std::env::var("CHAT_HTTP_ADDR")
+                .unwrap_or_else(|_| "127.0.0.1:8081".to_string()),
+            deflate: DeflateConfig::from_env(),
+            node,
+            backplane,
+            webhooks: webhooks::from_env(),
+            integrations,
+            integrations_file,
+            irc_addr: std::env::var("CHAT_IRC_ADDR").ok(),
+        }
+    }
+}
+
+/// Runs the chat on `listener` until it stops accepting connections.
+pub async fn serve(listener: TcpListener, config: Config) {
+    if !config.moderators.is_empty() && config.moderator_secret.is_none() {
+        warn!("CHAT_MODERATOR_SECRET is not set, so nobody can sign in as a moderator");
+    }
+    let state = SharedState::new(ServerState {
+        moderators: config.moderators,
+        moderator_secret: config.moderator_secret,
+        deflate: config.deflate,
+        webhooks: Webhooks::start(config.webhooks),
+        integrations: Integrations::new(config.integrations, config.integrations_file),
+        ..ServerState::new(config.node, config.backplane.clone())
+    });
+    
+    // Relay events from the other nodes
+    if let Some(backplane) = config.backplane {
+        tokio::spawn(backplane::run(state.clone(), backplane.subscribe()));
+    }
+    
+    // Spawn the HTTP server for uploaded files and incoming webhooks
+    tokio::spawn(http::serve(state.clone(), config.http_addr));
+    
+    // Spawn the IRC gateway if there is a port for it
+    if let Some(irc_addr) = config.irc_addr {
+        tokio::spawn(irc::serve(state.clone(), irc_addr));
+    }
+    
+    // Spawn the connection checker
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        check_connections(state_clone).await;
+    });
+    
+    // Spawn the idle checker for automatic away
+    tokio::spawn(presence::check_idle(state.clone()));
+    
+    // Spawn the typing indicator expiry
+    tokio::spawn(typing::expire_typing(state.clone()));
+    
+    // Accept and handle new connections
+    while let Ok((stream, addr)) = listener.accept().await {
+        let state_clone = state.clone();
+        tokio::spawn(async move {
+            handle_connection(state_clone, stream, addr).await;
+        });
+    }
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{backplane, ServerState};
+
+/// An `@username` in a message, as byte offsets into the message text.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Mention {
+    user: String,
+    start: usize,
+    end: usize,
+}
+
+impl Mention {
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+}
+
+/// Everyone a message could mention: connected users and anyone seen before.
+pub fn known_users(state: &ServerState) -> Vec<String> {
+    let mut users: Vec<String> = state.peers.lock().unwrap().keys().cloned().collect();
+    users.extend(state.presence.lock().unwrap().keys().cloned());
+    users.extend(backplane::remote_users(state));
+    users.sort();
+    users.dedup();
+    users
+}
+
+/// Finds `@username` mentions of known users. When several names match at
+/// the same `@` (e.g. `ann` and `anna`), the longest one wins.
+pub fn parse_mentions(text: &str, known_users: &[String]) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+
+    for (at, _) in text.match_indices('@') {
+        // An @ inside a word, like an email address, is not a mention
+        if text[..at].chars().next_back().is_some_and(is_name_char) {
+            continue;
+        }
+
+        let rest = &text[at + 1..];
+        let user = known_users
+            .iter()
+            .filter(|user| !user.is_empty() && rest.starts_with(user.as_str()))
+            .filter(|user| !rest[user.len()..].chars().next().is_some_and(is_name_char))
+            .max_by_key(|user| user.len());
+
+        if let Some(user) = user {
+            mentions.push(Mention {
+                user: user.clone(),
+                start: at,
+                end: at + 1 + user.len(),
+            });
+        }
+    }
+
+    mentions
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Vec<(String, &str)> {
+        let known = ["ann", "anna", "bob", "jean-luc"].map(String::from);
+        parse_mentions(text, &known)
+            .into_iter()
+            .map(|m| (m.user.clone(), &text[m.start..m.end]))
+            .collect()
+    }
+
+    #[test]
+    fn finds_known_users_around_punctuation() {
+        assert_eq!(
+            parse("@bob, (@ann) and @jean-luc!"),
+            [
+                ("bob".to_string(), "@bob"),
+                ("ann".to_string(), "@ann"),
+                ("jean-luc".to_string(), "@jean-luc"),
+            ]
+        );
+        assert_eq!(parse("thanks @bob."), [("bob".to_string(), "@bob")]);
+    }
+
+    #[test]
+    fn the_longest_name_wins() {
+        assert_eq!(parse("@anna hi"), [("anna".to_string(), "@anna")]);
+        assert_eq!(parse("@ann's"), [("ann".to_string(), "@ann")]);
+    }
+
+    #[test]
+    fn ignores_unknown_names_and_partial_matches() {
+        assert!(parse("@carol @annabel @bob_ @").is_empty());
+    }
+
+    #[test]
+    fn ignores_email_addresses() {
+        assert!(parse("mail ann@bob.com or x@ann").is_empty());
+    }
+
+    #[test]
+    fn offsets_count_bytes() {
+        let text = "héllo @bob";
+        let mentions = parse_mentions(text, &["bob".to_string()]);
+        assert_eq!((mentions[0].start, mentions[0].end), (7, 11));
+    }
+}
@@ -0,0 +1,264 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{self, Duration};
+
+use crate::{
+    backplane, broadcast_local, now_millis, MessageType, ServerState, SharedState,
+    WebSocketMessage,
+};
+
+/// Users with no activity for this long are switched from online to away.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Longest custom status text that is kept.
+const MAX_STATUS_TEXT: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Online,
+    Away,
+    Busy,
+    Offline,
+}
+
+/// What everyone sees about a user in the sidebar.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Presence {
+    status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    last_seen: u64,
+    // Set when the server switched the user to away because of inactivity
+    #[serde(skip)]
+    auto_away: bool,
+    #[serde(skip, default = "Instant::now")]
+    last_active: Instant,
+}
+
+impl Presence {
+    pub fn status(&self) -> Status {
+        self.status
+    }
+}
+
+// Presences are the same when users see the same status and text
+impl PartialEq for Presence {
+    fn eq(&self, other: &Self) -> bool {
+        self.status == other.status && self.text == other.text
+    }
+}
+
+// Status change requested by a client; offline cannot be chosen
+#[derive(Debug, Deserialize)]
+struct StatusUpdate {
+    status: Status,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+/// Marks a freshly registered user online, keeping any status text they had.
+pub fn connect(state: &ServerState, user_id: &str) {
+    {
+        let mut presence = state.presence.lock().unwrap();
+        let entry = presence.entry(user_id.to_string()).or_insert(Presence {
+            status: Status::Online,
+            text: None,
+            last_seen: 0,
+            auto_away: false,
+            last_active: Instant::now(),
+        });
+        entry.status = Status::Online;
+        entry.auto_away = false;
+        entry.last_active = Instant::now();
+        entry.last_seen = now_millis();
+    }
+    broadcast_presence(state);
+}
+
+/// Marks a user offline and remembers when they were last seen.
+pub fn disconnect(state: &ServerState, user_id: &str) {
+    {
+        let mut presence = state.presence.lock().unwrap();
+        let Some(entry) = presence.get_mut(user_id) else {
+            return;
+        };
+        entry.status = Status::Offline;
+        entry.last_seen = now_millis();
+    }
+    broadcast_presence(state);
+}
+
+/// Moves a user's presence to a new name after `/nick`.
+pub fn rename(state: &ServerState, old: &str, new: &str) {
+    {
+        let mut presence = state.presence.lock().unwrap();
+        if let Some(mut entry) = presence.remove(old) {
+            entry.last_seen = now_millis();
+            presence.insert(new.to_string(), entry);
+        }
+    }
+    broadcast_presence(state);
+}
+
+/// Records activity from a user, bringing them back from automatic away.
+pub fn touch(state: &ServerState, user_id: &str) {
+    let changed = {
+        let mut presence = state.presence.lock().unwrap();
+        let Some(entry) = presence.get_mut(user_id) else {
+            return;
+        };
+        entry.last_active = Instant::now();
+        entry.last_seen = now_millis();
+        if entry.auto_away {
+            entry.auto_away = false;
+            entry.status = Status::Online;
+            true
+        } else {
+            false
+        }
+    };
+
+    if changed {
+        broadcast_presence(state);
+    }
+}
+
+/// Applies a `status` frame sent by a client.
+pub fn set_status(state: &ServerState, user_id: &str, data: &str) {
+    let Ok(update) = serde_json::from_str::<StatusUpdate>(data) else {
+        return;
+    };
+    if update.status == Status::Offline {
+        return;
+    }
+
+    {
+        let mut presence = state.presence.lock().unwrap();
+        let Some(entry) = presence.get_mut(user_id) else {
+            return;
+        };
+        entry.status = update.status;
+        entry.auto_away = false;
+        entry.text = update
+            .text
+            .map(|t| t.trim().chars().take(MAX_STATUS_TEXT).collect::<String>())
+            .filter(|t| !t.is_empty());
+    }
+    broadcast_presence(state);
+}
+
+/// Frame with the presence of every user seen since the server started,
+/// on this node or any other.
+pub fn presence_frame(state: &ServerState) -> WebSocketMessage {
+    let mut presence = state.presence.lock().unwrap().clone();
+    backplane::merge_presence(state, &mut presence);
+    WebSocketMessage {
+        message_type: MessageType::Presence,
+        data: Some(serde_json::to_string(&presence).unwrap()),
+        data_array: None,
+    }
+}
+
+fn broadcast_presence(state: &ServerState) {
+    backplane::announce(state);
+    send_presence(state);
+}
+
+/// Sends the presence frame to the users connected to this node.
+pub fn send_presence(state: &ServerState) {
+    let json = serde_json::to_string(&presence_frame(state)).unwrap();
+    broadcast_local(&state.peers, &json);
+}
+
+/// Switches online users to away once they have been idle for `IDLE_TIMEOUT`.
+pub async fn check_idle(state: SharedState) {
+    let mut interval_stream = time::interval(Duration::from_secs(30));
+
+    loop {
+        interval_stream.tick().await;
+        mark_idle(&state);
+    }
+}
+
+// One sweep of `check_idle`, broadcasting presence if anyone went away
+fn mark_idle(state: &ServerState) {
+    let mut changed = false;
+    for entry in state.presence.lock().unwrap().values_mut() {
+        if entry.status == Status::Online && entry.last_active.elapsed() >= IDLE_TIMEOUT {
+            entry.status = Status::Away;
+            entry.auto_away = true;
+            changed = true;
+        }
+    }
+
+    if changed {
+        broadcast_presence(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_of(state: &ServerState, user_id: &str) -> (Status, Option<String>) {
+        let presence = state.presence.lock().unwrap();
+        let entry = &presence[user_id];
+        (entry.status, entry.text.clone())
+    }
+
+    // Pretends nobody has done anything for `IDLE_TIMEOUT`
+    fn idle(state: &ServerState) {
+        let long_ago = Instant::now() - IDLE_TIMEOUT;
+        for entry in state.presence.lock().unwrap().values_mut() {
+            entry.last_active = long_ago;
+        }
+        mark_idle(state);
+    }
+
+    #[test]
+    fn idle_users_are_away_until_they_come_back() {
+        let state = ServerState::new(1, None);
+        connect(&state, "ann");
+        connect(&state, "bob");
+        set_status(&state, "bob", r#"{"status":"busy"}"#);
+        idle(&state);
+        assert_eq!(status_of(&state, "ann"), (Status::Away, None));
+        assert_eq!(status_of(&state, "bob"), (Status::Busy, None));
+
+        touch(&state, "ann");
+        assert_eq!(status_of(&state, "ann"), (Status::Online, None));
+    }
+
+    #[test]
+    fn chosen_away_outlasts_activity() {
+        let state = ServerState::new(1, None);
+        connect(&state, "ann");
+        set_status(&state, "ann", r#"{"status":"away","text":"lunch"}"#);
+        touch(&state, "ann");
+        assert_eq!(status_of(&state, "ann"), (Status::Away, Some("lunch".to_string())));
+
+        // Reconnecting is online again, with the text kept
+        disconnect(&state, "ann");
+        assert_eq!(status_of(&state, "ann").0, Status::Offline);
+        connect(&state, "ann");
+        assert_eq!(status_of(&state, "ann"), (Status::Online, Some("lunch".to_string())));
+    }
+
+    #[test]
+    fn status_updates_are_checked() {
+        let state = ServerState::new(1, None);
+        connect(&state, "ann");
+        set_status(&state, "ann", r#"{"status":"offline"}"#);
+        set_status(&state, "ann", "busy");
+        assert_eq!(status_of(&state, "ann"), (Status::Online, None));
+
+        let long = format!(r#"{{"status":"busy","text":"  {}  "}}"#, "z".repeat(150));
+        set_status(&state, "ann", &long);
+        assert_eq!(status_of(&state, "ann"), (Status::Busy, Some("z".repeat(MAX_STATUS_TEXT))));
+
+        set_status(&state, "ann", r#"{"status":"online","text":"   "}"#);
+        assert_eq!(status_of(&state, "ann"), (Status::Online, None));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadcast_message, commands::notice, send_frame, ChatMessage, MessageType, ServerState, Tx,
+    UserId, WebSocketMessage,
+};
+
+/// Longest reaction accepted, in characters. Emoji with modifiers take several.
+const MAX_EMOJI_CHARS: usize = 16;
+
+/// Most distinct reactions a single message can collect.
+const MAX_REACTIONS_PER_MESSAGE: usize = 20;
+
+// Reaction added or removed by a client
+#[derive(Debug, Deserialize)]
+struct ReactionRequest {
+    id: u64,
+    emoji: String,
+}
+
+// Broadcast after any change to a message's reactions
+#[derive(Debug, Serialize)]
+struct ReactionUpdate<'a> {
+    id: u64,
+    reactions: &'a BTreeMap<String, Vec<UserId>>,
+}
+
+/// Applies a `react` (`add` is true) or `unreact` frame and broadcasts the
+/// message's reactions with the users behind each of them.
+pub fn set_reaction(state: &ServerState, user_id: &str, tx: &Tx, data: &str, add: bool) {
+    let Ok(request) = serde_json::from_str::<ReactionRequest>(data) else {
+        return;
+    };
+    let emoji = request.emoji.trim();
+    if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_CHARS {
+        return;
+    }
+
+    let json = {
+        let mut history = state.history.lock().unwrap();
+        let Some(message) = history.get_mut(request.id) else {
+            send_frame(tx, &notice("That message no longer exists"));
+            return;
+        };
+        if message.deleted {
+            return;
+        }
+
+        let changed = if add {
+            add_reaction(message, emoji, user_id)
+        } else {
+            remove_reaction(message, emoji, user_id)
+        };
+        if !changed {
+            return;
+        }
+
+        serde_json::to_string(&WebSocketMessage {
+            message_type: MessageType::Reactions,
+            data: Some(
+                serde_json::to_string(&ReactionUpdate {
+                    id: message.id,
+                    reactions: &message.reactions,
+                })
+                .unwrap(),
+            ),
+            data_array: None,
+        })
+        .unwrap()
+    };
+
+    broadcast_message(state, &json);
+}
+
+fn add_reaction(message: &mut ChatMessage, emoji: &str, user_id: &str) -> bool {
+    if !message.reactions.contains_key(emoji)
+        && message.reactions.len() >= MAX_REACTIONS_PER_MESSAGE
+    {
+        return false;
+    }
+    let users = message.reactions.entry(emoji.to_string()).or_default();
+    if users.iter().any(|u| u == user_id) {
+        return false;
+    }
+    users.push(user_id.to_string());
+    true
+}
+
+fn remove_reaction(message: &mut ChatMessage, emoji: &str, user_id: &str) -> bool {
+    let Some(users) = message.reactions.get_mut(emoji) else {
+        return false;
+    };
+    let before = users.len();
+    users.retain(|u| u != user_id);
+    let changed = users.len() != before;
+    if users.is_empty() {
+        message.reactions.remove(emoji);
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    // A state holding one message from ann, and its ID
+    fn with_message() -> (ServerState, u64) {
+        let state = ServerState::new(1, None);
+        let message = ChatMessage::text(&state, "ann", "hi".to_string());
+        let id = state.history.lock().unwrap().push(message).id;
+        (state, id)
+    }
+
+    fn react(state: &ServerState, user_id: &str, id: u64, emoji: &str) {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let data = serde_json::json!({ "id": id, "emoji": emoji }).to_string();
+        set_reaction(state, user_id, &tx, &data, true);
+    }
+
+    fn reactions_on(state: &ServerState, id: u64) -> BTreeMap<String, Vec<UserId>> {
+        state.history.lock().unwrap().get_mut(id).unwrap().reactions.clone()
+    }
+
+    #[test]
+    fn reactions_are_at_most_16_characters() {
+        let (state, id) = with_message();
+        let longest = "x".repeat(MAX_EMOJI_CHARS);
+        react(&state, "bob", id, &longest);
+        react(&state, "bob", id, &"y".repeat(MAX_EMOJI_CHARS + 1));
+        // Family emoji are several characters joined into one
+        react(&state, "bob", id, "👨‍👩‍👧‍👦");
+        react(&state, "bob", id, "  ");
+        let reactions = reactions_on(&state, id);
+        assert_eq!(reactions.keys().collect::<Vec<_>>(), [&longest, "👨‍👩‍👧‍👦"]);
+    }
+
+    #[test]
+    fn a_message_collects_at_most_20_distinct_reactions() {
+        let (state, id) = with_message();
+        for i in 0..=MAX_REACTIONS_PER_MESSAGE {
+            react(&state, "bob", id, &i.to_string());
+        }
+        let reactions = reactions_on(&state, id);
+        assert_eq!(reactions.len(), MAX_REACTIONS_PER_MESSAGE);
+        assert!(!reactions.contains_key(&MAX_REACTIONS_PER_MESSAGE.to_string()));
+
+        // Existing reactions can still be joined
+        react(&state, "ann", id, "0");
+        assert_eq!(reactions_on(&state, id)["0"], ["bob", "ann"]);
+    }
+
+    #[test]
+    fn reacting_twice_counts_once_and_removing_the_last_drops_the_emoji() {
+        let (state, id) = with_message();
+        react(&state, "bob", id, " 👍 ");
+        react(&state, "bob", id, "👍");
+        assert_eq!(reactions_on(&state, id)["👍"], ["bob"]);
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let data = serde_json::json!({ "id": id, "emoji": "👍" }).to_string();
+        set_reaction(&state, "bob", &tx, &data, false);
+        assert!(reactions_on(&state, id).is_empty());
+    }
+}
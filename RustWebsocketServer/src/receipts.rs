@@ -0,0 +1,91 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::{broadcast_message, send_to_user, MessageType, ServerState, UserId, WebSocketMessage};
+
+/// Delivery acknowledgements per message and the last message each user has read.
+#[derive(Default)]
+pub struct Receipts {
+    delivered: HashMap<u64, BTreeSet<UserId>>,
+    read: HashMap<UserId, u64>,
+}
+
+// Sent to the author of a message whenever someone new receives it
+#[derive(Debug, Serialize)]
+struct DeliveryReceipt<'a> {
+    id: u64,
+    delivered_to: &'a BTreeSet<UserId>,
+}
+
+/// Records that `user_id` received message `id` and tells the author.
+pub fn mark_delivered(state: &ServerState, user_id: &str, id: u64) {
+    let author = match state.history.lock().unwrap().get(id) {
+        Some(message) if message.from != user_id => message.from.clone(),
+        _ => return,
+    };
+
+    let frame = {
+        let mut receipts = state.receipts.lock().unwrap();
+        let delivered_to = receipts.delivered.entry(id).or_default();
+        if !delivered_to.insert(user_id.to_string()) {
+            return;
+        }
+        WebSocketMessage {
+            message_type: MessageType::Receipt,
+            data: Some(serde_json::to_string(&DeliveryReceipt { id, delivered_to }).unwrap()),
+            data_array: None,
+        }
+    };
+
+    send_to_user(state, &author, &frame);
+}
+
+/// Moves the read marker of `user_id` forward to `id` and broadcasts the markers.
+pub fn mark_read(state: &ServerState, user_id: &str, id: u64) {
+    if state.history.lock().unwrap().get(id).is_none() {
+        return;
+    }
+
+    {
+        let mut receipts = state.receipts.lock().unwrap();
+        let marker = receipts.read.entry(user_id.to_string()).or_insert(0);
+        if *marker >= id {
+            return;
+        }
+        *marker = id;
+    }
+
+    let json = serde_json::to_string(&read_markers_frame(state)).unwrap();
+    broadcast_message(state, &json);
+}
+
+/// Merges the read markers another node broadcast into this node's own,
+/// keeping the furthest marker of each user. Returns the merged frame to send
+/// to local users instead, as the other node may not know every marker this
+/// one does; `None` when `frame` holds no read markers.
+pub fn replicate(state: &ServerState, frame: &str) -> Option<String> {
+    let frame = serde_json::from_str::<WebSocketMessage>(frame).ok()?;
+    if frame.message_type != MessageType::ReadMarkers {
+        return None;
+    }
+    let remote: HashMap<UserId, u64> = serde_json::from_str(&frame.data?).ok()?;
+    {
+        let mut receipts = state.receipts.lock().unwrap();
+        for (user, id) in remote {
+            let marker = receipts.read.entry(user).or_insert(0);
+            *marker = (*marker).max(id);
+        }
+    }
+    Some(serde_json::to_string(&read_markers_frame(state)).unwrap())
+}
+
+/// Frame with the last read message ID of every user.
+pub fn read_markers_frame(state: &ServerState) -> WebSocketMessage {
+    let receipts = state.receipts.lock().unwrap();
+    WebSocketMessage {
+        message_type: MessageType::ReadMarkers,
+        data: Some(serde_json::to_string(&receipts.read).unwrap()),
+        data_array: None,
+    }
+}
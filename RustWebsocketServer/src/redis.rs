@@ -0,0 +1,357 @@
+//! Backplane over the Redis protocol: nodes PUBLISH envelopes as JSON on one
+//! channel and SUBSCRIBE to it on a second connection. Only those commands
+//! are spoken, so any server implementing RESP pub/sub will do.
+
+use std::io;
+
+use log::{error, info, warn};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+
+use crate::backplane::{Backplane, Envelope};
+
+/// Longest wait between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Largest bulk string accepted from the server.
+const MAX_BULK_SIZE: usize = 64 << 20;
+
+pub struct RedisBackplane {
+    address: String,
+    channel: String,
+    outgoing: mpsc::UnboundedSender<String>,
+}
+
+impl RedisBackplane {
+    /// Starts publishing to `channel` on the server at `address`. Connections
+    /// are made in the background and retried for as long as the node runs.
+    pub fn connect(address: String, channel: String) -> Self {
+        let (outgoing, rx) = mpsc::unbounded_channel();
+        tokio::spawn(publish_loop(address.clone(), channel.clone(), rx));
+        Self {
+            address,
+            channel,
+            outgoing,
+        }
+    }
+}
+
+impl Backplane for RedisBackplane {
+    fn publish(&self, envelope: Envelope) {
+        let _ = self
+            .outgoing
+            .send(serde_json::to_string(&envelope).unwrap());
+    }
+
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<Envelope> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(subscribe_loop(
+            self.address.clone(),
+            self.channel.clone(),
+            tx,
+        ));
+        rx
+    }
+}
+
+/// A reply from the server. Nested arrays are not needed by pub/sub and are
+/// not supported.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+/// Encodes a command as an array of bulk strings.
+pub fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn read_scalar<R: AsyncBufRead + Unpin>(reader: &mut R, line: &str) -> io::Result<Reply> {
+    let (kind, rest) = line.split_at(line.len().min(1));
+    let number = || rest.parse::<i64>().map_err(|_| invalid("bad number"));
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_string())),
+        "-" => Ok(Reply::Error(rest.to_string())),
+        ":" => Ok(Reply::Integer(number()?)),
+        "$" if number()? < 0 => Ok(Reply::Bulk(None)),
+        "$" => {
+            let len = number()? as usize;
+            if len > MAX_BULK_SIZE {
+                return Err(invalid("bulk string too large"));
+            }
+            let mut data = vec![0; len + 2];
+            reader.read_exact(&mut data).await?;
+            data.truncate(len);
+            Ok(Reply::Bulk(Some(data)))
+        }
+        _ => Err(invalid("unexpected reply")),
+    }
+}
+
+/// Reads one reply, or one command when acting as the server.
+pub async fn read_reply<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Reply> {
+    let line = read_line(reader).await?;
+    let Some(count) = line.strip_prefix('*') else {
+        return read_scalar(reader, &line).await;
+    };
+    let count: i64 = count.parse().map_err(|_| invalid("bad array length"))?;
+    let mut items = Vec::new();
+    for _ in 0..count.max(0) {
+        let line = read_line(reader).await?;
+        if line.starts_with('*') {
+            return Err(invalid("nested arrays are not supported"));
+        }
+        items.push(read_scalar(reader, &line).await?);
+    }
+    Ok(Reply::Array(items))
+}
+
+// Waits before the next connection attempt, doubling the wait each time
+async fn back_off(backoff: &mut Duration) {
+    time::sleep(*backoff).await;
+    *backoff = (*backoff * 2).min(MAX_BACKOFF);
+}
+
+async fn publish_loop(address: String, channel: String, mut rx: mpsc::UnboundedReceiver<String>) {
+    let mut backoff = Duration::from_millis(100);
+    loop {
+        let stream = match TcpStream::connect(&address).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Cannot reach Redis at {} to publish: {}", address, e);
+                back_off(&mut backoff).await;
+                continue;
+            }
+        };
+        backoff = Duration::from_millis(100);
+        let mut stream = BufReader::new(stream);
+
+        while let Some(payload) = rx.recv().await {
+            let publish = command(&[b"PUBLISH", channel.as_bytes(), payload.as_bytes()]);
+            let result = match stream.get_mut().write_all(&publish).await {
+                Ok(()) => read_reply(&mut stream).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Reply::Error(e)) => error!("Redis refused to publish: {}", e),
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Lost the Redis publishing connection: {}", e);
+                    break;
+                }
+            }
+        }
+        if rx.is_closed() {
+            return;
+        }
+    }
+}
+
+async fn subscribe_loop(address: String, channel: String, tx: mpsc::UnboundedSender<Envelope>) {
+    let mut backoff = Duration::from_millis(100);
+    while !tx.is_closed() {
+        match subscribe_once(&address, &channel, &tx).await {
+            Ok(()) => return,
+            Err(e) => warn!("Redis subscription to {} failed: {}", address, e),
+        }
+        back_off(&mut backoff).await;
+    }
+}
+
+// Forwards envelopes until the receiver goes away (Ok) or the connection fails
+async fn subscribe_once(
+    address: &str,
+    channel: &str,
+    tx: &mpsc::UnboundedSender<Envelope>,
+) -> io::Result<()> {
+    let mut stream = BufReader::new(TcpStream::connect(address).await?);
+    stream
+        .get_mut()
+        .write_all(&command(&[b"SUBSCRIBE", channel.as_bytes()]))
+        .await?;
+    info!("Subscribed to Redis channel {} at {}", channel, address);
+
+    loop {
+        let Reply::Array(items) = read_reply(&mut stream).await? else {
+            continue;
+        };
+        let [Reply::Bulk(Some(kind)), _, Reply::Bulk(Some(payload))] = &items[..] else {
+            continue;
+        };
+        if kind != b"message" {
+            continue;
+        }
+        match serde_json::from_slice::<Envelope>(payload) {
+            Ok(envelope) => {
+                if tx.send(envelope).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => warn!("Ignoring malformed envelope from Redis: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::backplane::Event;
+
+    type Subscribers = Arc<Mutex<Vec<(Vec<u8>, mpsc::UnboundedSender<Vec<u8>>)>>>;
+
+    // Stand-in for a Redis server that knows SUBSCRIBE and PUBLISH
+    async fn start_broker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let subscribers = Subscribers::default();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let subscribers = subscribers.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+                    tokio::spawn(async move {
+                        while let Some(bytes) = rx.recv().await {
+                            if write.write_all(&bytes).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    let mut read = BufReader::new(read);
+                    while let Ok(Reply::Array(args)) = read_reply(&mut read).await {
+                        let args: Vec<Vec<u8>> = args
+                            .into_iter()
+                            .filter_map(|arg| match arg {
+                                Reply::Bulk(Some(arg)) => Some(arg),
+                                _ => None,
+                            })
+                            .collect();
+                        match args
+                            .first()
+                            .map(|name| name.to_ascii_uppercase())
+                            .as_deref()
+                        {
+                            Some(b"SUBSCRIBE") => {
+                                subscribers
+                                    .lock()
+                                    .unwrap()
+                                    .push((args[1].clone(), tx.clone()));
+                                let _ = tx.send(command(&[b"subscribe", &args[1]]));
+                            }
+                            Some(b"PUBLISH") => {
+                                let message = command(&[b"message", &args[1], &args[2]]);
+                                let mut subscribers = subscribers.lock().unwrap();
+                                subscribers.retain(|(_, subscriber)| !subscriber.is_closed());
+                                let mut count = 0;
+                                for (channel, subscriber) in subscribers.iter() {
+                                    if *channel == args[1]
+                                        && subscriber.send(message.clone()).is_ok()
+                                    {
+                                        count += 1;
+                                    }
+                                }
+                                let _ = tx.send(format!(":{}\r\n", count).into_bytes());
+                            }
+                            _ => {
+                                let _ = tx.send(b"-ERR unknown command\r\n".to_vec());
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    fn broadcast(node: u64, frame: &str) -> Envelope {
+        Envelope {
+            node,
+            event: Event::Broadcast {
+                frame: frame.to_string(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_replies() {
+        let mut input: &[u8] =
+            b"+OK\r\n:3\r\n$5\r\nhello\r\n$-1\r\n*2\r\n$1\r\na\r\n:1\r\n-ERR no\r\n";
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Status("OK".into())
+        );
+        assert_eq!(read_reply(&mut input).await.unwrap(), Reply::Integer(3));
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Bulk(Some(b"hello".to_vec()))
+        );
+        assert_eq!(read_reply(&mut input).await.unwrap(), Reply::Bulk(None));
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Array(vec![Reply::Bulk(Some(b"a".to_vec())), Reply::Integer(1)])
+        );
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Error("ERR no".into())
+        );
+        assert!(read_reply(&mut input).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn relays_envelopes_between_nodes() {
+        let address = start_broker().await;
+        let a = RedisBackplane::connect(address.clone(), "chat".into());
+        let b = RedisBackplane::connect(address.clone(), "chat".into());
+        let other = RedisBackplane::connect(address, "elsewhere".into());
+        let mut received = b.subscribe();
+        let mut unrelated = other.subscribe();
+
+        // The subscription is made in the background, so publish until it is up
+        let envelope = time::timeout(Duration::from_secs(2), async {
+            loop {
+                a.publish(broadcast(0, "hello"));
+                if let Ok(Some(envelope)) =
+                    time::timeout(Duration::from_millis(50), received.recv()).await
+                {
+                    return envelope;
+                }
+            }
+        })
+        .await
+        .expect("envelope never arrived");
+
+        assert_eq!(envelope.node, 0);
+        assert!(matches!(envelope.event, Event::Broadcast { frame } if frame == "hello"));
+        assert!(unrelated.try_recv().is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::notice, history::History, send_frame, ChatMessage, MessageType, ServerState, Tx,
+    WebSocketMessage,
+};
+
+/// Most results returned for one search, newest first.
+const MAX_RESULTS: usize = 50;
+
+/// Messages sent on each side of a message opened from search.
+const CONTEXT_MESSAGES: usize = 10;
+
+/// The only room there is until the server grows more of them.
+const DEFAULT_ROOM: &str = "general";
+
+/// Inverted index from lowercased words to the messages containing them.
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, BTreeSet<u64>>,
+    // Words of each indexed message in order, for phrase matching
+    words: HashMap<u64, Vec<String>>,
+}
+
+impl SearchIndex {
+    /// Indexes the current text of `message`, replacing what was indexed for it.
+    /// Deleted messages are dropped from the index.
+    pub fn update(&mut self, message: &ChatMessage) {
+        self.remove(message.id);
+        if message.deleted {
+            return;
+        }
+
+        let mut words = tokenize(&message.message);
+        if let Some(attachment) = &message.attachment {
+            words.extend(tokenize(&attachment.name));
+        }
+        for word in &words {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(message.id);
+        }
+        self.words.insert(message.id, words);
+    }
+
+    fn remove(&mut self, id: u64) {
+        let Some(words) = self.words.remove(&id) else {
+            return;
+        };
+        for word in words {
+            if let Some(ids) = self.postings.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    // Messages containing every one of `words`, or None when there are no words
+    fn candidates(&self, words: &[String]) -> Option<BTreeSet<u64>> {
+        let mut sets = words
+            .iter()
+            .map(|word| self.postings.get(word).cloned().unwrap_or_default());
+        let first = sets.next()?;
+        Some(sets.fold(first, |all, ids| &all & &ids))
+    }
+
+    fn contains_phrase(&self, id: u64, phrase: &[String]) -> bool {
+        self.words
+            .get(&id)
+            .is_some_and(|words| words.windows(phrase.len()).any(|w| w == phrase))
+    }
+}
+
+/// Lowercased words of `text`, the unit the index is built from.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Search sent by a client. Filters may also be written into the query itself
+// as `from:name`, `room:name`, `after:YYYY-MM-DD` and `before:YYYY-MM-DD`;
+// text in double quotes must match as a phrase.
+#[derive(Debug, Deserialize, Default)]
+struct SearchRequest {
+    #[serde(default)]
+    query: String,
+    from: Option<String>,
+    room: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResults<'a> {
+    query: String,
+    total: usize,
+    results: Vec<&'a ChatMessage>,
+}
+
+#[derive(Debug, Serialize)]
+struct MessageContext<'a> {
+    id: u64,
+    messages: &'a [ChatMessage],
+}
+
+#[derive(Debug, Default)]
+struct Query {
+    words: Vec<String>,
+    phrases: Vec<Vec<String>>,
+    from: Option<String>,
+    room: Option<String>,
+    // Time range in milliseconds, start inclusive and end exclusive
+    after: Option<u64>,
+    before: Option<u64>,
+}
+
+fn parse_query(request: SearchRequest) -> Result<Query, String> {
+    let mut query = Query {
+        from: request.from.filter(|f| !f.is_empty()),
+        room: request.room.filter(|r| !r.is_empty()),
+        ..Query::default()
+    };
+    let mut after = request.after.filter(|a| !a.is_empty());
+    let mut before = request.before.filter(|b| !b.is_empty());
+
+    // Quoted parts alternate with unquoted ones
+    for (i, part) in request.query.split('"').enumerate() {
+        if i % 2 == 1 {
+            let phrase = tokenize(part);
+            if !phrase.is_empty() {
+                query.phrases.push(phrase);
+            }
+            continue;
+        }
+        for term in part.split_whitespace() {
+            match term.split_once(':') {
+                Some(("from", name)) => query.from = Some(name.trim_start_matches('@').to_string()),
+                Some(("room", room)) => query.room = Some(room.trim_start_matches('#').to_string()),
+                Some(("after", date)) => after = Some(date.to_string()),
+                Some(("before", date)) => before = Some(date.to_string()),
+                _ => query.words.extend(tokenize(term)),
+            }
+        }
+    }
+
+    // Like Slack, both ends exclude the given day
+    if let Some(date) = after {
+        query.after = Some(day_start(&date, 1)?);
+    }
+    if let Some(date) = before {
+        query.before = Some(day_start(&date, 0)?);
+    }
+
+    if query.words.is_empty() && query.phrases.is_empty() && query.from.is_none() {
+        return Err("Search for some words or filter by author".to_string());
+    }
+    Ok(query)
+}
+
+// Start of `date` plus `days_later` days, in milliseconds since the epoch (UTC)
+fn day_start(date: &str, days_later: i64) -> Result<u64, String> {
+    let invalid = || format!("Dates look like 2024-01-31, not {}", date);
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid())?;
+    let start = day
+        .checked_add_signed(Duration::days(days_later))
+        .ok_or_else(invalid)?
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis();
+    Ok(start.max(0) as u64)
+}
+
+fn search_history<'a>(history: &'a History, query: &Query) -> Vec<&'a ChatMessage> {
+    if query.room.as_deref().is_some_and(|room| room != DEFAULT_ROOM) {
+        return vec![];
+    }
+
+    let index = history.search_index();
+    let words: Vec<String> = query
+        .words
+        .iter()
+        .chain(query.phrases.iter().flatten())
+        .cloned()
+        .collect();
+    let candidates: Box<dyn Iterator<Item = &ChatMessage>> = match index.candidates(&words) {
+        Some(ids) => Box::new(ids.into_iter().filter_map(|id| history.get(id))),
+        None => Box::new(history.iter().filter(|m| !m.deleted)),
+    };
+
+    let mut results: Vec<&ChatMessage> = candidates
+        .filter(|m| query.from.as_ref().is_none_or(|from| m.from == *from))
+        .filter(|m| query.after.is_none_or(|after| m.time >= after))
+        .filter(|m| query.before.is_none_or(|before| m.time < before))
+        .filter(|m| query.phrases.iter().all(|p| index.contains_phrase(m.id, p)))
+        .collect();
+    results.reverse();
+    results
+}
+
+/// Answers a `search` frame with the newest matching messages.
+pub fn search(state: &ServerState, tx: &Tx, data: &str) {
+    let request: SearchRequest = serde_json::from_str(data).unwrap_or_default();
+    let text = request.query.clone();
+    let query = match parse_query(request) {
+        Ok(query) => query,
+        Err(error) => {
+            send_frame(tx, &notice(&error));
+            return;
+        }
+    };
+
+    let history = state.history.lock().unwrap();
+    let mut results = search_history(&history, &query);
+    let total = results.len();
+    results.truncate(MAX_RESULTS);
+
+    let results = SearchResults {
+        query: text,
+        total,
+        results,
+    };
+    send_frame(
+        tx,
+        &WebSocketMessage {
+            message_type: MessageType::SearchResults,
+            data: Some(serde_json::to_string(&results).unwrap()),
+            data_array: None,
+        },
+    );
+}
+
+/// Answers a `context` frame with the messages around message `id`, so a
+/// search result can be shown where it was said.
+pub fn send_context(state: &ServerState, tx: &Tx, id: u64) {
+    let history = state.history.lock().unwrap();
+    let Some(messages) = history.around(id, CONTEXT_MESSAGES) else {
+        send_frame(tx, &notice("That message no longer exists"));
+        return;
+    };
+
+    send_frame(
+        tx,
+        &WebSocketMessage {
+            message_type: MessageType::Context,
+            data: Some(serde_json::to_string(&MessageContext { id, messages }).unwrap()),
+            data_array: None,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Result<Query, String> {
+        parse_query(SearchRequest {
+            query: query.to_string(),
+            ..SearchRequest::default()
+        })
+    }
+
+    #[test]
+    fn reads_filters_from_the_query() {
+        let query = parse("deploy from:@ann room:#general Friday").unwrap();
+        assert_eq!(query.words, ["deploy", "friday"]);
+        assert_eq!(query.from.as_deref(), Some("ann"));
+        assert_eq!(query.room.as_deref(), Some("general"));
+
+        // Filters written into the query win over the request's own
+        let query = parse_query(SearchRequest {
+            query: "from:bob".to_string(),
+            from: Some("ann".to_string()),
+            ..SearchRequest::default()
+        })
+        .unwrap();
+        assert_eq!(query.from.as_deref(), Some("bob"));
+        assert!(query.words.is_empty());
+    }
+
+    #[test]
+    fn dates_exclude_the_given_day() {
+        let query = parse("release after:2024-01-31 before:2024-03-01").unwrap();
+        assert_eq!(query.after, Some(1_706_745_600_000)); // 2024-02-01
+        assert_eq!(query.before, Some(1_709_251_200_000)); // 2024-03-01
+        assert_eq!(parse("x after:1960-01-01").unwrap().after, Some(0));
+    }
+
+    #[test]
+    fn quoted_text_is_a_phrase() {
+        let query = parse(r#"bug "Out of memory" "" crash"#).unwrap();
+        assert_eq!(query.words, ["bug", "crash"]);
+        assert_eq!(query.phrases, [["out", "of", "memory"]]);
+
+        // An unclosed quote still makes a phrase of the rest
+        assert_eq!(parse(r#""half open"#).unwrap().phrases, [["half", "open"]]);
+    }
+
+    #[test]
+    fn bad_dates_are_refused() {
+        for date in ["31-01-2024", "2024-02-30", "soon", "+262142-12-31"] {
+            let error = parse(&format!("x after:{}", date)).unwrap_err();
+            assert!(error.contains(date), "{}", error);
+        }
+        assert!(parse("x before:+262142-12-31").is_ok());
+        assert!(parse("after:2024-01-01").is_err());
+    }
+}
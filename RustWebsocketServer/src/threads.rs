@@ -0,0 +1,77 @@
+use serde::Serialize;
+
+use crate::{
+    broadcast_message, commands::notice, history::History, send_frame, ChatMessage, MessageType,
+    ServerState, Tx, WebSocketMessage,
+};
+
+// Contents of a thread, sent to the client that asked for it
+#[derive(Debug, Serialize)]
+struct ThreadContents<'a> {
+    root: &'a ChatMessage,
+    replies: Vec<&'a ChatMessage>,
+}
+
+// Broadcast whenever a thread gains or loses a reply
+#[derive(Debug, Serialize)]
+struct ThreadUpdate {
+    id: u64,
+    reply_count: usize,
+}
+
+/// Root of the thread that message `id` belongs to. Replying inside a thread
+/// stays in that thread, so threads are never nested.
+pub fn resolve_root(history: &History, id: u64) -> Option<u64> {
+    let message = history.get(id)?;
+    Some(message.thread_id.unwrap_or(message.id))
+}
+
+/// Recounts the replies of a thread root that were not deleted and tells
+/// everyone the new count.
+pub fn update_reply_count(state: &ServerState, root_id: u64) {
+    let reply_count = {
+        let mut history = state.history.lock().unwrap();
+        let reply_count = history.thread(root_id).filter(|m| !m.deleted).count();
+        let Some(root) = history.get_mut(root_id) else {
+            return;
+        };
+        root.reply_count = reply_count;
+        reply_count
+    };
+
+    let json = serde_json::to_string(&WebSocketMessage {
+        message_type: MessageType::ThreadUpdate,
+        data: Some(
+            serde_json::to_string(&ThreadUpdate {
+                id: root_id,
+                reply_count,
+            })
+            .unwrap(),
+        ),
+        data_array: None,
+    })
+    .unwrap();
+    broadcast_message(state, &json);
+}
+
+/// Answers a `thread` frame with the root message and all of its replies.
+pub fn send_thread(state: &ServerState, tx: &Tx, id: u64) {
+    let history = state.history.lock().unwrap();
+    let Some(root) = resolve_root(&history, id).and_then(|root| history.get(root)) else {
+        send_frame(tx, &notice("That thread no longer exists"));
+        return;
+    };
+
+    let contents = ThreadContents {
+        root,
+        replies: history.thread(root.id).collect(),
+    };
+    send_frame(
+        tx,
+        &WebSocketMessage {
+            message_type: MessageType::Thread,
+            data: Some(serde_json::to_string(&contents).unwrap()),
+            data_array: None,
+        },
+    );
+}
@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use tokio::time::{self, Duration};
+
+use crate::{broadcast_message, MessageType, ServerState, SharedState, WebSocketMessage};
+
+/// How long a "start" signal keeps a user marked as typing. Clients resend it
+/// while the user keeps typing, so this only matters when the stop is lost.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// Marks `user_id` as typing or not, broadcasting the typing list if it changed.
+pub fn set_typing(state: &ServerState, user_id: &str, typing: bool) {
+    let changed = {
+        let mut typing_users = state.typing.lock().unwrap();
+        if typing {
+            typing_users
+                .insert(user_id.to_string(), Instant::now())
+                .is_none()
+        } else {
+            typing_users.remove(user_id).is_some()
+        }
+    };
+
+    if changed {
+        broadcast_typing(state);
+    }
+}
+
+fn broadcast_typing(state: &ServerState) {
+    let mut users: Vec<String> = state.typing.lock().unwrap().keys().cloned().collect();
+    users.sort();
+
+    let json = serde_json::to_string(&WebSocketMessage {
+        message_type: MessageType::Typing,
+        data: None,
+        data_array: Some(users),
+    })
+    .unwrap();
+    broadcast_message(state, &json);
+}
+
+/// Drops typing markers that were not refreshed within `TYPING_TIMEOUT`.
+pub async fn expire_typing(state: SharedState) {
+    let mut interval_stream = time::interval(Duration::from_secs(1));
+
+    loop {
+        interval_stream.tick().await;
+        drop_stale(&state);
+    }
+}
+
+// One sweep of `expire_typing`, broadcasting the typing list if it changed
+fn drop_stale(state: &ServerState) {
+    let changed = {
+        let mut typing_users = state.typing.lock().unwrap();
+        let before = typing_users.len();
+        typing_users.retain(|_, since| since.elapsed() < TYPING_TIMEOUT);
+        typing_users.len() != before
+    };
+
+    if changed {
+        broadcast_typing(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+
+    // Typing lists broadcast to a listener so far
+    fn broadcasts(rx: &mut UnboundedReceiver<Message>) -> Vec<serde_json::Value> {
+        let mut lists = Vec::new();
+        while let Ok(frame) = rx.try_recv() {
+            let frame: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            assert_eq!(frame["messageType"], "typing");
+            lists.push(frame["dataArray"].clone());
+        }
+        lists
+    }
+
+    fn listen(state: &ServerState) -> UnboundedReceiver<Message> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        state.peers.lock().unwrap().insert("carol".to_string(), (tx, true));
+        rx
+    }
+
+    #[test]
+    fn only_changes_are_broadcast() {
+        let state = ServerState::new(1, None);
+        let mut rx = listen(&state);
+        set_typing(&state, "bob", true);
+        set_typing(&state, "ann", true);
+        set_typing(&state, "ann", true);
+        set_typing(&state, "ann", false);
+        set_typing(&state, "ann", false);
+        let lists = broadcasts(&mut rx);
+        assert_eq!(lists, [json!(["bob"]), json!(["ann", "bob"]), json!(["bob"])]);
+    }
+
+    #[test]
+    fn markers_expire_unless_refreshed() {
+        let state = ServerState::new(1, None);
+        set_typing(&state, "ann", true);
+        set_typing(&state, "bob", true);
+        let mut rx = listen(&state);
+
+        let long_ago = Instant::now() - TYPING_TIMEOUT;
+        for since in state.typing.lock().unwrap().values_mut() {
+            *since = long_ago;
+        }
+        // Bob is still typing and says so again
+        set_typing(&state, "bob", true);
+        drop_stale(&state);
+        drop_stale(&state);
+        assert_eq!(broadcasts(&mut rx), [json!(["bob"])]);
+    }
+}
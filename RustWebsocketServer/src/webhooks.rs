@@ -0,0 +1,344 @@
+//! Outgoing webhooks: chat events are POSTed as JSON to configured URLs, so
+//! other tools can follow the chat without a WebSocket client.
+//!
+//! Each hook has a queue of its own and delivers in order, retrying failed
+//! deliveries with backoff. With a secret, the body is signed with
+//! HMAC-SHA256 in `X-Chat-Signature: sha256=<hex>`. Only plain `http://`
+//! URLs are supported. Events are sent by the node they happen on, so nodes
+//! sharing a backplane may share the same hooks.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+
+use crate::{now_millis, ChatMessage, ServerState};
+
+/// Attempts made at each delivery before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry; it doubles after each failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long a receiver may take to answer one attempt.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Deliveries kept for `/webhooks`, across all hooks.
+const LOG_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    /// A chat message was posted.
+    Message,
+    /// A user registered.
+    Join,
+    /// A user disconnected.
+    Leave,
+    /// A message mentioned a user; sent once per mentioned user.
+    Mention,
+}
+
+/// Where to send which events, as read from the `CHAT_WEBHOOKS` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Key the body is signed with, unsigned when missing.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Events sent to this hook, all of them when empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+impl Webhook {
+    fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Outcome of one delivery, as listed by `/webhooks`.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub id: u64,
+    pub url: String,
+    pub event: WebhookEvent,
+    pub time: u64,
+    pub attempts: u32,
+    /// Status of the last answer, if the receiver answered at all.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+struct Job {
+    id: u64,
+    event: WebhookEvent,
+    body: String,
+}
+
+/// The configured hooks and what became of recent deliveries.
+#[derive(Default)]
+pub struct Webhooks {
+    hooks: Vec<(Webhook, mpsc::UnboundedSender<Job>)>,
+    next_id: Mutex<u64>,
+    log: Arc<Mutex<VecDeque<Delivery>>>,
+}
+
+impl Webhooks {
+    /// Starts a delivery task for each hook.
+    pub fn start(hooks: Vec<Webhook>) -> Self {
+        let log = Arc::new(Mutex::new(VecDeque::new()));
+        let hooks = hooks
+            .into_iter()
+            .map(|hook| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                info!("Sending webhooks to {}", hook.url);
+                tokio::spawn(deliver_loop(hook.clone(), rx, log.clone()));
+                (hook, tx)
+            })
+            .collect();
+        Self {
+            hooks,
+            next_id: Mutex::new(1),
+            log,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Recent deliveries, oldest first.
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.log.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Hooks listed in the JSON file named by `CHAT_WEBHOOKS`, such as
+/// `[{"url": "http://127.0.0.1:9000/chat", "secret": "s3cret", "events": ["message"]}]`.
+pub fn from_env() -> Vec<Webhook> {
+    let Ok(path) = std::env::var("CHAT_WEBHOOKS") else {
+        return Vec::new();
+    };
+    let file = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Cannot read CHAT_WEBHOOKS file {}: {}", path, e));
+    serde_json::from_str(&file)
+        .unwrap_or_else(|e| panic!("CHAT_WEBHOOKS file {} is not a list of hooks: {}", path, e))
+}
+
+/// Queues `event` for every hook that wants it. `fields` are merged into the
+/// payload next to `event` and `time`.
+pub fn notify(state: &ServerState, event: WebhookEvent, fields: Value) {
+    let webhooks = &state.webhooks;
+    if !webhooks.hooks.iter().any(|(hook, _)| hook.wants(event)) {
+        return;
+    }
+    let mut payload = json!({ "event": event, "time": now_millis() });
+    if let (Some(payload), Value::Object(fields)) = (payload.as_object_mut(), fields) {
+        payload.extend(fields);
+    }
+    let body = payload.to_string();
+
+    for (hook, queue) in &webhooks.hooks {
+        if hook.wants(event) {
+            let id = {
+                let mut next_id = webhooks.next_id.lock().unwrap();
+                *next_id += 1;
+                *next_id - 1
+            };
+            let _ = queue.send(Job {
+                id,
+                event,
+                body: body.clone(),
+            });
+        }
+    }
+}
+
+/// Sends the events for a message just posted: the message itself, then a
+/// mention for each user it mentions.
+pub fn message_posted(state: &ServerState, message: &ChatMessage) {
+    if state.webhooks.is_empty() {
+        return;
+    }
+    notify(state, WebhookEvent::Message, json!({ "message": message }));
+    for mention in &message.mentions {
+        notify(
+            state,
+            WebhookEvent::Mention,
+            json!({ "user": mention.user(), "message": message }),
+        );
+    }
+}
+
+/// Sends a join or leave event for `user_id`.
+pub fn presence_changed(state: &ServerState, event: WebhookEvent, user_id: &str) {
+    if !user_id.is_empty() {
+        notify(state, event, json!({ "user": user_id }));
+    }
+}
+
+/// `sha256=` and the hex HMAC of `body` under `secret`.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+async fn deliver_loop(
+    hook: Webhook,
+    mut jobs: mpsc::UnboundedReceiver<Job>,
+    log: Arc<Mutex<VecDeque<Delivery>>>,
+) {
+    while let Some(job) = jobs.recv().await {
+        let delivery = deliver(&hook, &job).await;
+        match &delivery.error {
+            None => info!(
+                "Webhook {} delivered {:?} event {}",
+                hook.url, job.event, job.id
+            ),
+            Some(e) => warn!(
+                "Webhook {} gave up on {:?} event {} after {} attempt(s): {}",
+                hook.url, job.event, job.id, delivery.attempts, e
+            ),
+        }
+
+        let mut log = log.lock().unwrap();
+        if log.len() == LOG_SIZE {
+            log.pop_front();
+        }
+        log.push_back(delivery);
+    }
+}
+
+// Posts `job` until the receiver accepts it, it is refused for good, or
+// the attempts run out
+async fn deliver(hook: &Webhook, job: &Job) -> Delivery {
+    let mut delivery = Delivery {
+        id: job.id,
+        url: hook.url.clone(),
+        event: job.event,
+        time: now_millis(),
+        attempts: 0,
+        status: None,
+        error: None,
+    };
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        delivery.attempts += 1;
+        let result = time::timeout(REQUEST_TIMEOUT, post(hook, job))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+        let retry = match result {
+            Ok(status) => {
+                delivery.status = Some(status);
+                delivery.error = (!(200..300).contains(&status))
+                    .then(|| format!("receiver answered {}", status));
+                // Other client errors will not go away by asking again
+                status == 429 || status >= 500
+            }
+            Err(e) => {
+                delivery.status = None;
+                delivery.error = Some(e.to_string());
+                true
+            }
+        };
+        if !retry || delivery.attempts == MAX_ATTEMPTS {
+            return delivery;
+        }
+        time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+// Sends one POST and returns the status the receiver answered with
+async fn post(hook: &Webhook, job: &Job) -> io::Result<u16> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+    let rest = hook
+        .url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rust-websocket-server\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\
+         X-Chat-Event: {}\r\nX-Chat-Delivery: {}\r\n",
+        path,
+        host,
+        job.body.len(),
+        serde_json::to_value(job.event).unwrap().as_str().unwrap(),
+        job.id,
+    );
+    if let Some(secret) = &hook.secret {
+        head.push_str(&format!(
+            "X-Chat-Signature: {}\r\n",
+            signature(secret, job.body.as_bytes())
+        ));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    let mut stream = TcpStream::connect(&address).await?;
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(job.body.as_bytes()).await?;
+
+    // Only the status line matters; the rest of the answer is ignored
+    let mut answer = Vec::new();
+    let mut buffer = [0; 256];
+    while !answer.contains(&b'\n') {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        answer.extend_from_slice(&buffer[..read]);
+    }
+    let status_line = String::from_utf8_lossy(&answer);
+    status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no HTTP status in the answer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_bodies() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn filters_events() {
+        let hook = Webhook {
+            url: "http://127.0.0.1/".to_string(),
+            secret: None,
+            events: vec![WebhookEvent::Join, WebhookEvent::Leave],
+        };
+        assert!(hook.wants(WebhookEvent::Join));
+        assert!(!hook.wants(WebhookEvent::Message));
+        assert!(Webhook { events: Vec::new(), ..hook }.wants(WebhookEvent::Mention));
+    }
+}
@@ -0,0 +1,114 @@
+//! Files uploaded over the WebSocket and served by the HTTP server.
+
+mod common;
+
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+
+use common::{start_server_with_http, TestClient};
+use rust_websocket_server::Config;
+
+// GETs `url` and returns the status, waiting for the HTTP server to come up
+// if needed
+async fn get_status(url: &str) -> u16 {
+    let (address, path) = url.strip_prefix("http://").unwrap().split_once('/').unwrap();
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(address).await {
+            stream = Some(connected);
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    let mut stream = stream.expect("HTTP server never came up");
+    let request = format!("GET /{} HTTP/1.1\r\nHost: {}\r\n\r\n", path, address);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await.unwrap();
+    answer.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+// Points every test at one upload directory; the variable is process wide
+fn use_upload_dir() -> PathBuf {
+    let uploads = std::env::temp_dir().join(format!("chat-uploads-{}", std::process::id()));
+    std::env::set_var("CHAT_UPLOAD_DIR", &uploads);
+    uploads
+}
+
+// Uploads `content` as a text file and returns its attachment
+async fn upload(client: &mut TestClient, content: &[u8]) -> Value {
+    let request = json!({ "name": "secret.txt", "mime": "text/plain", "size": content.len() });
+    client.send("upload", &request.to_string()).await;
+    client.send_binary(content).await;
+    let uploaded = client.expect("uploaded").await;
+    serde_json::from_str(uploaded["data"].as_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn deleting_the_last_message_with_a_file_removes_it() {
+    let uploads = use_upload_dir();
+    let (url, http) = start_server_with_http(Config::default()).await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    // The same content twice is one file shared by two messages
+    let mut ids = Vec::new();
+    let mut attachment = Value::Null;
+    for _ in 0..2 {
+        attachment = upload(&mut ann, b"hunter2").await;
+        let message = json!({ "text": "", "attachment": attachment["id"] });
+        ann.send("message", &message.to_string()).await;
+        ids.push(ann.expect_message().await["id"].as_u64().unwrap());
+    }
+    let file = format!("{}{}", http, attachment["url"].as_str().unwrap());
+    assert_eq!(get_status(&file).await, 200);
+
+    ann.send("delete", &ids[0].to_string()).await;
+    ann.expect("delete").await;
+    assert_eq!(get_status(&file).await, 200);
+
+    ann.send("delete", &ids[1].to_string()).await;
+    ann.expect("delete").await;
+    assert_eq!(get_status(&file).await, 404);
+    assert!(!uploads.join(attachment["id"].as_str().unwrap()).exists());
+
+    // Only goes once the other tests are done with it
+    let _ = std::fs::remove_dir(&uploads);
+}
+
+#[tokio::test]
+async fn files_uploaded_but_not_sent_yet_are_kept() {
+    let uploads = use_upload_dir();
+    let (url, http) = start_server_with_http(Config::default()).await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut bob = TestClient::register(&url, "bob").await;
+
+    let attachment = upload(&mut ann, b"correct horse").await;
+    let message = json!({ "text": "", "attachment": attachment["id"] });
+    ann.send("message", &message.to_string()).await;
+    let id = ann.expect_message().await["id"].as_u64().unwrap();
+
+    // Bob uploads the same file and is still writing his message
+    upload(&mut bob, b"correct horse").await;
+    ann.send("delete", &id.to_string()).await;
+    ann.expect("delete").await;
+    let file = format!("{}{}", http, attachment["url"].as_str().unwrap());
+    assert_eq!(get_status(&file).await, 200);
+
+    let message = json!({ "text": "mine too", "attachment": attachment["id"] });
+    bob.send("message", &message.to_string()).await;
+    let sent = bob
+        .expect_frame(|frame| {
+            frame["messageType"] == "message"
+                && frame["data"].as_str().unwrap().contains("mine too")
+        })
+        .await;
+    let sent: Value = serde_json::from_str(sent["data"].as_str().unwrap()).unwrap();
+    assert_eq!(sent["attachment"]["id"], attachment["id"]);
+
+    let _ = std::fs::remove_file(uploads.join(attachment["id"].as_str().unwrap()));
+    let _ = std::fs::remove_dir(&uploads);
+}
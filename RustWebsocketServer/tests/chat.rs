@@ -0,0 +1,319 @@
+mod common;
+
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use common::{start_server, start_server_with, TestClient, MODERATOR_SECRET};
+use rust_websocket_server::{Backplane, Config, InProcess};
+
+#[tokio::test]
+async fn registering_lists_the_user_and_sends_commands() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    let commands = ann.expect("commands").await;
+    assert!(commands["data"].as_str().unwrap().contains(r#""name":"nick""#));
+    ann.expect("moderators").await;
+}
+
+#[tokio::test]
+async fn messages_reach_everyone_including_the_sender() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut bob = TestClient::register(&url, "bob").await;
+
+    ann.say("hello bob").await;
+    for client in [&mut ann, &mut bob] {
+        let message = client.expect_message().await;
+        assert_eq!(message["from"], "ann");
+        assert_eq!(message["message"], "hello bob");
+    }
+}
+
+#[tokio::test]
+async fn sent_text_is_trimmed_like_edits() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    ann.say("  \n").await;
+    ann.say("\t hello  \n").await;
+    let message = ann.expect_message().await;
+    assert_eq!(message["message"], "hello");
+
+    let edit = json!({ "id": message["id"], "text": " hello " });
+    ann.send("edit", &edit.to_string()).await;
+    ann.expect_no("edit").await;
+}
+
+#[tokio::test]
+async fn message_ids_increase() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    ann.say("first").await;
+    ann.say("second").await;
+    let first = ann.expect_message().await;
+    let second = ann.expect_message().await;
+    assert!(second["id"].as_u64() > first["id"].as_u64());
+}
+
+#[tokio::test]
+async fn replies_quote_the_stored_original() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut bob = TestClient::register(&url, "bob").await;
+
+    ann.say("what time is it?").await;
+    let original = bob.expect_message().await;
+
+    // The quoted text comes from the history, not from the client
+    let mut forged = original.clone();
+    forged["message"] = "something else".into();
+    bob.reply(&forged, "noon").await;
+
+    let reply = ann
+        .expect_frame(|frame| frame["data"].as_str().is_some_and(|data| data.contains("noon")))
+        .await;
+    let reply: serde_json::Value = serde_json::from_str(reply["data"].as_str().unwrap()).unwrap();
+    assert_eq!(reply["from"], "bob");
+    assert_eq!(reply["reply_to"]["id"], original["id"]);
+    assert_eq!(reply["reply_to"]["from"], "ann");
+    assert_eq!(reply["reply_to"]["message"], "what time is it?");
+}
+
+#[tokio::test]
+async fn user_list_follows_joins_and_nick_changes() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut bob = TestClient::register(&url, "bob").await;
+
+    let mut users = ann.expect_users(|users| users.len() == 2).await;
+    users.sort();
+    assert_eq!(users, ["ann", "bob"]);
+
+    bob.say("/nick robert").await;
+    bob.expect_frame(|frame| frame["messageType"] == "register" && frame["data"] == "robert")
+        .await;
+    let mut users = ann.expect_users(|users| users.contains(&"robert".to_string())).await;
+    users.sort();
+    assert_eq!(users, ["ann", "robert"]);
+}
+
+#[tokio::test]
+async fn disconnecting_removes_the_user() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let bob = TestClient::register(&url, "bob").await;
+    ann.expect_users(|users| users.len() == 2).await;
+
+    bob.close().await;
+    let users = ann.expect_users(|users| users.len() == 1).await;
+    assert_eq!(users, ["ann"]);
+    let presence = ann
+        .expect_frame(|frame| {
+            frame["messageType"] == "presence"
+                && frame["data"].as_str().unwrap().contains(r#""bob":{"status":"offline""#)
+        })
+        .await;
+    assert!(presence["data"].as_str().unwrap().contains(r#""ann":{"status":"online""#));
+}
+
+#[tokio::test]
+async fn unregistered_clients_hear_nothing() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut lurker = TestClient::connect(&url).await;
+
+    ann.say("anyone there?").await;
+    ann.expect_message().await;
+    lurker.expect_no("message").await;
+}
+
+#[tokio::test]
+async fn only_registered_clients_send_and_register_once() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    // Without registering, neither commands nor messages get through
+    let mut lurker = TestClient::connect(&url).await;
+    lurker.say("/nick ghost").await;
+    lurker.say("boo").await;
+    lurker.expect_no("notice").await;
+    ann.expect_no("message").await;
+
+    ann.send("register", "anna").await;
+    let refused = ann.expect("notice").await;
+    assert!(refused["data"].as_str().unwrap().contains("already registered as ann"));
+    let bob = TestClient::register(&url, "bob").await;
+    let mut users = ann.expect_users(|users| users.len() == 2).await;
+    users.sort();
+    assert_eq!(users, ["ann", "bob"]);
+    bob.close().await;
+}
+
+#[tokio::test]
+async fn nodes_on_a_backplane_share_one_chat() {
+    let hub: Arc<dyn Backplane> = Arc::new(InProcess::default());
+    let node = |node| Config {
+        node,
+        backplane: Some(hub.clone()),
+        ..Config::default()
+    };
+    let (first, second) = (start_server_with(node(0)).await, start_server_with(node(1)).await);
+    let mut ann = TestClient::register(&first, "ann").await;
+    let mut bob = TestClient::register(&second, "bob").await;
+    ann.expect_users(|users| users.len() == 2).await;
+
+    bob.say("hello from the other node").await;
+    let message = ann.expect_message().await;
+    assert_eq!(message["from"], "bob");
+}
+
+#[tokio::test]
+async fn read_markers_are_shared_between_nodes() {
+    let hub: Arc<dyn Backplane> = Arc::new(InProcess::default());
+    let node = |node| Config {
+        node,
+        backplane: Some(hub.clone()),
+        ..Config::default()
+    };
+    let (first, second) = (start_server_with(node(0)).await, start_server_with(node(1)).await);
+    let mut ann = TestClient::register(&first, "ann").await;
+    let mut bob = TestClient::register(&second, "bob").await;
+    ann.expect_users(|users| users.len() == 2).await;
+
+    let mut ids = vec![];
+    for text in ["one", "two"] {
+        ann.say(text).await;
+        ids.push(ann.expect_message().await["id"].as_u64().unwrap());
+        bob.expect_message().await;
+    }
+
+    // Each node hears about the other's marker, and keeps its own
+    let markers = |frame: &Value| -> Value {
+        serde_json::from_str(frame["data"].as_str().unwrap()).unwrap()
+    };
+    bob.send("read", &ids[1].to_string()).await;
+    ann.expect_frame(|frame| {
+        frame["messageType"] == "readmarkers" && markers(frame)["bob"] == ids[1]
+    })
+    .await;
+    ann.send("read", &ids[0].to_string()).await;
+    let both = json!({ "ann": ids[0], "bob": ids[1] });
+    for client in [&mut ann, &mut bob] {
+        let frame = client
+            .expect_frame(|frame| {
+                frame["messageType"] == "readmarkers" && markers(frame)["ann"] == ids[0]
+            })
+            .await;
+        assert_eq!(markers(&frame), both);
+    }
+}
+
+#[tokio::test]
+async fn nodes_keep_their_own_messages_in_order_after_replicating() {
+    let hub: Arc<dyn Backplane> = Arc::new(InProcess::default());
+    let node = |node| Config {
+        node,
+        backplane: Some(hub.clone()),
+        ..Config::default()
+    };
+    let (first, second) = (start_server_with(node(0)).await, start_server_with(node(1)).await);
+    let mut ann = TestClient::register(&first, "ann").await;
+    let mut bob = TestClient::register(&second, "bob").await;
+    ann.expect_users(|users| users.len() == 2).await;
+
+    // Node 1 hands out the first IDs, so node 0 must number its own after them
+    let mut last = 0;
+    for text in ["one", "two", "three"] {
+        bob.say(text).await;
+        last = ann.expect_message().await["id"].as_u64().unwrap();
+    }
+    ann.say("four").await;
+    let id = ann.expect_message().await["id"].as_u64().unwrap();
+    assert!(id > last, "{} came after {}", id, last);
+
+    ann.send("edit", &json!({ "id": id, "text": "four!" }).to_string())
+        .await;
+    let edited = ann.expect("edit").await;
+    assert!(edited["data"].as_str().unwrap().contains("four!"));
+
+    ann.send("history", "{}").await;
+    let page = ann.expect("history").await;
+    let page: Value = serde_json::from_str(page["data"].as_str().unwrap()).unwrap();
+    let texts: Vec<&str> = page["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, ["one", "two", "three", "four!"]);
+
+    ann.send("delete", &id.to_string()).await;
+    assert_eq!(ann.expect("delete").await["data"], id.to_string());
+}
+
+#[tokio::test]
+async fn names_in_use_or_reserved_for_moderators_are_refused() {
+    let config = Config {
+        moderators: ["mod".to_string()].into(),
+        moderator_secret: Some(MODERATOR_SECRET.to_string()),
+        ..Config::default()
+    };
+    let url = start_server_with(config).await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    let mut impostor = TestClient::connect(&url).await;
+    impostor.send("register", "ann").await;
+    let refused = impostor.expect("notice").await;
+    assert!(refused["data"].as_str().unwrap().contains("already taken"));
+
+    let wrong = json!({ "name": "mod", "secret": "guess" });
+    for data in ["mod".to_string(), wrong.to_string()] {
+        impostor.send("register", &data).await;
+        let refused = impostor.expect("notice").await;
+        assert!(refused["data"].as_str().unwrap().contains("moderator secret"));
+    }
+
+    // Renaming cannot get around it either
+    ann.say("/nick mod").await;
+    let refused = ann.expect("notice").await;
+    assert!(refused["data"].as_str().unwrap().contains("moderator"));
+
+    let moderator = TestClient::register_moderator(&url, "mod").await;
+    let mut users = ann.expect_users(|users| users.len() == 2).await;
+    users.sort();
+    assert_eq!(users, ["ann", "mod"]);
+    impostor.expect_no("users").await;
+    moderator.close().await;
+}
+
+#[tokio::test]
+async fn deleting_a_thread_reply_lowers_the_reply_count() {
+    let url = start_server().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    ann.say("root").await;
+    let root = ann.expect_message().await["id"].as_u64().unwrap();
+    let mut replies = vec![];
+    for (text, count) in [("first", 1), ("second", 2)] {
+        let reply = json!({ "text": text, "thread_id": root });
+        ann.send("message", &reply.to_string()).await;
+        replies.push(ann.expect_message().await["id"].as_u64().unwrap());
+        let update = ann.expect("threadupdate").await;
+        let update: Value = serde_json::from_str(update["data"].as_str().unwrap()).unwrap();
+        assert_eq!(update, json!({ "id": root, "reply_count": count }));
+    }
+
+    ann.send("delete", &replies[0].to_string()).await;
+    ann.expect("delete").await;
+    let update = ann.expect("threadupdate").await;
+    let update: Value = serde_json::from_str(update["data"].as_str().unwrap()).unwrap();
+    assert_eq!(update, json!({ "id": root, "reply_count": 1 }));
+
+    ann.send("thread", &root.to_string()).await;
+    let thread = ann.expect("thread").await;
+    let thread: Value = serde_json::from_str(thread["data"].as_str().unwrap()).unwrap();
+    assert_eq!(thread["root"]["reply_count"], 1);
+}
@@ -0,0 +1,212 @@
+//! Runs a server on an ephemeral port and talks to it the way clients do.
+
+// Each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use rust_websocket_server::{serve, Config};
+
+/// Moderator secret of servers whose config sets it to this.
+pub const MODERATOR_SECRET: &str = "m0d3rat0r";
+
+/// How long a client waits for a frame before the test fails.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a client listens before deciding no frame is coming.
+const QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Starts a server with the default settings and returns its URL.
+pub async fn start_server() -> String {
+    start_server_with(Config::default()).await
+}
+
+/// Starts a server with `config` and returns its URL. Both the WebSocket
+/// and the HTTP server listen on ports picked by the OS.
+pub async fn start_server_with(config: Config) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let config = Config {
+        http_addr: "127.0.0.1:0".to_string(),
+        ..config
+    };
+    tokio::spawn(serve(listener, config));
+    url
+}
+
+/// Starts a server with `config` like `start_server_with`, and returns the
+/// base URL of its HTTP server too.
+pub async fn start_server_with_http(config: Config) -> (String, String) {
+    // Take a free port for the HTTP server, which binds it on its own
+    let http_addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let config = Config {
+        http_addr: http_addr.clone(),
+        ..config
+    };
+    tokio::spawn(serve(listener, config));
+    (url, format!("http://{}", http_addr))
+}
+
+pub struct TestClient {
+    pub name: String,
+    outgoing: SplitSink<Stream, Message>,
+    incoming: SplitStream<Stream>,
+}
+
+impl TestClient {
+    /// Connects to `url` without registering.
+    pub async fn connect(url: &str) -> Self {
+        let (stream, _) = connect_async(url).await.expect("cannot connect to the server");
+        let (outgoing, incoming) = stream.split();
+        Self {
+            name: String::new(),
+            outgoing,
+            incoming,
+        }
+    }
+
+    /// Connects to `url` and registers as `name`, returning once the server
+    /// lists the new user.
+    pub async fn register(url: &str, name: &str) -> Self {
+        let mut client = Self::connect(url).await;
+        client.name = name.to_string();
+        client.send("register", name).await;
+        client.expect_users(|users| users.iter().any(|user| user == name)).await;
+        client
+    }
+
+    /// Connects to `url` and registers as moderator `name` with
+    /// `MODERATOR_SECRET`.
+    pub async fn register_moderator(url: &str, name: &str) -> Self {
+        let mut client = Self::connect(url).await;
+        client.name = name.to_string();
+        let data = json!({ "name": name, "secret": MODERATOR_SECRET });
+        client.send("register", &data.to_string()).await;
+        client.expect_users(|users| users.iter().any(|user| user == name)).await;
+        client
+    }
+
+    /// Sends a frame of `message_type` carrying `data`.
+    pub async fn send(&mut self, message_type: &str, data: &str) {
+        let frame = json!({ "messageType": message_type, "data": data });
+        self.outgoing
+            .send(Message::Text(frame.to_string()))
+            .await
+            .expect("cannot send to the server");
+    }
+
+    /// Sends a binary frame, such as a chunk of an upload.
+    pub async fn send_binary(&mut self, data: &[u8]) {
+        self.outgoing
+            .send(Message::Binary(data.to_vec()))
+            .await
+            .expect("cannot send to the server");
+    }
+
+    /// Sends a chat message, or a command when `text` starts with a slash.
+    pub async fn say(&mut self, text: &str) {
+        self.send("message", &json!({ "text": text }).to_string()).await;
+    }
+
+    /// Sends a chat message replying to `original`, a message frame's data.
+    pub async fn reply(&mut self, original: &Value, text: &str) {
+        let reply_to = json!({
+            "id": original["id"],
+            "from": original["from"],
+            "message": original["message"],
+        });
+        let data = json!({ "text": text, "reply_to": reply_to.to_string() });
+        self.send("message", &data.to_string()).await;
+    }
+
+    /// Next frame of `message_type`, skipping frames of other types.
+    pub async fn expect(&mut self, message_type: &str) -> Value {
+        self.expect_frame(|frame| frame["messageType"] == message_type).await
+    }
+
+    /// Next `message` frame, with its data parsed.
+    pub async fn expect_message(&mut self) -> Value {
+        let frame = self.expect("message").await;
+        serde_json::from_str(frame["data"].as_str().unwrap()).unwrap()
+    }
+
+    /// Next user list that satisfies `wanted`.
+    pub async fn expect_users(&mut self, wanted: impl Fn(&[String]) -> bool) -> Vec<String> {
+        let frame = self
+            .expect_frame(|frame| {
+                frame["messageType"] == "users" && wanted(&user_list(frame))
+            })
+            .await;
+        user_list(&frame)
+    }
+
+    /// Next frame that satisfies `wanted`, skipping the others.
+    pub async fn expect_frame(&mut self, wanted: impl Fn(&Value) -> bool) -> Value {
+        let name = self.name.clone();
+        timeout(FRAME_TIMEOUT, async {
+            loop {
+                let frame = self.next_frame().await.expect("connection closed");
+                if wanted(&frame) {
+                    return frame;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{} never got the expected frame", name))
+    }
+
+    /// Fails if a frame of `message_type` arrives shortly.
+    pub async fn expect_no(&mut self, message_type: &str) {
+        let _ = timeout(QUIET_PERIOD, async {
+            while let Some(frame) = self.next_frame().await {
+                assert_ne!(
+                    frame["messageType"], message_type,
+                    "{} got an unexpected frame: {}",
+                    self.name, frame
+                );
+            }
+        })
+        .await;
+    }
+
+    /// Closes the connection with a close handshake.
+    pub async fn close(mut self) {
+        let _ = self.outgoing.close().await;
+    }
+
+    // Next text frame as JSON, `None` once the connection is closed
+    async fn next_frame(&mut self) -> Option<Value> {
+        while let Some(message) = self.incoming.next().await {
+            if let Message::Text(text) = message.ok()? {
+                return Some(serde_json::from_str(&text).expect("frame is not JSON"));
+            }
+        }
+        None
+    }
+}
+
+fn user_list(frame: &Value) -> Vec<String> {
+    frame["dataArray"]
+        .as_array()
+        .map(|users| {
+            users
+                .iter()
+                .filter_map(|user| user.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
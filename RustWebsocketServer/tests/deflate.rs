@@ -0,0 +1,255 @@
+//! permessage-deflate spoken by a client written against the raw socket,
+//! since tungstenite's own client does not support the extension.
+
+mod common;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use common::{start_server, TestClient};
+
+/// How long the client waits for a frame before the test fails.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+struct Frame {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+struct RawClient {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    // Extension the server answered with, if any
+    extension: Option<String>,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl RawClient {
+    // Upgrades a connection to `url`, offering `extensions` when given
+    async fn connect(url: &str, extensions: Option<&str>) -> Self {
+        let address = url.strip_prefix("ws://").unwrap();
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut request = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n",
+            address
+        );
+        if let Some(extensions) = extensions {
+            request.push_str(&format!("Sec-WebSocket-Extensions: {}\r\n", extensions));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut client = Self {
+            stream,
+            buf: Vec::new(),
+            extension: None,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+        };
+        let head = loop {
+            if let Some(end) = client.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head: Vec<u8> = client.buf.drain(..end + 4).collect();
+                break String::from_utf8(head).unwrap();
+            }
+            client.fill().await;
+        };
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+        client.extension = head.lines().find_map(|line| {
+            let (name, value) = line.split_once(": ")?;
+            name.eq_ignore_ascii_case("sec-websocket-extensions")
+                .then(|| value.to_string())
+        });
+        client
+    }
+
+    async fn fill(&mut self) {
+        let mut chunk = [0; 8192];
+        let read = timeout(FRAME_TIMEOUT, self.stream.read(&mut chunk))
+            .await
+            .expect("the server sent nothing")
+            .unwrap();
+        assert!(read > 0, "the server closed the connection");
+        self.buf.extend_from_slice(&chunk[..read]);
+    }
+
+    // Sends one masked frame as it is
+    async fn send_frame(&mut self, fin: bool, rsv1: bool, opcode: u8, payload: &[u8]) {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![(fin as u8) << 7 | (rsv1 as u8) << 6 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        self.stream.write_all(&frame).await.unwrap();
+    }
+
+    // Deflates a whole message the way RFC 7692 puts it on the wire
+    fn deflate(&mut self, message: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(message.len() + 64);
+        self.compress
+            .compress_vec(message, &mut output, FlushCompress::Sync)
+            .unwrap();
+        assert!(output.ends_with(&TRAILER));
+        output.truncate(output.len() - TRAILER.len());
+        output
+    }
+
+    // Next frame, inflated when compressed. Every compressed frame has to
+    // be inflated in order, as they share the server's context.
+    async fn next_frame(&mut self) -> Frame {
+        loop {
+            if self.buf.len() >= 2 {
+                let (header_len, len) = match self.buf[1] & 0x7f {
+                    126 if self.buf.len() >= 4 => {
+                        (4, u16::from_be_bytes([self.buf[2], self.buf[3]]) as usize)
+                    }
+                    127 if self.buf.len() >= 10 => (
+                        10,
+                        u64::from_be_bytes(self.buf[2..10].try_into().unwrap()) as usize,
+                    ),
+                    126 | 127 => (usize::MAX, 0),
+                    len => (2, len as usize),
+                };
+                if header_len != usize::MAX && self.buf.len() >= header_len + len {
+                    assert_eq!(self.buf[1] & 0x80, 0, "server frames are never masked");
+                    let frame: Vec<u8> = self.buf.drain(..header_len + len).collect();
+                    let rsv1 = frame[0] & 0x40 != 0;
+                    let payload = &frame[header_len..];
+                    return Frame {
+                        fin: frame[0] & 0x80 != 0,
+                        rsv1,
+                        opcode: frame[0] & 0x0f,
+                        payload: if rsv1 {
+                            self.inflate(payload)
+                        } else {
+                            payload.to_vec()
+                        },
+                    };
+                }
+            }
+            self.fill().await;
+        }
+    }
+
+    fn inflate(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut input = payload.to_vec();
+        input.extend_from_slice(&TRAILER);
+        let mut output = Vec::with_capacity(input.len() * 4 + 64);
+        let start = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            output.reserve(output.capacity().max(64));
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .unwrap();
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if consumed == input.len() && output.len() < output.capacity() {
+                return output;
+            }
+        }
+    }
+
+    // Next text frame that satisfies `wanted`
+    async fn expect_text(&mut self, wanted: impl Fn(&Value) -> bool) -> (Frame, Value) {
+        loop {
+            let frame = self.next_frame().await;
+            if frame.opcode != 1 {
+                continue;
+            }
+            let value: Value = serde_json::from_slice(&frame.payload).unwrap();
+            if wanted(&value) {
+                return (frame, value);
+            }
+        }
+    }
+}
+
+fn message_text(frame: &Value) -> Option<String> {
+    if frame["messageType"] != "message" {
+        return None;
+    }
+    let message: Value = serde_json::from_str(frame["data"].as_str()?).ok()?;
+    message["message"].as_str().map(str::to_string)
+}
+
+#[tokio::test]
+async fn compressed_fragmented_messages_are_understood() {
+    let url = start_server().await;
+    let mut raw =
+        RawClient::connect(&url, Some("permessage-deflate; client_max_window_bits")).await;
+    assert_eq!(raw.extension.as_deref(), Some("permessage-deflate"));
+
+    let register = json!({ "messageType": "register", "data": "ann" }).to_string();
+    let deflated = raw.deflate(register.as_bytes());
+    raw.send_frame(true, true, 1, &deflated).await;
+    raw.expect_text(|frame| frame["messageType"] == "users")
+        .await;
+
+    // One compressed message in three fragments, with a ping between them
+    let text = "squeeze me ".repeat(30).trim_end().to_string();
+    let message = json!({
+        "messageType": "message",
+        "data": json!({ "text": text }).to_string(),
+    })
+    .to_string();
+    let deflated = raw.deflate(message.as_bytes());
+    let (first, rest) = deflated.split_at(deflated.len() / 3);
+    let (second, third) = rest.split_at(rest.len() / 2);
+    raw.send_frame(false, true, 1, first).await;
+    raw.send_frame(false, false, 0, second).await;
+    raw.send_frame(true, false, 9, b"still there?").await;
+    raw.send_frame(true, false, 0, third).await;
+
+    let pong = loop {
+        let frame = raw.next_frame().await;
+        if frame.opcode == 0xa {
+            break frame;
+        }
+    };
+    assert_eq!(pong.payload, b"still there?");
+
+    // The broadcast is over the size threshold, so it comes back compressed
+    let (frame, echoed) = raw.expect_text(|frame| message_text(frame).is_some()).await;
+    assert!(frame.fin && frame.rsv1);
+    assert_eq!(message_text(&echoed).unwrap(), text);
+}
+
+#[tokio::test]
+async fn clients_without_the_extension_get_plain_frames() {
+    let url = start_server().await;
+    let mut raw = RawClient::connect(&url, None).await;
+    assert_eq!(raw.extension, None);
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    let register = json!({ "messageType": "register", "data": "bob" }).to_string();
+    raw.send_frame(true, false, 1, register.as_bytes()).await;
+    raw.expect_text(|frame| frame["messageType"] == "users")
+        .await;
+
+    let text = "plain as can be ".repeat(30).trim_end().to_string();
+    ann.say(&text).await;
+    let (frame, message) = raw.expect_text(|frame| message_text(frame).is_some()).await;
+    assert!(!frame.rsv1);
+    assert_eq!(message_text(&message).unwrap(), text);
+}
+
+#[tokio::test]
+async fn unacceptable_offers_are_declined() {
+    let url = start_server().await;
+    let raw = RawClient::connect(&url, Some("permessage-deflate; server_max_window_bits=8")).await;
+    assert_eq!(raw.extension, None);
+}
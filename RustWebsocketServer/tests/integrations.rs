@@ -0,0 +1,167 @@
+//! Incoming webhooks posted to the HTTP server.
+
+mod common;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+
+use common::{start_server_with_http, TestClient, MODERATOR_SECRET};
+use rust_websocket_server::{Config, Integration};
+
+struct Answer {
+    status: u16,
+    head: String,
+    body: String,
+}
+
+// POSTs `body` to `url`, waiting for the HTTP server to come up if needed
+async fn post(url: &str, content_type: &str, body: &str) -> Answer {
+    let (address, path) = url.strip_prefix("http://").unwrap().split_once('/').unwrap();
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(address).await {
+            stream = Some(connected);
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    let mut stream = stream.expect("HTTP server never came up");
+    let request = format!(
+        "POST /{} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        address,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await.unwrap();
+
+    let (head, body) = answer.split_once("\r\n\r\n").unwrap();
+    Answer {
+        status: head.split(' ').nth(1).unwrap().parse().unwrap(),
+        head: head.to_string(),
+        body: body.to_string(),
+    }
+}
+
+async fn post_json(url: &str, body: &str) -> Answer {
+    post(url, "application/json", body).await
+}
+
+fn ci(rate_limit: Option<u32>) -> Config {
+    Config {
+        moderators: ["ann".to_string()].into(),
+        moderator_secret: Some(MODERATOR_SECRET.to_string()),
+        integrations: vec![Integration {
+            name: "ci".to_string(),
+            token: "t0k3n".to_string(),
+            rate_limit,
+        }],
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn payloads_are_posted_as_the_integration() {
+    let (url, http) = start_server_with_http(ci(None)).await;
+    let mut ann = TestClient::register_moderator(&url, "ann").await;
+
+    let answer = post_json(
+        &format!("{}/hooks/t0k3n", http),
+        r#"{"text": "Build <https://ci/12|#12> passed"}"#,
+    )
+    .await;
+    assert_eq!((answer.status, answer.body.as_str()), (200, "ok"));
+
+    let message = ann.expect_message().await;
+    assert_eq!(message["from"], "ci");
+    assert_eq!(message["message"], "Build #12 (https://ci/12) passed");
+}
+
+#[tokio::test]
+async fn slack_form_payloads_are_accepted() {
+    let (url, http) = start_server_with_http(ci(None)).await;
+    let mut ann = TestClient::register_moderator(&url, "ann").await;
+
+    let payload = r#"{"text":"Deploy finished","attachments":[{"title":"Release 1.2","title_link":"https://ci/r/1.2","text":"3 services & 1 job"}]}"#;
+    let encoded: String = payload
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (byte as char).to_string(),
+            b' ' => "+".to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect();
+    let answer = post(
+        &format!("{}/hooks/t0k3n", http),
+        "application/x-www-form-urlencoded",
+        &format!("payload={}", encoded),
+    )
+    .await;
+    assert_eq!(answer.status, 200);
+
+    let message = ann.expect_message().await;
+    assert_eq!(
+        message["message"],
+        "Deploy finished\nRelease 1.2 (https://ci/r/1.2)\n3 services & 1 job"
+    );
+}
+
+#[tokio::test]
+async fn bad_payloads_are_refused() {
+    let (_url, http) = start_server_with_http(ci(None)).await;
+    let hook = format!("{}/hooks/t0k3n", http);
+
+    assert_eq!(post_json(&hook, "not json").await.status, 400);
+    assert_eq!(post_json(&hook, r#"{"text": "  "}"#).await.body, "no_text");
+    let unknown = format!("{}/hooks/nope", http);
+    assert_eq!(post_json(&unknown, r#"{"text": "hi"}"#).await.status, 404);
+}
+
+#[tokio::test]
+async fn each_token_is_rate_limited() {
+    let (_url, http) = start_server_with_http(ci(Some(1))).await;
+    let hook = format!("{}/hooks/t0k3n", http);
+
+    assert_eq!(post_json(&hook, r#"{"text": "one"}"#).await.status, 200);
+    let limited = post_json(&hook, r#"{"text": "two"}"#).await;
+    assert_eq!(limited.status, 429);
+    assert!(limited.head.contains("Retry-After: "), "{}", limited.head);
+}
+
+#[tokio::test]
+async fn moderators_add_and_revoke_tokens() {
+    let (url, http) = start_server_with_http(ci(None)).await;
+    let mut ann = TestClient::register_moderator(&url, "ann").await;
+    let mut bob = TestClient::register(&url, "bob").await;
+
+    bob.say("/hook revoke ci").await;
+    let refused = bob.expect("notice").await;
+    assert!(refused["data"].as_str().unwrap().contains("Only moderators"));
+
+    ann.say("/hook add muted 0").await;
+    assert!(ann.expect("notice").await["data"].as_str().unwrap().contains("positive"));
+
+    ann.say("/hook add deploy 5").await;
+    let added = ann.expect("notice").await;
+    let token = added["data"]
+        .as_str()
+        .unwrap()
+        .split("/hooks/")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next())
+        .unwrap()
+        .to_string();
+    let deploy = format!("{}/hooks/{}", http, token);
+    assert_eq!(post_json(&deploy, r#"{"text": "shipped"}"#).await.status, 200);
+    assert_eq!(ann.expect_message().await["from"], "deploy");
+
+    ann.say("/hook revoke deploy").await;
+    ann.expect("notice").await;
+    assert_eq!(post_json(&deploy, r#"{"text": "again"}"#).await.status, 404);
+    let ci = format!("{}/hooks/t0k3n", http);
+    assert_eq!(post_json(&ci, r#"{"text": "still here"}"#).await.status, 200);
+}
@@ -0,0 +1,166 @@
+//! IRC clients and WebSocket clients sharing one chat.
+
+mod common;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+use common::{start_server_with, TestClient};
+use rust_websocket_server::Config;
+
+/// How long an IRC client waits for a line before the test fails.
+const LINE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Starts a server with an IRC port, returning its WebSocket URL and the
+// IRC address
+async fn start_server_with_irc() -> (String, String) {
+    // Take a free port for the gateway, which binds it on its own
+    let irc_addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let config = Config {
+        irc_addr: Some(irc_addr.clone()),
+        ..Config::default()
+    };
+    (start_server_with(config).await, irc_addr)
+}
+
+struct IrcClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl IrcClient {
+    // Connects, waiting for the gateway to come up if needed
+    async fn connect(addr: &str) -> Self {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect(addr).await {
+                let (reader, writer) = stream.into_split();
+                return Self {
+                    reader: BufReader::new(reader),
+                    writer,
+                };
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        panic!("IRC gateway never came up");
+    }
+
+    // Connects and registers as `nick`, returning once the names are listed
+    async fn register(addr: &str, nick: &str) -> Self {
+        let mut client = Self::connect(addr).await;
+        client.send(&format!("NICK {}", nick)).await;
+        client.send(&format!("USER {} 0 * :{}", nick, nick)).await;
+        client.expect(" 001 ").await;
+        client.expect(" 366 ").await;
+        client
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await
+            .unwrap();
+    }
+
+    // Next line containing `wanted`, skipping the others
+    async fn expect(&mut self, wanted: &str) -> String {
+        timeout(LINE_TIMEOUT, async {
+            loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).await.unwrap() == 0 {
+                    panic!("connection closed waiting for {:?}", wanted);
+                }
+                if line.contains(wanted) {
+                    return line.trim_end().to_string();
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no line with {:?} arrived", wanted))
+    }
+}
+
+#[tokio::test]
+async fn irc_users_appear_in_the_user_list() {
+    let (url, irc) = start_server_with_irc().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    let mut bob = IrcClient::connect(&irc).await;
+    bob.send("CAP LS 302").await;
+    bob.send("NICK bob").await;
+    bob.send("USER bob 0 * :Bob").await;
+    bob.expect(":chat 001 bob :Welcome").await;
+    bob.expect(":bob!bob@chat JOIN #chat").await;
+    assert_eq!(bob.expect(" 353 ").await, ":chat 353 bob = #chat :ann bob");
+
+    ann.expect_users(|users| users.iter().any(|user| user == "bob")).await;
+
+    // People joining and leaving show up on IRC
+    let carl = TestClient::register(&url, "carl").await;
+    bob.expect(":carl!carl@chat JOIN #chat").await;
+    carl.close().await;
+    bob.expect(":carl!carl@chat QUIT").await;
+
+    bob.send("QUIT :bye").await;
+    ann.expect_users(|users| !users.iter().any(|user| user == "bob")).await;
+}
+
+#[tokio::test]
+async fn messages_flow_both_ways() {
+    let (url, irc) = start_server_with_irc().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut bob = IrcClient::register(&irc, "bob").await;
+
+    bob.send("PRIVMSG #chat :hello from irc").await;
+    let message = ann.expect_message().await;
+    assert_eq!(message["from"], "bob");
+    assert_eq!(message["message"], "hello from irc");
+
+    ann.say("hello from the web").await;
+    bob.expect(":ann!ann@chat PRIVMSG #chat :hello from the web").await;
+
+    bob.send("PRIVMSG #chat :\u{1}ACTION waves\u{1}").await;
+    let notice = ann.expect("notice").await;
+    assert_eq!(notice["data"], "* bob waves");
+}
+
+#[tokio::test]
+async fn private_messages_reach_one_user() {
+    let (url, irc) = start_server_with_irc().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+    let mut bob = IrcClient::register(&irc, "bob").await;
+
+    bob.send("PRIVMSG ann :psst").await;
+    assert_eq!(ann.expect("notice").await["data"], "[bob -> you] psst");
+
+    ann.say("/msg bob hi back").await;
+    bob.expect(":ann!ann@chat PRIVMSG bob :hi back").await;
+
+    bob.send("PRIVMSG nobody :hello?").await;
+    bob.expect(" 401 bob nobody ").await;
+}
+
+#[tokio::test]
+async fn nicknames_follow_the_user_registry() {
+    let (url, irc) = start_server_with_irc().await;
+    let mut ann = TestClient::register(&url, "ann").await;
+
+    let mut client = IrcClient::connect(&irc).await;
+    client.send("NICK ann").await;
+    client.expect(" 433 * ann :Nickname is already in use").await;
+    client.send("NICK bob").await;
+    client.send("USER bob 0 * :Bob").await;
+    client.expect(" 001 bob ").await;
+
+    client.send("NICK robert").await;
+    client.expect(":bob!bob@chat NICK :robert").await;
+    ann.expect_users(|users| users.iter().any(|user| user == "robert")).await;
+
+    client.send("NAMES #chat").await;
+    assert_eq!(client.expect(" 353 ").await, ":chat 353 robert = #chat :ann robert");
+}
@@ -0,0 +1,160 @@
+//! Outgoing webhooks delivered to a receiver running in the test.
+
+mod common;
+
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+
+use common::{start_server_with, TestClient, MODERATOR_SECRET};
+use rust_websocket_server::{Config, Webhook, WebhookEvent};
+
+/// Long enough for a delivery that needs one retry.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct Delivery {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl Delivery {
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+// Accepts POSTs, answering with `statuses` in turn and 200 once they run
+// out, and hands every request it gets to the test
+async fn start_receiver(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<Delivery>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut statuses = statuses.into_iter();
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut stream = BufReader::new(stream);
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(": ") {
+                    headers.insert(name.to_ascii_lowercase(), value.to_string());
+                }
+            }
+            let length = headers["content-length"].parse().unwrap();
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body).await.unwrap();
+
+            let status = statuses.next().unwrap_or(200);
+            let answer = format!("HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n", status);
+            stream.get_mut().write_all(answer.as_bytes()).await.unwrap();
+            let _ = tx.send(Delivery {
+                headers,
+                body: String::from_utf8(body).unwrap(),
+            });
+        }
+    });
+    (url, rx)
+}
+
+async fn next(deliveries: &mut mpsc::UnboundedReceiver<Delivery>) -> Delivery {
+    timeout(DELIVERY_TIMEOUT, deliveries.recv())
+        .await
+        .expect("no delivery arrived")
+        .unwrap()
+}
+
+fn hook(url: &str, events: &[WebhookEvent]) -> Config {
+    Config {
+        webhooks: vec![Webhook {
+            url: url.to_string(),
+            secret: Some("s3cret".to_string()),
+            events: events.to_vec(),
+        }],
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn chat_events_are_posted_and_signed() {
+    let (url, mut deliveries) = start_receiver(Vec::new()).await;
+    let server = start_server_with(hook(&url, &[])).await;
+
+    let mut ann = TestClient::register(&server, "ann").await;
+    let join = next(&mut deliveries).await;
+    assert_eq!(join.headers["x-chat-event"], "join");
+    assert_eq!(join.json()["user"], "ann");
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(join.body.as_bytes());
+    let expected = format!("sha256={:x}", mac.finalize().into_bytes());
+    assert_eq!(join.headers["x-chat-signature"], expected);
+
+    let bob = TestClient::register(&server, "bob").await;
+    assert_eq!(next(&mut deliveries).await.json()["user"], "bob");
+
+    ann.say("hi @bob").await;
+    let message = next(&mut deliveries).await.json();
+    assert_eq!(message["event"], "message");
+    assert_eq!(message["message"]["from"], "ann");
+    assert_eq!(message["message"]["message"], "hi @bob");
+    let mention = next(&mut deliveries).await.json();
+    assert_eq!(mention["event"], "mention");
+    assert_eq!(mention["user"], "bob");
+    assert_eq!(mention["message"]["id"], message["message"]["id"]);
+
+    bob.close().await;
+    let leave = next(&mut deliveries).await.json();
+    assert_eq!((&leave["event"], &leave["user"]), (&"leave".into(), &"bob".into()));
+}
+
+#[tokio::test]
+async fn hooks_only_get_the_events_they_ask_for() {
+    let (url, mut deliveries) = start_receiver(Vec::new()).await;
+    let server = start_server_with(hook(&url, &[WebhookEvent::Leave])).await;
+
+    let mut ann = TestClient::register(&server, "ann").await;
+    ann.say("nobody hears this").await;
+    ann.expect_message().await;
+    ann.close().await;
+
+    let leave = next(&mut deliveries).await;
+    assert_eq!(leave.json()["event"], "leave");
+    assert!(timeout(Duration::from_millis(200), deliveries.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_and_logged() {
+    let (url, mut deliveries) = start_receiver(vec![503]).await;
+    let config = Config {
+        moderators: ["ann".to_string()].into(),
+        moderator_secret: Some(MODERATOR_SECRET.to_string()),
+        ..hook(&url, &[WebhookEvent::Join])
+    };
+    let server = start_server_with(config).await;
+
+    let mut ann = TestClient::register_moderator(&server, "ann").await;
+    let first = next(&mut deliveries).await;
+    let second = next(&mut deliveries).await;
+    assert_eq!(first.body, second.body);
+    assert_eq!(first.headers["x-chat-delivery"], second.headers["x-chat-delivery"]);
+
+    // The log is written once the delivery is over, just after the receiver answers
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    ann.say("/webhooks").await;
+    let notice = ann.expect("notice").await;
+    let log = notice["data"].as_str().unwrap();
+    assert!(log.contains("Join"), "{}", log);
+    assert!(log.contains("delivered (200) after 2 attempt(s)"), "{}", log);
+}
@@ -9,6 +9,7 @@ interface User {
 }
 
 interface Message {
+	id: number;
 	from: string;
 	message: string;
 	time: number;
@@ -16,6 +17,7 @@ interface Message {
 }
 
 let users: User[] = [];
+let nextMessageId = 1;
 
 console.log(`Listening on port ${PORT}`);
 
@@ -49,6 +51,7 @@ wss.on('connection', (ws) => {
 							: undefined;
 
 						const messageObj: Message = {
+							id: nextMessageId++,
 							from: sender.nick,
 							message: messageText,
 							time: Date.now(),
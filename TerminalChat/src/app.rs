@@ -0,0 +1,272 @@
+//! State of the terminal client: what the server told us and what the user is
+//! typing. Frames and key presses come in, frames to send go out; drawing is
+//! left to `ui`.
+
+use chat_protocol::{
+    ChatMessage, MessageData, MessageType, RegisterData, ReplyData, WebSocketMessage,
+};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+/// Messages loaded when joining and each time the user scrolls past the
+/// oldest one.
+const PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Clone)]
+pub enum Entry {
+    Message(ChatMessage),
+    /// Text from the server, such as command output.
+    Notice(String),
+}
+
+#[derive(Deserialize)]
+struct HistoryPage {
+    messages: Vec<ChatMessage>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct ThreadUpdate {
+    id: u64,
+    reply_count: usize,
+}
+
+pub struct App {
+    pub name: String,
+    pub entries: Vec<Entry>,
+    pub users: Vec<String>,
+    pub typing: Vec<String>,
+    pub topic: Option<String>,
+    pub input: String,
+    /// Entry highlighted while picking a message to reply to.
+    pub selected: Option<usize>,
+    pub replying_to: Option<ChatMessage>,
+    /// Lines scrolled up from the newest message.
+    pub scroll: usize,
+    /// Height of the message pane and whether its oldest line is in view,
+    /// as of the last draw.
+    pub page_height: usize,
+    pub at_top: bool,
+    /// Whether the server has messages older than the oldest loaded one.
+    has_more: bool,
+    loading_history: bool,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            entries: Vec::new(),
+            users: Vec::new(),
+            typing: Vec::new(),
+            topic: None,
+            input: String::new(),
+            selected: None,
+            replying_to: None,
+            scroll: 0,
+            page_height: 0,
+            at_top: false,
+            has_more: false,
+            loading_history: false,
+            quit: false,
+        }
+    }
+
+    /// Frames to send right after connecting: registration, with the
+    /// moderator secret if there is one, and the latest messages. Servers
+    /// without history just ignore the second one.
+    pub fn join(&mut self, secret: Option<String>) -> Vec<WebSocketMessage> {
+        self.loading_history = true;
+        let register = RegisterData {
+            name: self.name.clone(),
+            secret,
+        };
+        vec![
+            frame(MessageType::Register, register.to_data()),
+            frame(MessageType::History, format!(r#"{{"limit":{}}}"#, PAGE_SIZE)),
+        ]
+    }
+
+    pub fn handle_frame(&mut self, frame: WebSocketMessage) {
+        let data = frame.data.unwrap_or_default();
+        match frame.message_type {
+            MessageType::Users => self.users = frame.data_array.unwrap_or_default(),
+            MessageType::Typing => self.typing = frame.data_array.unwrap_or_default(),
+            MessageType::Register => self.name = data,
+            MessageType::Topic => self.topic = Some(data),
+            MessageType::Notice => self.entries.push(Entry::Notice(data)),
+            MessageType::Message => {
+                if let Ok(message) = serde_json::from_str::<ChatMessage>(&data) {
+                    // Thread replies only show up as a count on their root
+                    if message.thread_id.is_none() && self.position(message.id).is_none() {
+                        self.entries.push(Entry::Message(message));
+                    }
+                }
+            }
+            MessageType::Edit => {
+                if let Ok(edited) = serde_json::from_str::<ChatMessage>(&data) {
+                    if let Some(message) = self.message_mut(edited.id) {
+                        *message = edited;
+                    }
+                }
+            }
+            MessageType::Delete => {
+                if let Some(message) = data.parse().ok().and_then(|id| self.message_mut(id)) {
+                    message.deleted = true;
+                    message.message.clear();
+                }
+            }
+            MessageType::ThreadUpdate => {
+                if let Ok(update) = serde_json::from_str::<ThreadUpdate>(&data) {
+                    if let Some(message) = self.message_mut(update.id) {
+                        message.reply_count = update.reply_count;
+                    }
+                }
+            }
+            MessageType::History => {
+                if let Ok(page) = serde_json::from_str::<HistoryPage>(&data) {
+                    self.prepend(page);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles a key press, returning a frame to send if it calls for one.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<WebSocketMessage> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => self.quit = true,
+            KeyCode::Up => self.select(-1),
+            KeyCode::Down => self.select(1),
+            KeyCode::Esc => {
+                self.selected = None;
+                self.replying_to = None;
+            }
+            KeyCode::PageUp => {
+                if self.at_top {
+                    return self.load_older();
+                }
+                self.scroll += self.page_height.max(1);
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(self.page_height.max(1)),
+            KeyCode::End if ctrl => self.scroll = 0,
+            KeyCode::Enter => return self.enter(),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            _ => {}
+        }
+        None
+    }
+
+    // Enter picks the selected message as the one to reply to, or sends
+    // what was typed
+    fn enter(&mut self) -> Option<WebSocketMessage> {
+        if let Some(index) = self.selected.take() {
+            if let Some(Entry::Message(message)) = self.entries.get(index) {
+                self.replying_to = Some(message.clone());
+            }
+            return None;
+        }
+        if self.input.trim().is_empty() {
+            return None;
+        }
+
+        let reply_to = self.replying_to.take().map(|message| {
+            let quoted = ReplyData {
+                id: message.id,
+                from: message.from,
+                message: message.message,
+                deleted: false,
+            };
+            serde_json::to_string(&quoted).unwrap()
+        });
+        let data = MessageData {
+            text: std::mem::take(&mut self.input),
+            reply_to,
+            ..Default::default()
+        };
+        self.scroll = 0;
+        Some(frame(MessageType::Message, serde_json::to_string(&data).unwrap()))
+    }
+
+    // Moves the selection among messages, starting from the newest one
+    fn select(&mut self, step: isize) {
+        let messages: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| matches!(entry, Entry::Message(message) if !message.deleted))
+            .map(|(index, _)| index)
+            .collect();
+        let Some(&newest) = messages.last() else {
+            return;
+        };
+        self.selected = match self.selected.and_then(|s| messages.iter().position(|&i| i == s)) {
+            None if step < 0 => Some(newest),
+            None => None,
+            Some(at) => {
+                let next = at as isize + step;
+                if next >= messages.len() as isize {
+                    None
+                } else {
+                    Some(messages[next.max(0) as usize])
+                }
+            }
+        };
+    }
+
+    fn load_older(&mut self) -> Option<WebSocketMessage> {
+        if !self.has_more || self.loading_history {
+            return None;
+        }
+        let oldest = self.entries.iter().find_map(|entry| match entry {
+            Entry::Message(message) => Some(message.id),
+            Entry::Notice(_) => None,
+        })?;
+        self.loading_history = true;
+        let request = format!(r#"{{"before":{},"limit":{}}}"#, oldest, PAGE_SIZE);
+        Some(frame(MessageType::History, request))
+    }
+
+    // Puts a page of older messages in front of those already loaded
+    fn prepend(&mut self, page: HistoryPage) {
+        self.loading_history = false;
+        self.has_more = page.has_more;
+        let older: Vec<Entry> = page
+            .messages
+            .into_iter()
+            .filter(|message| self.position(message.id).is_none())
+            .map(Entry::Message)
+            .collect();
+        if let Some(selected) = &mut self.selected {
+            *selected += older.len();
+        }
+        self.entries.splice(0..0, older);
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| matches!(entry, Entry::Message(message) if message.id == id))
+    }
+
+    fn message_mut(&mut self, id: u64) -> Option<&mut ChatMessage> {
+        let index = self.position(id)?;
+        match &mut self.entries[index] {
+            Entry::Message(message) => Some(message),
+            Entry::Notice(_) => None,
+        }
+    }
+}
+
+fn frame(message_type: MessageType, data: String) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type,
+        data: Some(data),
+        data_array: None,
+    }
+}
@@ -0,0 +1,98 @@
+//! Terminal client for the chat. Speaks the same protocol as YewChat, so it
+//! works against the Rust server as well as the TypeScript one.
+
+use clap::Parser;
+use crossterm::event::{Event, EventStream, KeyEventKind};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use chat_protocol::WebSocketMessage;
+
+mod app;
+mod ui;
+
+use app::App;
+
+#[derive(Parser)]
+#[command(about = "Chat from the terminal")]
+struct Args {
+    /// Name to register as
+    name: String,
+
+    /// Server to connect to
+    #[arg(long, default_value = "ws://127.0.0.1:8080")]
+    url: String,
+
+    /// Moderator secret, needed to register under a moderator's name
+    #[arg(long)]
+    secret: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let (stream, _) = match connect_async(&args.url).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("Cannot connect to {}: {}", args.url, e);
+            std::process::exit(1);
+        }
+    };
+    let (mut outgoing, mut incoming) = stream.split();
+
+    let mut app = App::new(args.name);
+    for frame in app.join(args.secret) {
+        let _ = outgoing.send(text(&frame)).await;
+    }
+
+    let mut terminal = ratatui::init();
+    let mut events = EventStream::new();
+    let mut closed = None;
+    while !app.quit {
+        terminal.draw(|frame| ui::draw(frame, &mut app))?;
+
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind != KeyEventKind::Release => {
+                    if let Some(frame) = app.handle_key(key) {
+                        if outgoing.send(text(&frame)).await.is_err() {
+                            closed = Some("Lost the connection to the server");
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(frame) = serde_json::from_str::<WebSocketMessage>(&text) {
+                        app.handle_frame(frame);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    closed = Some("The server closed the connection");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) => {
+                    closed = Some("Lost the connection to the server");
+                    break;
+                }
+            },
+        }
+    }
+
+    ratatui::restore();
+    let _ = outgoing.close().await;
+    if let Some(reason) = closed {
+        eprintln!("{}", reason);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn text(frame: &WebSocketMessage) -> Message {
+    Message::Text(serde_json::to_string(frame).unwrap())
+}
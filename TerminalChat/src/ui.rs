@@ -0,0 +1,252 @@
+//! Draws the message pane, the user list and the input box.
+
+use chrono::{DateTime, Local};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph};
+use ratatui::Frame;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use chat_protocol::ChatMessage;
+
+use crate::app::{App, Entry};
+
+const HELP: &str = " Enter send · ↑↓ pick a reply · Esc cancel · PgUp/PgDn scroll · Ctrl+C quit ";
+
+/// Colors names are drawn in, picked by a hash of the name.
+const NAME_COLORS: &[Color] = &[
+    Color::Cyan,
+    Color::Green,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Blue,
+    Color::LightRed,
+];
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, input] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+    let [messages, users] =
+        Layout::horizontal([Constraint::Min(20), Constraint::Length(22)]).areas(main);
+
+    draw_messages(frame, app, messages);
+    draw_users(frame, app, users);
+    draw_input(frame, app, input);
+}
+
+fn draw_messages(frame: &mut Frame, app: &mut App, area: Rect) {
+    let title = match &app.topic {
+        Some(topic) => format!(" {} ", topic),
+        None => " Chat ".to_string(),
+    };
+    let block = Block::bordered().title(title);
+    let inner = block.inner(area);
+    let width = inner.width as usize;
+
+    // Who else is typing takes the last line
+    let typing: Vec<&str> = app
+        .typing
+        .iter()
+        .filter(|user| **user != app.name)
+        .map(String::as_str)
+        .collect();
+    let height = (inner.height as usize).saturating_sub(usize::from(!typing.is_empty()));
+
+    // Lay out every entry, remembering which lines the selected one covers
+    let mut lines = Vec::new();
+    let mut selected_lines = None;
+    for (index, entry) in app.entries.iter().enumerate() {
+        let first = lines.len();
+        let selected = app.selected == Some(index);
+        entry_lines(entry, &app.name, selected, width, &mut lines);
+        if selected {
+            selected_lines = Some(first..lines.len());
+        }
+    }
+
+    // Scroll just enough to show the selected message, and never past the top
+    let max_scroll = lines.len().saturating_sub(height);
+    app.scroll = app.scroll.min(max_scroll);
+    if let Some(range) = selected_lines {
+        let bottom = lines.len() - app.scroll;
+        if range.end > bottom {
+            app.scroll = lines.len() - range.end;
+        } else if range.start < bottom.saturating_sub(height) {
+            app.scroll = lines.len() - (range.start + height).min(lines.len());
+        }
+    }
+    app.scroll = app.scroll.min(max_scroll);
+    app.page_height = height;
+    app.at_top = app.scroll == max_scroll;
+
+    let end = lines.len() - app.scroll;
+    let start = end.saturating_sub(height);
+    let mut visible: Vec<Line> = lines.drain(start..end).collect();
+    if !typing.is_empty() {
+        visible.resize(height, Line::default());
+        visible.push(Line::from(format!("{} typing…", typing.join(", "))).dark_gray());
+    }
+
+    frame.render_widget(Paragraph::new(visible).block(block), area);
+}
+
+// Appends the lines showing `entry`, wrapped to `width`
+fn entry_lines(
+    entry: &Entry,
+    me: &str,
+    selected: bool,
+    width: usize,
+    lines: &mut Vec<Line<'static>>,
+) {
+    let first = lines.len();
+    match entry {
+        Entry::Notice(text) => {
+            for line in text.lines() {
+                for chunk in wrap(line, width) {
+                    lines.push(Line::from(chunk).dark_gray().italic());
+                }
+            }
+        }
+        Entry::Message(message) => message_lines(message, me, width, lines),
+    }
+    if selected {
+        for line in &mut lines[first..] {
+            line.style = line.style.add_modifier(Modifier::REVERSED);
+        }
+    }
+}
+
+fn message_lines(message: &ChatMessage, me: &str, width: usize, lines: &mut Vec<Line<'static>>) {
+    if let Some(quoted) = &message.reply_to {
+        let text = if quoted.deleted {
+            "(deleted)".to_string()
+        } else {
+            quoted.message.replace('\n', " ")
+        };
+        let quote = format!("  ↳ {}: {}", quoted.from, text);
+        let quote = wrap(&quote, width).into_iter().next().unwrap_or_default();
+        lines.push(Line::from(quote).dark_gray());
+    }
+
+    let time = message
+        .time
+        .and_then(DateTime::from_timestamp_millis)
+        .map(|time| time.with_timezone(&Local).format("%H:%M ").to_string())
+        .unwrap_or_default();
+    let name = format!("{}: ", message.from);
+    let indent = time.width() + name.width();
+    let prefix = vec![
+        Span::from(time).dark_gray(),
+        Span::styled(name, Style::new().fg(name_color(&message.from)).bold()),
+    ];
+
+    let mut text = if message.deleted {
+        "(deleted)".to_string()
+    } else {
+        message.message.clone()
+    };
+    if message.edited_at.is_some() && !message.deleted {
+        text.push_str(" (edited)");
+    }
+    if message.reply_count > 0 {
+        text.push_str(&format!(" [{} replies]", message.reply_count));
+    }
+    let style = if message.deleted {
+        Style::new().dark_gray().italic()
+    } else if text.contains(&format!("@{}", me)) {
+        Style::new().yellow()
+    } else {
+        Style::new()
+    };
+
+    // Continuation lines line up under the text unless the prefix is too wide
+    let indent = if indent * 2 < width { indent } else { 2 };
+    let mut first = true;
+    for line in text.split('\n') {
+        for chunk in wrap(line, width.saturating_sub(indent)) {
+            let mut spans = if first {
+                prefix.clone()
+            } else {
+                vec![Span::raw(" ".repeat(indent))]
+            };
+            spans.push(Span::styled(chunk, style));
+            lines.push(Line::from(spans));
+            first = false;
+        }
+    }
+}
+
+fn draw_users(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .users
+        .iter()
+        .map(|user| {
+            let mut line = Line::from(Span::styled(user.clone(), Style::new().fg(name_color(user))));
+            if *user == app.name {
+                line.push_span(Span::from(" (you)").dark_gray());
+            }
+            ListItem::new(line)
+        })
+        .collect();
+    let title = format!(" Users ({}) ", app.users.len());
+    frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+}
+
+fn draw_input(frame: &mut Frame, app: &App, area: Rect) {
+    let title = match &app.replying_to {
+        Some(message) => format!(" Reply to {} ", message.from),
+        None if app.selected.is_some() => " Enter to reply to the highlighted message ".to_string(),
+        None => format!(" {} ", app.name),
+    };
+    let block = Block::bordered()
+        .title(title)
+        .title_bottom(Line::from(HELP).dark_gray());
+    let inner = block.inner(area);
+
+    // Keep the end of long input in view
+    let width = inner.width.saturating_sub(1) as usize;
+    let mut shown = app.input.as_str();
+    while shown.width() > width {
+        let mut chars = shown.chars();
+        chars.next();
+        shown = chars.as_str();
+    }
+    frame.render_widget(Paragraph::new(shown.to_string()).block(block), area);
+    frame.set_cursor_position(Position::new(inner.x + shown.width() as u16, inner.y));
+}
+
+fn name_color(name: &str) -> Color {
+    let hash = name.bytes().fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+    NAME_COLORS[hash % NAME_COLORS.len()]
+}
+
+// Splits `text` into lines at most `width` columns wide, preferring to break
+// at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+    for word in text.split_inclusive(' ') {
+        let word_width = word.width();
+        if line_width + word_width > width && !line.is_empty() {
+            lines.push(std::mem::take(&mut line).trim_end().to_string());
+            line_width = 0;
+        }
+        // Words longer than a line are cut wherever the line ends
+        for c in word.chars() {
+            let char_width = c.width().unwrap_or(0);
+            if line_width + char_width > width && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+            line.push(c);
+            line_width += char_width;
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
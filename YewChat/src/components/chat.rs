@@ -1,49 +1,115 @@
-use serde::{Deserialize, Serialize};
-use web_sys::HtmlInputElement;
+use serde::Deserialize;
+use gloo_net::websocket::Message;
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+    HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement, Notification, NotificationOptions,
+    NotificationPermission,
+};
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
-use chrono::{DateTime, Utc, TimeZone, NaiveDateTime};
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chat_protocol::{
+    MessageData as OutgoingMessage, MessageType, RegisterData, ReplyData, WebSocketMessage,
+};
 
 use crate::{User, services::websocket::WebsocketService};
 use crate::services::event_bus::EventBus;
+use super::content::{
+    Attachment, AttachmentContent, ContentKind, ImageContent, Mention, SystemContent, TextContent,
+};
 
 pub enum Msg {
     HandleMsg(String),
     SubmitMessage,
     ReplyTo(usize),
     CancelReply,
+    ComposerInput(String),
+    ComposerBlur,
+    CompleteCommand(String),
+    VisibilityChanged,
+    SetStatus(String),
+    SetStatusText(String),
+    StartEdit(usize),
+    CancelEdit,
+    ToggleVersions(u64),
+    DeleteMessage(u64),
+    TogglePicker(u64),
+    ToggleReaction(u64, String),
+    OpenThread(u64),
+    CloseThread,
+    SubmitThreadReply,
+    ToggleInbox,
+    JumpTo(u64),
+    ScrollMessages,
+    ToggleSearch,
+    SubmitSearch,
+    OpenResult(u64),
+    TogglePreview,
+    PickFile,
+    FileSelected,
+    FileLoaded(String, String, Vec<u8>),
 }
 
 #[derive(Deserialize, Clone)]
 struct MessageData {
+    #[serde(default)]
+    id: u64,
     from: String,
     message: String,
     time: Option<i64>,
     reply_to: Option<ReplyData>,
+    #[serde(default)]
+    kind: ContentKind,
+    edited_at: Option<i64>,
+    #[serde(default)]
+    edits: Vec<MessageVersion>,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    reactions: BTreeMap<String, Vec<String>>,
+    thread_id: Option<u64>,
+    #[serde(default)]
+    reply_count: usize,
+    #[serde(default)]
+    mentions: Vec<Mention>,
+    attachment: Option<Attachment>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct ReplyData {
-    id: usize,
-    from: String,
+#[derive(Deserialize, Clone)]
+struct MessageVersion {
     message: String,
+    time: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MsgTypes {
-    Users,
-    Register,
-    Message,
+impl MessageData {
+    fn notice(message: String) -> Self {
+        Self {
+            id: 0,
+            from: String::new(),
+            message,
+            time: None,
+            reply_to: None,
+            kind: ContentKind::System,
+            edited_at: None,
+            edits: vec![],
+            deleted: false,
+            reactions: BTreeMap::new(),
+            thread_id: None,
+            reply_count: 0,
+            mentions: vec![],
+            attachment: None,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct WebSocketMessage {
-    message_type: MsgTypes,
-    data_array: Option<Vec<String>>,
-    data: Option<String>,
+#[derive(Deserialize, Clone)]
+struct CommandInfo {
+    name: String,
+    usage: String,
+    description: String,
 }
 
 #[derive(Clone)]
@@ -53,12 +119,558 @@ struct UserProfile {
 }
 
 pub struct Chat {
+    user: User,
     users: Vec<UserProfile>,
     chat_input: NodeRef,
     wss: WebsocketService,
     messages: Vec<MessageData>,
     _producer: Box<dyn Bridge<EventBus>>,
     replying_to: Option<(usize, MessageData)>,
+    commands: Vec<CommandInfo>,
+    composer: String,
+    topic: Option<String>,
+    typing_users: Vec<String>,
+    last_typing_sent: Option<i64>,
+    delivered_to: HashMap<u64, Vec<String>>,
+    read_markers: HashMap<String, u64>,
+    last_read_sent: u64,
+    visibility_listener: Closure<dyn Fn()>,
+    presence: HashMap<String, PresenceInfo>,
+    editing: Option<u64>,
+    expanded_versions: HashSet<u64>,
+    moderators: Vec<String>,
+    picker_open: Option<u64>,
+    open_thread: Option<u64>,
+    thread_input: NodeRef,
+    inbox_open: bool,
+    // Newest mention the user has already seen in the inbox
+    inbox_seen: u64,
+    file_input: NodeRef,
+    // Name of the file being uploaded
+    uploading: Option<String>,
+    // Whether the composer shows how its Markdown will render
+    preview: bool,
+    search_open: bool,
+    search_input: NodeRef,
+    search_from: NodeRef,
+    search_after: NodeRef,
+    search_before: NodeRef,
+    search_results: Option<SearchResults>,
+    // Message to scroll to and highlight once it has been rendered
+    pending_jump: Option<u64>,
+    highlighted: Option<u64>,
+    message_list: NodeRef,
+    history_loading: bool,
+    history_more: bool,
+    // Oldest message received in a history page, where the next page starts.
+    // Messages merged from search context or threads may be older, with a gap
+    // before the pages loaded so far.
+    history_oldest: Option<u64>,
+    // Scroll height and offset of the message list before older messages were
+    // prepended, so the view stays where it was
+    scroll_anchor: Option<(i32, i32)>,
+    scroll_to_bottom: bool,
+}
+
+#[derive(Deserialize)]
+struct HistoryPage {
+    messages: Vec<MessageData>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchResults {
+    query: String,
+    total: usize,
+    results: Vec<MessageData>,
+}
+
+#[derive(Deserialize)]
+struct MessageContext {
+    id: u64,
+    messages: Vec<MessageData>,
+}
+
+#[derive(Deserialize)]
+struct ThreadContents {
+    root: MessageData,
+    replies: Vec<MessageData>,
+}
+
+#[derive(Deserialize)]
+struct ThreadUpdate {
+    id: u64,
+    reply_count: usize,
+}
+
+#[derive(Deserialize)]
+struct ReactionUpdate {
+    id: u64,
+    reactions: BTreeMap<String, Vec<String>>,
+}
+
+// Emoji offered by the reaction picker
+const REACTION_EMOJI: &[&str] = &["👍", "❤️", "😂", "🎉", "😮", "😢"];
+
+#[derive(Deserialize, Clone)]
+struct PresenceInfo {
+    status: String,
+    text: Option<String>,
+    last_seen: i64,
+}
+
+// Statuses a user can pick; "offline" is only ever set by the server
+const STATUSES: &[(&str, &str)] = &[("online", "Online"), ("away", "Away"), ("busy", "Busy")];
+
+fn status_label(status: &str) -> &'static str {
+    match status {
+        "online" => "Online",
+        "away" => "Away",
+        "busy" => "Busy",
+        _ => "Offline",
+    }
+}
+
+fn status_color(status: &str) -> &'static str {
+    match status {
+        "online" => "bg-green-500",
+        "away" => "bg-yellow-400",
+        "busy" => "bg-red-500",
+        _ => "bg-gray-400",
+    }
+}
+
+fn format_date_time(millis: i64) -> String {
+    match DateTime::<Utc>::from_timestamp_millis(millis) {
+        Some(datetime) => format!("{}", datetime.format("%Y-%m-%d %H:%M")),
+        None => "".to_string(),
+    }
+}
+
+fn format_time(millis: i64) -> String {
+    match DateTime::<Utc>::from_timestamp_millis(millis) {
+        Some(datetime) => format!("{}", datetime.format("%H:%M:%S")),
+        None => "".to_string(),
+    }
+}
+
+// "Last seen 5m ago" style text for offline users
+fn last_seen_text(last_seen: i64) -> String {
+    let minutes = (Utc::now().timestamp_millis() - last_seen) / 60_000;
+    match minutes {
+        m if m < 1 => "Last seen just now".to_string(),
+        m if m < 60 => format!("Last seen {}m ago", m),
+        m if m < 24 * 60 => format!("Last seen {}h ago", m / 60),
+        _ => match DateTime::<Utc>::from_timestamp_millis(last_seen) {
+            Some(dt) => format!("Last seen {}", dt.format("%Y-%m-%d")),
+            None => "Offline".to_string(),
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct DeliveryReceipt {
+    id: u64,
+    delivered_to: Vec<String>,
+}
+
+// Delivery state of a message sent by the current user
+enum SendStatus {
+    Sent,
+    Delivered,
+    Read,
+}
+
+fn document_hidden() -> bool {
+    web_sys::window()
+        .and_then(|w| w.document())
+        .map_or(false, |d| d.hidden())
+}
+
+fn notification_permission() -> NotificationPermission {
+    Notification::permission()
+}
+
+// Shows a browser notification when the user is mentioned while the tab is hidden
+fn notify_mention(from: &str, text: &str) {
+    if notification_permission() != NotificationPermission::Granted {
+        return;
+    }
+    let mut options = NotificationOptions::new();
+    options.body(text);
+    if let Err(e) = Notification::new_with_options(&format!("{} mentioned you", from), &options) {
+        log::debug!("notification failed: {:?}", e);
+    }
+}
+
+// Limits mirrored from the server so bad files are refused before uploading
+const MAX_ATTACHMENT_SIZE: f64 = 10.0 * 1024.0 * 1024.0;
+const ALLOWED_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+];
+
+// Messages loaded per page of history
+const HISTORY_PAGE_SIZE: usize = 50;
+
+// Older messages load when the list is scrolled this close to its top, in pixels
+const HISTORY_LOAD_THRESHOLD: i32 = 100;
+
+// Uploads are streamed as binary frames of this size
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+// Minimum delay between two "start" typing signals
+const TYPING_THROTTLE_MS: i64 = 3000;
+
+// The composer grows with its text up to this many lines, then scrolls
+const COMPOSER_MAX_ROWS: usize = 6;
+
+fn composer_rows(text: &str) -> usize {
+    text.split('\n').count().clamp(1, COMPOSER_MAX_ROWS)
+}
+
+impl Chat {
+    fn send(&self, message: &WebSocketMessage) {
+        if let Err(e) = self
+            .wss
+            .tx
+            .clone()
+            .try_send(Message::Text(serde_json::to_string(message).unwrap()))
+        {
+            log::debug!("error sending to channel: {:?}", e);
+        }
+    }
+
+    fn send_binary(&self, bytes: Vec<u8>) {
+        if let Err(e) = self.wss.tx.clone().try_send(Message::Bytes(bytes)) {
+            log::debug!("error sending to channel: {:?}", e);
+        }
+    }
+
+    fn send_typing(&mut self, typing: bool) {
+        if typing {
+            let now = Utc::now().timestamp_millis();
+            if matches!(self.last_typing_sent, Some(sent) if now - sent < TYPING_THROTTLE_MS) {
+                return;
+            }
+            self.last_typing_sent = Some(now);
+        } else if self.last_typing_sent.take().is_none() {
+            return;
+        }
+
+        self.send(&WebSocketMessage {
+            message_type: MessageType::Typing,
+            data: Some(if typing { "start" } else { "stop" }.to_string()),
+            data_array: None,
+        });
+    }
+
+    fn mark_read(&mut self, id: u64) {
+        if id <= self.last_read_sent {
+            return;
+        }
+        self.last_read_sent = id;
+        self.send(&WebSocketMessage {
+            message_type: MessageType::Read,
+            data: Some(id.to_string()),
+            data_array: None,
+        });
+    }
+
+    fn send_status(&self, id: u64) -> SendStatus {
+        let me = self.user.username.borrow();
+        if self
+            .read_markers
+            .iter()
+            .any(|(user, read)| *user != *me && *read >= id)
+        {
+            SendStatus::Read
+        } else if self.delivered_to.get(&id).map_or(false, |users| !users.is_empty()) {
+            SendStatus::Delivered
+        } else {
+            SendStatus::Sent
+        }
+    }
+
+    // Other users whose last read message is `id`
+    fn readers_at(&self, id: u64) -> Vec<String> {
+        let me = self.user.username.borrow();
+        let mut readers: Vec<String> = self
+            .read_markers
+            .iter()
+            .filter(|(user, read)| **user != *me && **read == id)
+            .map(|(user, _)| user.clone())
+            .collect();
+        readers.sort();
+        readers
+    }
+
+    fn update_status(&self, status: &str, text: Option<String>) {
+        let update = serde_json::json!({ "status": status, "text": text });
+        self.send(&WebSocketMessage {
+            message_type: MessageType::Status,
+            data: Some(update.to_string()),
+            data_array: None,
+        });
+    }
+
+    // Root and replies of the open thread, oldest first
+    fn view_thread_panel(&self, ctx: &Context<Self>) -> Html {
+        let Some(root_id) = self.open_thread else {
+            return html! {};
+        };
+        let mut thread: Vec<&MessageData> = self
+            .messages
+            .iter()
+            .filter(|m| m.id == root_id || m.thread_id == Some(root_id))
+            .collect();
+        thread.sort_by_key(|m| m.id);
+
+        let close = ctx.link().callback(|_| Msg::CloseThread);
+        let submit = ctx.link().callback(|_| Msg::SubmitThreadReply);
+        let onkeydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+            (e.key() == "Enter").then(|| Msg::SubmitThreadReply)
+        });
+
+        html! {
+            <div class="flex-none w-80 h-screen flex flex-col border-l-2 border-gray-300">
+                <div class="w-full h-14 border-b-2 border-gray-300 flex items-center justify-between p-3">
+                    <div class="text-xl">{"🧵 Thread"}</div>
+                    <button onclick={close} class="text-gray-500 hover:text-gray-700">
+                        <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                            <line x1="18" y1="6" x2="6" y2="18"></line>
+                            <line x1="6" y1="6" x2="18" y2="18"></line>
+                        </svg>
+                    </button>
+                </div>
+                <div class="grow overflow-auto">
+                    {
+                        thread.iter().map(|m| html! {
+                            <div class={classes!("mx-3", "my-2", "p-2", "rounded-lg", if m.id == root_id { "bg-gray-200" } else { "bg-gray-100" })}>
+                                <div class="text-sm flex justify-between">
+                                    <span>{m.from.clone()}</span>
+                                    <span class="text-xs text-gray-400">{m.time.map(format_time).unwrap_or_default()}</span>
+                                </div>
+                                if m.deleted {
+                                    <div class="text-xs text-gray-400 italic">{"message deleted"}</div>
+                                } else {
+                                    <div class="text-xs text-gray-500">{self.view_content(m)}</div>
+                                }
+                            </div>
+                        }).collect::<Html>()
+                    }
+                </div>
+                <div class="w-full h-14 flex px-3 items-center border-t-2 border-gray-300">
+                    <input ref={self.thread_input.clone()} {onkeydown} type="text" placeholder="Reply in thread" class="block w-full py-2 pl-4 mr-3 bg-gray-100 rounded-full outline-none focus:text-gray-700" />
+                    <button onclick={submit} class="p-3 shadow-sm bg-blue-600 w-10 h-10 rounded-full flex justify-center items-center color-white">
+                        <svg fill="#000000" viewBox="0 0 24 24" xmlns="http://www.w3.org/2000/svg" class="fill-white">
+                            <path d="M0 0h24v24H0z" fill="none"></path><path d="M2.01 21L23 12 2.01 3 2 10l15 2-15 2z"></path>
+                        </svg>
+                    </button>
+                </div>
+            </div>
+        }
+    }
+
+    // Adds messages loaded from the server that are not shown yet, in ID order
+    fn merge_messages(&mut self, incoming: impl IntoIterator<Item = MessageData>) {
+        for m in incoming {
+            if self.messages.iter().any(|known| known.id == m.id) {
+                continue;
+            }
+            // Local notices have no ID and keep their place
+            let position = self
+                .messages
+                .iter()
+                .position(|known| known.id > m.id)
+                .unwrap_or(self.messages.len());
+            self.messages.insert(position, m);
+        }
+    }
+
+    fn view_search_panel(&self, ctx: &Context<Self>) -> Html {
+        if !self.search_open {
+            return html! {};
+        }
+        let mut authors: Vec<&String> = self.presence.keys().collect();
+        authors.sort();
+
+        let close = ctx.link().callback(|_| Msg::ToggleSearch);
+        let submit = ctx.link().callback(|_| Msg::SubmitSearch);
+        let onkeydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+            (e.key() == "Enter").then(|| Msg::SubmitSearch)
+        });
+
+        html! {
+            <div class="flex-none w-80 h-screen flex flex-col border-l-2 border-gray-300">
+                <div class="w-full h-14 border-b-2 border-gray-300 flex items-center justify-between p-3">
+                    <div class="text-xl">{"🔍 Search"}</div>
+                    <button onclick={close} class="text-gray-500 hover:text-gray-700">
+                        <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                            <line x1="18" y1="6" x2="6" y2="18"></line>
+                            <line x1="6" y1="6" x2="18" y2="18"></line>
+                        </svg>
+                    </button>
+                </div>
+                <div class="p-3 border-b border-gray-200 text-xs space-y-2">
+                    <input ref={self.search_input.clone()} {onkeydown} type="text" placeholder="Words or \"a phrase\"" class="block w-full py-2 px-3 bg-gray-100 rounded-full outline-none focus:text-gray-700" />
+                    <select ref={self.search_from.clone()} class="block w-full p-1 bg-white border border-gray-200 rounded">
+                        <option value="">{"Anyone"}</option>
+                        {
+                            authors.into_iter().map(|name| html! {
+                                <option value={name.clone()}>{name.clone()}</option>
+                            }).collect::<Html>()
+                        }
+                    </select>
+                    <div class="flex items-center space-x-1 text-gray-500">
+                        <span>{"After"}</span>
+                        <input ref={self.search_after.clone()} type="date" class="min-w-0 p-1 border border-gray-200 rounded" />
+                        <span>{"Before"}</span>
+                        <input ref={self.search_before.clone()} type="date" class="min-w-0 p-1 border border-gray-200 rounded" />
+                    </div>
+                    <button onclick={submit} class="w-full py-1 rounded bg-blue-600 text-white">{"Search"}</button>
+                </div>
+                <div class="grow overflow-auto">
+                    if let Some(ref results) = self.search_results {
+                        <div class="px-3 pt-2 text-xs text-gray-400">
+                            {format!("{} result(s) for {}", results.total, results.query)}
+                        </div>
+                        {
+                            results.results.iter().map(|m| {
+                                let id = m.id;
+                                let onclick = ctx.link().callback(move |_| Msg::OpenResult(id));
+                                html! {
+                                    <div {onclick} class="mx-3 my-2 p-2 rounded-lg bg-gray-100 cursor-pointer hover:bg-gray-200">
+                                        <div class="text-sm flex justify-between">
+                                            <span>{m.from.clone()}</span>
+                                            <span class="text-xs text-gray-400">{m.time.map(format_date_time).unwrap_or_default()}</span>
+                                        </div>
+                                        <div class="text-xs text-gray-500">{self.view_content(m)}</div>
+                                    </div>
+                                }
+                            }).collect::<Html>()
+                        }
+                    }
+                </div>
+            </div>
+        }
+    }
+
+    fn mentions_me(&self, m: &MessageData) -> bool {
+        let me = self.user.username.borrow();
+        m.mentions.iter().any(|mention| mention.user == *me)
+    }
+
+    // Renders a message through the component for its kind
+    fn view_content(&self, m: &MessageData) -> Html {
+        match (m.kind, &m.attachment) {
+            (ContentKind::Image, _) => html! { <ImageContent url={m.message.clone()} /> },
+            (ContentKind::Attachment, Some(attachment)) => html! {
+                <>
+                    <TextContent text={m.message.clone()} mentions={m.mentions.clone()} me={self.user.username.borrow().clone()} />
+                    <AttachmentContent attachment={attachment.clone()} />
+                </>
+            },
+            (ContentKind::System, _) => html! { <SystemContent text={m.message.clone()} /> },
+            _ => html! {
+                <TextContent text={m.message.clone()} mentions={m.mentions.clone()} me={self.user.username.borrow().clone()} />
+            },
+        }
+    }
+
+    fn view_inbox(&self, ctx: &Context<Self>) -> Html {
+        let mut mentions: Vec<&MessageData> = self
+            .messages
+            .iter()
+            .filter(|m| !m.deleted && self.mentions_me(m))
+            .collect();
+        mentions.sort_by(|a, b| b.id.cmp(&a.id));
+
+        html! {
+            <div class="absolute right-3 top-12 w-80 max-h-96 overflow-auto bg-white border border-gray-200 rounded-lg shadow z-20">
+                <div class="px-3 py-2 text-sm font-semibold border-b border-gray-200">{"Mentions"}</div>
+                if mentions.is_empty() {
+                    <div class="px-3 py-2 text-xs text-gray-400">{"Nobody has mentioned you yet"}</div>
+                }
+                {
+                    mentions.iter().map(|m| {
+                        let id = m.id;
+                        let onclick = ctx.link().callback(move |_| Msg::JumpTo(id));
+                        html! {
+                            <div {onclick} class="px-3 py-2 cursor-pointer hover:bg-gray-100">
+                                <div class="text-xs flex justify-between">
+                                    <span class="font-semibold">{m.from.clone()}</span>
+                                    <span class="text-gray-400">{m.time.map(format_time).unwrap_or_default()}</span>
+                                </div>
+                                <div class="text-xs text-gray-500 truncate">
+                                    <TextContent text={m.message.clone()} mentions={m.mentions.clone()} me={self.user.username.borrow().clone()} />
+                                </div>
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        }
+    }
+
+    fn own_presence(&self) -> Option<&PresenceInfo> {
+        self.presence.get(&*self.user.username.borrow())
+    }
+
+    fn view_user(&self, name: &str, presence: Option<&PresenceInfo>) -> Html {
+        let status = presence.map_or("online", |p| p.status.as_str());
+        let subtitle = match presence {
+            Some(p) if status == "offline" => last_seen_text(p.last_seen),
+            Some(PresenceInfo { text: Some(text), .. }) => text.clone(),
+            _ => status_label(status).to_string(),
+        };
+
+        html! {
+            <div class="flex m-3 bg-white rounded-lg p-2">
+                <div class="relative flex-none">
+                    <img class="w-12 h-12 rounded-full" src={"https://res.cloudinary.com/dr1tp0gwd/image/upload/v1747738474/mnzlvv15ooei5t3xusua.png"} alt="avatar"/>
+                    <span class={classes!("absolute", "bottom-0", "right-0", "w-3", "h-3", "rounded-full", "ring-2", "ring-white", status_color(status))} title={status_label(status)}></span>
+                </div>
+                <div class="flex-grow p-3 min-w-0">
+                    <div class="flex text-xs justify-between">
+                        <div>{name.to_string()}</div>
+                    </div>
+                    <div class="text-xs text-gray-400 truncate">
+                        {subtitle}
+                    </div>
+                </div>
+            </div>
+        }
+    }
+
+    // "alice is typing…" for everyone typing except the current user
+    fn typing_text(&self) -> Option<String> {
+        let me = self.user.username.borrow();
+        let others: Vec<&String> = self.typing_users.iter().filter(|u| **u != *me).collect();
+        match others.as_slice() {
+            [] => None,
+            [one] => Some(format!("{} is typing…", one)),
+            [one, two] => Some(format!("{} and {} are typing…", one, two)),
+            _ => Some("Several people are typing…".to_string()),
+        }
+    }
+
+    // Commands matching the composer while the user is still typing the command name
+    fn command_suggestions(&self) -> Vec<&CommandInfo> {
+        match self.composer.strip_prefix('/') {
+            Some(prefix) if !prefix.contains(char::is_whitespace) && !prefix.starts_with('/') => self
+                .commands
+                .iter()
+                .filter(|c| c.name.starts_with(prefix))
+                .collect(),
+            _ => vec![],
+        }
+    }
 }
 
 impl Component for Chat {
@@ -72,37 +684,113 @@ impl Component for Chat {
             .expect("context to be set");
         let wss = WebsocketService::new();
         let username = user.username.borrow().clone();
+        let secret = user.secret.borrow().clone();
 
+        // Moderators prove who they are with the secret; others send just the name
+        let register = RegisterData {
+            name: username.to_string(),
+            secret: Some(secret).filter(|secret| !secret.is_empty()),
+        };
         let message = WebSocketMessage {
-            message_type: MsgTypes::Register,
-            data: Some(username.to_string()),
+            message_type: MessageType::Register,
+            data: Some(register.to_data()),
             data_array: None,
         };
 
         if let Ok(_) = wss
             .tx
             .clone()
-            .try_send(serde_json::to_string(&message).unwrap())
+            .try_send(Message::Text(serde_json::to_string(&message).unwrap()))
         {
             log::debug!("message sent successfully");
         }
 
+        // Start with the latest page of history, older pages load on scroll
+        let history = WebSocketMessage {
+            message_type: MessageType::History,
+            data: Some(serde_json::json!({ "limit": HISTORY_PAGE_SIZE }).to_string()),
+            data_array: None,
+        };
+        let _ = wss
+            .tx
+            .clone()
+            .try_send(Message::Text(serde_json::to_string(&history).unwrap()));
+
+        // Needed for mention notifications while the tab is in the background
+        if notification_permission() == NotificationPermission::Default {
+            let _ = Notification::request_permission();
+        }
+
+        // Messages received while the tab was hidden are read once it is shown again
+        let visibility_listener = {
+            let link = ctx.link().clone();
+            Closure::wrap(Box::new(move || link.send_message(Msg::VisibilityChanged)) as Box<dyn Fn()>)
+        };
+        if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+            let _ = document.add_event_listener_with_callback(
+                "visibilitychange",
+                visibility_listener.as_ref().unchecked_ref(),
+            );
+        }
+
         Self {
+            user,
             users: vec![],
             messages: vec![],
             chat_input: NodeRef::default(),
             wss,
             _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
             replying_to: None,
+            commands: vec![],
+            composer: String::new(),
+            topic: None,
+            typing_users: vec![],
+            last_typing_sent: None,
+            delivered_to: HashMap::new(),
+            read_markers: HashMap::new(),
+            last_read_sent: 0,
+            visibility_listener,
+            presence: HashMap::new(),
+            editing: None,
+            expanded_versions: HashSet::new(),
+            moderators: vec![],
+            picker_open: None,
+            open_thread: None,
+            thread_input: NodeRef::default(),
+            inbox_open: false,
+            inbox_seen: 0,
+            file_input: NodeRef::default(),
+            uploading: None,
+            preview: false,
+            search_open: false,
+            search_input: NodeRef::default(),
+            search_from: NodeRef::default(),
+            search_after: NodeRef::default(),
+            search_before: NodeRef::default(),
+            search_results: None,
+            pending_jump: None,
+            highlighted: None,
+            message_list: NodeRef::default(),
+            history_loading: true,
+            history_more: true,
+            history_oldest: None,
+            scroll_anchor: None,
+            scroll_to_bottom: false,
         }
     }
     
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::HandleMsg(s) => {
-                let msg: WebSocketMessage = serde_json::from_str(&s).unwrap();
+                let msg: WebSocketMessage = match serde_json::from_str(&s) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        log::warn!("ignoring unreadable frame: {}", e);
+                        return false;
+                    }
+                };
                 match msg.message_type {
-                    MsgTypes::Users => {
+                    MessageType::Users => {
                         let users_from_message = msg.data_array.unwrap_or_default();
                         self.users = users_from_message
                             .iter()
@@ -117,50 +805,269 @@ impl Component for Chat {
                             .collect();
                         return true;
                     }
-                    MsgTypes::Message => {
-                        let message_data: MessageData =
-                            serde_json::from_str(&msg.data.unwrap()).unwrap();
+                    MessageType::Message => {
+                        let parsed = msg.data.as_deref().map(serde_json::from_str::<MessageData>);
+                        let message_data = match parsed {
+                            Some(Ok(message_data)) => message_data,
+                            Some(Err(e)) => {
+                                log::warn!("ignoring unreadable message: {}", e);
+                                return false;
+                            }
+                            None => {
+                                log::warn!("ignoring message frame without data");
+                                return false;
+                            }
+                        };
+                        let id = message_data.id;
+                        let from_other = message_data.from != *self.user.username.borrow();
+                        if from_other && self.mentions_me(&message_data) && document_hidden() {
+                            notify_mention(&message_data.from, &message_data.message);
+                        }
                         self.messages.push(message_data);
+
+                        if id != 0 && from_other {
+                            self.send(&WebSocketMessage {
+                                message_type: MessageType::Delivered,
+                                data: Some(id.to_string()),
+                                data_array: None,
+                            });
+                            if !document_hidden() {
+                                self.mark_read(id);
+                            }
+                        }
+                        return true;
+                    }
+                    MessageType::Notice => {
+                        self.messages.push(MessageData::notice(msg.data.unwrap_or_default()));
+                        return true;
+                    }
+                    MessageType::Topic => {
+                        self.topic = msg.data;
+                        return true;
+                    }
+                    MessageType::Uploaded => {
+                        self.uploading = None;
+                        let Some(attachment) = msg
+                            .data
+                            .and_then(|d| serde_json::from_str::<Attachment>(&d).ok())
+                        else {
+                            return true;
+                        };
+                        let data = serde_json::json!({ "text": "", "attachment": attachment.id });
+                        self.send(&WebSocketMessage {
+                            message_type: MessageType::Message,
+                            data: Some(data.to_string()),
+                            data_array: None,
+                        });
+                        return true;
+                    }
+                    MessageType::UploadFailed => {
+                        self.uploading = None;
+                        let reason = msg.data.unwrap_or_default();
+                        self.messages.push(MessageData::notice(format!("Upload failed: {}", reason)));
+                        return true;
+                    }
+                    MessageType::Commands => {
+                        self.commands = serde_json::from_str(&msg.data.unwrap_or_default())
+                            .unwrap_or_default();
+                        return true;
+                    }
+                    MessageType::Typing => {
+                        self.typing_users = msg.data_array.unwrap_or_default();
+                        return true;
+                    }
+                    MessageType::Receipt => {
+                        if let Ok(receipt) =
+                            serde_json::from_str::<DeliveryReceipt>(&msg.data.unwrap_or_default())
+                        {
+                            self.delivered_to.insert(receipt.id, receipt.delivered_to);
+                            return true;
+                        }
+                        return false;
+                    }
+                    MessageType::ReadMarkers => {
+                        self.read_markers = serde_json::from_str(&msg.data.unwrap_or_default())
+                            .unwrap_or_default();
+                        return true;
+                    }
+                    MessageType::Edit => {
+                        let Ok(edited) =
+                            serde_json::from_str::<MessageData>(&msg.data.unwrap_or_default())
+                        else {
+                            return false;
+                        };
+                        if let Some(m) = self.messages.iter_mut().find(|m| m.id == edited.id) {
+                            *m = edited;
+                            return true;
+                        }
+                        return false;
+                    }
+                    MessageType::Delete => {
+                        let Some(id) = msg.data.and_then(|d| d.parse::<u64>().ok()) else {
+                            return false;
+                        };
+                        // Drop the content everywhere it is shown, quotes included
+                        for m in self.messages.iter_mut() {
+                            if m.id == id {
+                                m.deleted = true;
+                                m.message.clear();
+                                m.edits.clear();
+                                m.reactions.clear();
+                            }
+                            if let Some(reply) = m.reply_to.as_mut().filter(|r| r.id == id) {
+                                reply.deleted = true;
+                                reply.message.clear();
+                            }
+                        }
+                        if matches!(self.replying_to, Some((_, ref m)) if m.id == id) {
+                            self.replying_to = None;
+                        }
+                        if self.editing == Some(id) {
+                            self.editing = None;
+                        }
+                        self.expanded_versions.remove(&id);
+                        return true;
+                    }
+                    MessageType::Reactions => {
+                        let Ok(update) =
+                            serde_json::from_str::<ReactionUpdate>(&msg.data.unwrap_or_default())
+                        else {
+                            return false;
+                        };
+                        if let Some(m) = self.messages.iter_mut().find(|m| m.id == update.id) {
+                            m.reactions = update.reactions;
+                            return true;
+                        }
+                        return false;
+                    }
+                    MessageType::Thread => {
+                        let Ok(contents) =
+                            serde_json::from_str::<ThreadContents>(&msg.data.unwrap_or_default())
+                        else {
+                            return false;
+                        };
+                        // Keep replies we did not receive live, e.g. from before we joined
+                        self.merge_messages(std::iter::once(contents.root).chain(contents.replies));
+                        return true;
+                    }
+                    MessageType::History => {
+                        let Ok(page) =
+                            serde_json::from_str::<HistoryPage>(&msg.data.unwrap_or_default())
+                        else {
+                            return false;
+                        };
+                        // Older pages keep the view in place, the first one opens at the bottom
+                        let prepends = matches!(
+                            (self.history_oldest, page.messages.last()),
+                            (Some(oldest), Some(newest)) if newest.id < oldest
+                        );
+                        if !prepends {
+                            self.scroll_to_bottom = true;
+                        } else if let Some(list) = self.message_list.cast::<web_sys::Element>() {
+                            self.scroll_anchor = Some((list.scroll_height(), list.scroll_top()));
+                        }
+                        self.history_loading = false;
+                        self.history_more = page.has_more;
+                        if let Some(first) = page.messages.first() {
+                            self.history_oldest = Some(first.id);
+                        }
+                        self.merge_messages(page.messages);
+                        return true;
+                    }
+                    MessageType::SearchResults => {
+                        self.search_results = msg
+                            .data
+                            .and_then(|d| serde_json::from_str::<SearchResults>(&d).ok());
+                        return true;
+                    }
+                    MessageType::Context => {
+                        let Ok(context) =
+                            serde_json::from_str::<MessageContext>(&msg.data.unwrap_or_default())
+                        else {
+                            return false;
+                        };
+                        self.merge_messages(context.messages);
+                        ctx.link().send_message(Msg::JumpTo(context.id));
+                        return true;
+                    }
+                    MessageType::ThreadUpdate => {
+                        let Ok(update) =
+                            serde_json::from_str::<ThreadUpdate>(&msg.data.unwrap_or_default())
+                        else {
+                            return false;
+                        };
+                        if let Some(m) = self.messages.iter_mut().find(|m| m.id == update.id) {
+                            m.reply_count = update.reply_count;
+                            return true;
+                        }
+                        return false;
+                    }
+                    MessageType::Moderators => {
+                        self.moderators = msg.data_array.unwrap_or_default();
+                        return true;
+                    }
+                    MessageType::Presence => {
+                        self.presence = serde_json::from_str(&msg.data.unwrap_or_default())
+                            .unwrap_or_default();
                         return true;
                     }
+                    MessageType::Register => {
+                        // The server confirms a new name after /nick
+                        if let Some(name) = msg.data {
+                            *self.user.username.borrow_mut() = name;
+                        }
+                        return false;
+                    }
                     _ => {
                         return false;
                     }
                 }
             }
             Msg::SubmitMessage => {
-                let input = self.chat_input.cast::<HtmlInputElement>();
+                let input = self.chat_input.cast::<HtmlTextAreaElement>();
                 if let Some(input) = input {
                     if !input.value().trim().is_empty() {
-                        let mut data_to_send = HashMap::new();
-                        data_to_send.insert("text", input.value());
+                        // Editing replaces the text of an existing message instead
+                        if let Some(id) = self.editing.take() {
+                            let edit = serde_json::json!({ "id": id, "text": input.value() });
+                            self.send(&WebSocketMessage {
+                                message_type: MessageType::Edit,
+                                data: Some(edit.to_string()),
+                                data_array: None,
+                            });
+                            self.send_typing(false);
+                            input.set_value("");
+                            self.composer.clear();
+                            return true;
+                        }
+
+                        let mut data_to_send = OutgoingMessage {
+                            text: input.value(),
+                            ..Default::default()
+                        };
                         
                         // Add reply data if we're replying to a message
-                        if let Some((id, ref msg)) = self.replying_to {
+                        if let Some((_, ref msg)) = self.replying_to {
                             let reply_data = ReplyData {
-                                id,
+                                id: msg.id,
                                 from: msg.from.clone(),
                                 message: msg.message.clone(),
+                                deleted: false,
                             };
-                            data_to_send.insert("reply_to", serde_json::to_string(&reply_data).unwrap());
+                            data_to_send.reply_to = Some(serde_json::to_string(&reply_data).unwrap());
                         }
                         
                         let message = WebSocketMessage {
-                            message_type: MsgTypes::Message,
+                            message_type: MessageType::Message,
                             data: Some(serde_json::to_string(&data_to_send).unwrap()),
                             data_array: None,
                         };
                         
-                        if let Err(e) = self
-                            .wss
-                            .tx
-                            .clone()
-                            .try_send(serde_json::to_string(&message).unwrap())
-                        {
-                            log::debug!("error sending to channel: {:?}", e);
-                        }
+                        self.send(&message);
+                        self.send_typing(false);
                         
                         input.set_value("");
+                        self.composer.clear();
                         self.replying_to = None;
                         return true;
                     }
@@ -170,10 +1077,242 @@ impl Component for Chat {
             Msg::ReplyTo(index) => {
                 if index < self.messages.len() {
                     self.replying_to = Some((index, self.messages[index].clone()));
+                    self.editing = None;
                     return true;
                 }
                 false
             }
+            Msg::StartEdit(index) => {
+                if let (Some(m), Some(input)) =
+                    (self.messages.get(index), self.chat_input.cast::<HtmlTextAreaElement>())
+                {
+                    self.editing = Some(m.id);
+                    self.replying_to = None;
+                    self.composer = m.message.clone();
+                    input.set_value(&m.message);
+                    let _ = input.focus();
+                    return true;
+                }
+                false
+            }
+            Msg::CancelEdit => {
+                if self.editing.take().is_some() {
+                    if let Some(input) = self.chat_input.cast::<HtmlTextAreaElement>() {
+                        input.set_value("");
+                    }
+                    self.composer.clear();
+                    return true;
+                }
+                false
+            }
+            Msg::DeleteMessage(id) => {
+                let confirmed = web_sys::window()
+                    .and_then(|w| w.confirm_with_message("Delete this message for everyone?").ok())
+                    .unwrap_or(false);
+                if confirmed {
+                    self.send(&WebSocketMessage {
+                        message_type: MessageType::Delete,
+                        data: Some(id.to_string()),
+                        data_array: None,
+                    });
+                }
+                false
+            }
+            Msg::TogglePicker(id) => {
+                self.picker_open = if self.picker_open == Some(id) { None } else { Some(id) };
+                true
+            }
+            Msg::ToggleReaction(id, emoji) => {
+                let me = self.user.username.borrow().clone();
+                let reacted = self
+                    .messages
+                    .iter()
+                    .find(|m| m.id == id)
+                    .and_then(|m| m.reactions.get(&emoji))
+                    .map_or(false, |users| users.contains(&me));
+                let request = serde_json::json!({ "id": id, "emoji": emoji });
+                self.send(&WebSocketMessage {
+                    message_type: if reacted { MessageType::Unreact } else { MessageType::React },
+                    data: Some(request.to_string()),
+                    data_array: None,
+                });
+                self.picker_open.take().is_some()
+            }
+            Msg::OpenThread(id) => {
+                self.open_thread = Some(id);
+                self.send(&WebSocketMessage {
+                    message_type: MessageType::Thread,
+                    data: Some(id.to_string()),
+                    data_array: None,
+                });
+                true
+            }
+            Msg::CloseThread => {
+                self.open_thread = None;
+                true
+            }
+            Msg::SubmitThreadReply => {
+                let (Some(root_id), Some(input)) =
+                    (self.open_thread, self.thread_input.cast::<HtmlInputElement>())
+                else {
+                    return false;
+                };
+                if input.value().trim().is_empty() {
+                    return false;
+                }
+                let reply = serde_json::json!({ "text": input.value(), "thread_id": root_id });
+                self.send(&WebSocketMessage {
+                    message_type: MessageType::Message,
+                    data: Some(reply.to_string()),
+                    data_array: None,
+                });
+                input.set_value("");
+                false
+            }
+            Msg::TogglePreview => {
+                self.preview = !self.preview;
+                true
+            }
+            Msg::PickFile => {
+                if let Some(input) = self.file_input.cast::<HtmlInputElement>() {
+                    input.click();
+                }
+                false
+            }
+            Msg::FileSelected => {
+                let Some(input) = self.file_input.cast::<HtmlInputElement>() else {
+                    return false;
+                };
+                let Some(file) = input.files().and_then(|files| files.get(0)) else {
+                    return false;
+                };
+                // Lets the same file be picked again later
+                input.set_value("");
+
+                let error = if file.size() > MAX_ATTACHMENT_SIZE {
+                    Some(format!("{} is larger than 10 MB", file.name()))
+                } else if !ALLOWED_TYPES.contains(&file.type_().as_str()) {
+                    Some(format!("{} is not a supported file type", file.name()))
+                } else if self.uploading.is_some() {
+                    Some("Wait for the current upload to finish".to_string())
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    self.messages.push(MessageData::notice(error));
+                    return true;
+                }
+
+                self.uploading = Some(file.name());
+                let link = ctx.link().clone();
+                spawn_local(async move {
+                    match JsFuture::from(file.array_buffer()).await {
+                        Ok(buffer) => {
+                            let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+                            link.send_message(Msg::FileLoaded(file.name(), file.type_(), bytes));
+                        }
+                        Err(e) => log::error!("could not read {}: {:?}", file.name(), e),
+                    }
+                });
+                true
+            }
+            Msg::FileLoaded(name, mime, bytes) => {
+                let upload = serde_json::json!({ "name": name, "mime": mime, "size": bytes.len() });
+                self.send(&WebSocketMessage {
+                    message_type: MessageType::Upload,
+                    data: Some(upload.to_string()),
+                    data_array: None,
+                });
+                for chunk in bytes.chunks(UPLOAD_CHUNK_SIZE) {
+                    self.send_binary(chunk.to_vec());
+                }
+                false
+            }
+            Msg::ToggleInbox => {
+                self.inbox_open = !self.inbox_open;
+                self.inbox_seen = self
+                    .messages
+                    .iter()
+                    .filter(|m| self.mentions_me(m))
+                    .map(|m| m.id)
+                    .max()
+                    .unwrap_or(self.inbox_seen);
+                true
+            }
+            Msg::JumpTo(id) => {
+                self.inbox_open = false;
+                // Thread replies live in the thread panel, not the main list
+                if let Some(root_id) = self.messages.iter().find(|m| m.id == id).and_then(|m| m.thread_id) {
+                    ctx.link().send_message(Msg::OpenThread(root_id));
+                    return true;
+                }
+                self.pending_jump = Some(id);
+                self.highlighted = Some(id);
+                true
+            }
+            Msg::ScrollMessages => {
+                let Some(list) = self.message_list.cast::<web_sys::Element>() else {
+                    return false;
+                };
+                if list.scroll_top() > HISTORY_LOAD_THRESHOLD || self.history_loading || !self.history_more {
+                    return false;
+                }
+                self.history_loading = true;
+                let request = serde_json::json!({ "before": self.history_oldest, "limit": HISTORY_PAGE_SIZE });
+                self.send(&WebSocketMessage {
+                    message_type: MessageType::History,
+                    data: Some(request.to_string()),
+                    data_array: None,
+                });
+                true
+            }
+            Msg::ToggleSearch => {
+                self.search_open = !self.search_open;
+                true
+            }
+            Msg::SubmitSearch => {
+                let value = |node: &NodeRef| {
+                    node.cast::<HtmlInputElement>()
+                        .map(|input| input.value())
+                        .filter(|value| !value.is_empty())
+                };
+                let from = self
+                    .search_from
+                    .cast::<HtmlSelectElement>()
+                    .map(|select| select.value())
+                    .filter(|value| !value.is_empty());
+                let search = serde_json::json!({
+                    "query": value(&self.search_input).unwrap_or_default(),
+                    "from": from,
+                    "after": value(&self.search_after),
+                    "before": value(&self.search_before),
+                });
+                self.send(&WebSocketMessage {
+                    message_type: MessageType::Search,
+                    data: Some(search.to_string()),
+                    data_array: None,
+                });
+                false
+            }
+            Msg::OpenResult(id) => {
+                if self.messages.iter().any(|m| m.id == id) {
+                    ctx.link().send_message(Msg::JumpTo(id));
+                } else {
+                    // Load the messages around it first; the reply jumps to it
+                    self.send(&WebSocketMessage {
+                        message_type: MessageType::Context,
+                        data: Some(id.to_string()),
+                        data_array: None,
+                    });
+                }
+                false
+            }
+            Msg::ToggleVersions(id) => {
+                if !self.expanded_versions.remove(&id) {
+                    self.expanded_versions.insert(id);
+                }
+                true
+            }
             Msg::CancelReply => {
                 if self.replying_to.is_some() {
                     self.replying_to = None;
@@ -181,59 +1320,224 @@ impl Component for Chat {
                 }
                 false
             }
+            Msg::ComposerInput(value) => {
+                let was_suggesting = !self.command_suggestions().is_empty();
+                let rows = composer_rows(&self.composer);
+                self.composer = value;
+                // Commands are never broadcast, so they don't count as typing
+                let typing = !self.composer.trim().is_empty() && !self.composer.starts_with('/');
+                self.send_typing(typing);
+                was_suggesting
+                    || !self.command_suggestions().is_empty()
+                    || rows != composer_rows(&self.composer)
+                    || self.preview
+            }
+            Msg::VisibilityChanged => {
+                let latest = self.messages.iter().map(|m| m.id).max().unwrap_or(0);
+                if !document_hidden() && latest != 0 {
+                    self.mark_read(latest);
+                }
+                false
+            }
+            Msg::SetStatus(status) => {
+                let text = self.own_presence().and_then(|p| p.text.clone());
+                self.update_status(&status, text);
+                false
+            }
+            Msg::SetStatusText(text) => {
+                let status = self
+                    .own_presence()
+                    .map(|p| p.status.clone())
+                    .filter(|s| s != "offline")
+                    .unwrap_or_else(|| "online".to_string());
+                self.update_status(&status, Some(text));
+                false
+            }
+            Msg::ComposerBlur => {
+                self.send_typing(false);
+                false
+            }
+            Msg::CompleteCommand(name) => {
+                if let Some(input) = self.chat_input.cast::<HtmlTextAreaElement>() {
+                    self.composer = format!("/{} ", name);
+                    input.set_value(&self.composer);
+                    let _ = input.focus();
+                }
+                true
+            }
         }
     }
     
+    fn rendered(&mut self, _ctx: &Context<Self>, _first_render: bool) {
+        if let Some(list) = self.message_list.cast::<web_sys::Element>() {
+            if std::mem::take(&mut self.scroll_to_bottom) {
+                list.set_scroll_top(list.scroll_height());
+            } else if let Some((height, top)) = self.scroll_anchor.take() {
+                list.set_scroll_top(list.scroll_height() - height + top);
+            }
+        }
+
+        let Some(id) = self.pending_jump.take() else {
+            return;
+        };
+        if let Some(element) = web_sys::window()
+            .and_then(|w| w.document())
+            .and_then(|d| d.get_element_by_id(&format!("msg-{}", id)))
+        {
+            element.scroll_into_view();
+        }
+    }
+
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+            let _ = document.remove_event_listener_with_callback(
+                "visibilitychange",
+                self.visibility_listener.as_ref().unchecked_ref(),
+            );
+        }
+    }
+
     fn view(&self, ctx: &Context<Self>) -> Html {
         let submit = ctx.link().callback(|_| Msg::SubmitMessage);
         let cancel_reply = ctx.link().callback(|_| Msg::CancelReply);
+        let cancel_edit = ctx.link().callback(|_| Msg::CancelEdit);
+        let oninput = ctx.link().callback(|e: InputEvent| {
+            let input: HtmlTextAreaElement = e.target_unchecked_into();
+            Msg::ComposerInput(input.value())
+        });
+        let onblur = ctx.link().callback(|_: FocusEvent| Msg::ComposerBlur);
+        let suggestions = self.command_suggestions();
+        let unread_mentions = self
+            .messages
+            .iter()
+            .filter(|m| m.id > self.inbox_seen && !m.deleted && self.mentions_me(m))
+            .count();
+        let own_status = self.own_presence().map_or("online".to_string(), |p| p.status.clone());
+        let own_status_text = self.own_presence().and_then(|p| p.text.clone()).unwrap_or_default();
+        let on_status = ctx.link().callback(|e: Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            Msg::SetStatus(select.value())
+        });
+        let on_status_text = ctx.link().callback(|e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            Msg::SetStatusText(input.value())
+        });
+        let mut offline: Vec<(&String, &PresenceInfo)> = self
+            .presence
+            .iter()
+            .filter(|(name, p)| p.status == "offline" && !self.users.iter().any(|u| u.name == **name))
+            .collect();
+        offline.sort_by(|a, b| b.1.last_seen.cmp(&a.1.last_seen));
+        let onkeydown = {
+            let first = suggestions.first().map(|c| c.name.clone());
+            ctx.link().batch_callback(move |e: KeyboardEvent| {
+                // Tab completes the first suggested command
+                match first.clone() {
+                    Some(name) if e.key() == "Tab" => {
+                        e.prevent_default();
+                        Some(Msg::CompleteCommand(name))
+                    }
+                    // Enter sends, Shift+Enter starts a new line
+                    _ if e.key() == "Enter" && !e.shift_key() && !e.is_composing() => {
+                        e.prevent_default();
+                        Some(Msg::SubmitMessage)
+                    }
+                    _ => None,
+                }
+            })
+        };
         
         html! {
             <div class="flex w-screen">
-                <div class="flex-none w-56 h-screen bg-gray-100">
+                <div class="flex-none w-56 h-screen bg-gray-100 overflow-auto">
                     <div class="text-xl p-3">{"Users"}</div>
-                    {
-                        self.users.clone().iter().map(|u| {
-                            html!{
-                                <div class="flex m-3 bg-white rounded-lg p-2">
-                                    <div>
-                                        <img class="w-12 h-12 rounded-full" src={"https://res.cloudinary.com/dr1tp0gwd/image/upload/v1747738474/mnzlvv15ooei5t3xusua.png"} alt="avatar"/>
-                                    </div>
-                                    <div class="flex-grow p-3">
-                                        <div class="flex text-xs justify-between">
-                                            <div>{u.name.clone()}</div>
-                                        </div>
-                                        <div class="text-xs text-gray-400">
-                                            {"Hi there!"}
-                                        </div>
-                                    </div>
-                                </div>
+                    <div class="mx-3 mb-1 flex flex-col space-y-1">
+                        <select onchange={on_status} class="text-xs p-1 rounded bg-white outline-none">
+                            {
+                                STATUSES.iter().map(|(value, label)| html! {
+                                    <option value={*value} selected={own_status == *value}>{*label}</option>
+                                }).collect::<Html>()
                             }
-                        }).collect::<Html>()
+                        </select>
+                        <input onchange={on_status_text} value={own_status_text} type="text" placeholder="Set a status…" class="text-xs p-1 rounded bg-white outline-none" />
+                    </div>
+                    {
+                        self.users.iter().map(|u| self.view_user(&u.name, self.presence.get(&u.name))).collect::<Html>()
+                    }
+                    if !offline.is_empty() {
+                        <div class="text-xs uppercase text-gray-400 px-3 pt-3">{"Offline"}</div>
+                        {
+                            offline.iter().map(|(name, p)| self.view_user(name, Some(p))).collect::<Html>()
+                        }
                     }
                 </div>
                 <div class="grow h-screen flex flex-col">
-                    <div class="w-full h-14 border-b-2 border-gray-300"><div class="text-xl p-3">{"💬 Chat!"}</div></div>
-                    <div class="w-full grow overflow-auto border-b-2 border-gray-300">
+                    <div class="relative w-full h-14 border-b-2 border-gray-300"><div class="flex items-baseline p-3">
+                        <div class="text-xl">{"💬 Chat!"}</div>
+                        {
+                            if let Some(ref topic) = self.topic {
+                                html! { <div class="ml-4 text-sm text-gray-500 truncate">{topic.clone()}</div> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        <button onclick={ctx.link().callback(|_| Msg::ToggleSearch)} class="ml-auto px-2 text-lg text-gray-500 hover:text-gray-700" title="Search">
+                            {"🔍"}
+                        </button>
+                        <button onclick={ctx.link().callback(|_| Msg::ToggleInbox)} class="relative px-2 text-lg text-gray-500 hover:text-gray-700" title="Mentions">
+                            {"@"}
+                            if unread_mentions > 0 {
+                                <span class="absolute -top-1 -right-1 px-1 rounded-full bg-red-500 text-white" style="font-size: 0.6rem">{unread_mentions}</span>
+                            }
+                        </button>
+                    </div>
+                    if self.inbox_open {
+                        {self.view_inbox(ctx)}
+                    }
+                    </div>
+                    <div ref={self.message_list.clone()} onscroll={ctx.link().callback(|_| Msg::ScrollMessages)} class="w-full grow overflow-auto border-b-2 border-gray-300">
+                        if self.history_loading {
+                            <div class="py-2 text-center text-xs text-gray-400">{"Loading older messages…"}</div>
+                        } else if !self.history_more {
+                            <div class="py-2 text-center text-xs text-gray-400">{"This is the beginning of the chat"}</div>
+                        }
                         {
                             self.messages.iter().enumerate().map(|(index, m)| {
-                                let user = self.users.iter().find(|u| u.name == m.from).unwrap();
-                                let timestamp = match m.time {
-                                    Some(t) => {
-                                        if let Some(dt) = NaiveDateTime::from_timestamp_millis(t) {
-                                            let datetime: DateTime<Utc> = Utc.from_utc_datetime(&dt);
-                                            format!("{}", datetime.format("%H:%M:%S"))
-                                        } else {
-                                            "".to_string()
-                                        }
-                                    },
-                                    None => "".to_string(),
-                                };
+                                if m.kind == ContentKind::System {
+                                    return html! { <SystemContent text={m.message.clone()} /> };
+                                }
+                                // Thread replies only show up in the thread panel
+                                if m.thread_id.is_some() {
+                                    return html! {};
+                                }
+
+                                let timestamp = m.time.map(format_time).unwrap_or_default();
                                 
                                 let reply_callback = ctx.link().callback(move |_| Msg::ReplyTo(index));
+                                let id = m.id;
+                                let own = m.id != 0 && m.from == *self.user.username.borrow();
+                                let can_delete = m.id != 0
+                                    && !m.deleted
+                                    && (own || self.moderators.contains(&*self.user.username.borrow()));
+                                let edited = if m.edited_at.is_some() {
+                                    let toggle = ctx.link().callback(move |_| Msg::ToggleVersions(id));
+                                    html! { <span onclick={toggle} class="ml-1 cursor-pointer hover:underline" title="Show previous versions">{"(edited)"}</span> }
+                                } else {
+                                    html! {}
+                                };
+                                let status = if own {
+                                    match self.send_status(m.id) {
+                                        SendStatus::Sent => html! { <span class="ml-1 text-gray-400" title="Sent">{"✓"}</span> },
+                                        SendStatus::Delivered => html! { <span class="ml-1 text-gray-400" title="Delivered">{"✓✓"}</span> },
+                                        SendStatus::Read => html! { <span class="ml-1 text-blue-500" title="Read">{"✓✓"}</span> },
+                                    }
+                                } else {
+                                    html! {}
+                                };
+                                let readers = self.readers_at(m.id);
                                 
                                 html!{
-                                    <div class="flex flex-col items-end w-3/6 bg-gray-100 m-8 rounded-tl-lg rounded-tr-lg rounded-br-lg ">
+                                    <div id={format!("msg-{}", m.id)} class={classes!("flex", "flex-col", "items-end", "w-3/6", "bg-gray-100", "m-8", "rounded-tl-lg", "rounded-tr-lg", "rounded-br-lg", (self.highlighted == Some(m.id)).then(|| "ring-2 ring-yellow-400"))}>
                                         {
                                             if let Some(ref reply) = m.reply_to {
                                                 html! {
@@ -241,9 +1545,13 @@ impl Component for Chat {
                                                         <div class="text-xs font-semibold text-blue-600">
                                                             {format!("↩️ Reply to {}", reply.from)}
                                                         </div>
-                                                        <div class="text-xs text-gray-500 truncate">
-                                                            {reply.message.clone()}
-                                                        </div>
+                                                        if reply.deleted {
+                                                            <div class="text-xs text-gray-400 italic">{"message deleted"}</div>
+                                                        } else {
+                                                            <div class="text-xs text-gray-500 truncate">
+                                                                {reply.message.clone()}
+                                                            </div>
+                                                        }
                                                     </div>
                                                 }
                                             } else {
@@ -255,31 +1563,151 @@ impl Component for Chat {
                                             <div class="p-3 w-full">
                                                 <div class="text-sm flex justify-between">
                                                     <span>{m.from.clone()}</span>
-                                                    <span class="text-xs text-gray-400">{timestamp}</span>
+                                                    <span class="text-xs text-gray-400">{edited}{" "}{timestamp}{status}</span>
                                                 </div>
                                                 <div class="text-xs text-gray-500">
-                                                    if m.message.ends_with(".gif") {
-                                                        <img class="mt-3" src={m.message.clone()}/>
+                                                    if m.deleted {
+                                                        <span class="text-gray-400 italic">{"message deleted"}</span>
                                                     } else {
-                                                        {m.message.clone()}
+                                                        {self.view_content(m)}
                                                     }
                                                 </div>
+                                                if self.expanded_versions.contains(&m.id) {
+                                                    <div class="mt-2 pl-2 border-l-2 border-gray-300">
+                                                        {
+                                                            m.edits.iter().rev().map(|v| html! {
+                                                                <div class="text-xs text-gray-400">
+                                                                    <span class="mr-2">{format_time(v.time)}</span>
+                                                                    {v.message.clone()}
+                                                                </div>
+                                                            }).collect::<Html>()
+                                                        }
+                                                    </div>
+                                                }
+                                                if m.reply_count > 0 {
+                                                    <button onclick={ctx.link().callback(move |_| Msg::OpenThread(id))} class="mt-2 text-xs font-semibold text-blue-600 hover:underline">
+                                                        {if m.reply_count == 1 { "1 reply".to_string() } else { format!("{} replies", m.reply_count) }}
+                                                    </button>
+                                                }
                                             </div>
-                                            <button onclick={reply_callback} class="p-2 m-2 text-blue-500 hover:bg-blue-100 rounded-full">
-                                                <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
-                                                    <path d="M21 11.5a8.38 8.38 0 0 1-.9 3.8 8.5 8.5 0 0 1-7.6 4.7 8.38 8.38 0 0 1-3.8-.9L3 21l1.9-5.7a8.38 8.38 0 0 1-.9-3.8 8.5 8.5 0 0 1 4.7-7.6 8.38 8.38 0 0 1 3.8-.9h.5a8.48 8.48 0 0 1 8 8v.5z"></path>
-                                                </svg>
-                                            </button>
+                                            if can_delete {
+                                                <button onclick={ctx.link().callback(move |_| Msg::DeleteMessage(id))} class="p-2 my-2 text-gray-500 hover:bg-red-100 hover:text-red-600 rounded-full" title="Delete">
+                                                    <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                                        <polyline points="3 6 5 6 21 6"></polyline>
+                                                        <path d="M19 6l-1 14a2 2 0 0 1-2 2H8a2 2 0 0 1-2-2L5 6m3 0V4a1 1 0 0 1 1-1h6a1 1 0 0 1 1 1v2"></path>
+                                                    </svg>
+                                                </button>
+                                            }
+                                            if own && !m.deleted {
+                                                <button onclick={ctx.link().callback(move |_| Msg::StartEdit(index))} class="p-2 my-2 text-gray-500 hover:bg-gray-200 rounded-full" title="Edit">
+                                                    <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                                        <path d="M12 20h9"></path>
+                                                        <path d="M16.5 3.5a2.121 2.121 0 0 1 3 3L7 19l-4 1 1-4L16.5 3.5z"></path>
+                                                    </svg>
+                                                </button>
+                                            }
+                                            if !m.deleted {
+                                                <div class="relative my-2">
+                                                    <button onclick={ctx.link().callback(move |_| Msg::TogglePicker(id))} class="p-2 text-gray-500 hover:bg-gray-200 rounded-full" title="React">
+                                                        <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                                            <circle cx="12" cy="12" r="10"></circle>
+                                                            <path d="M8 14s1.5 2 4 2 4-2 4-2"></path>
+                                                            <line x1="9" y1="9" x2="9.01" y2="9"></line>
+                                                            <line x1="15" y1="9" x2="15.01" y2="9"></line>
+                                                        </svg>
+                                                    </button>
+                                                    if self.picker_open == Some(id) {
+                                                        <div class="absolute bottom-full right-0 mb-1 flex bg-white border border-gray-200 rounded-full shadow px-1 z-10">
+                                                            {
+                                                                REACTION_EMOJI.iter().map(|emoji| {
+                                                                    let emoji = emoji.to_string();
+                                                                    let onclick = ctx.link().callback({
+                                                                        let emoji = emoji.clone();
+                                                                        move |_| Msg::ToggleReaction(id, emoji.clone())
+                                                                    });
+                                                                    html! { <button {onclick} class="p-1 text-lg hover:scale-125">{emoji}</button> }
+                                                                }).collect::<Html>()
+                                                            }
+                                                        </div>
+                                                    }
+                                                </div>
+                                                <button onclick={ctx.link().callback(move |_| Msg::OpenThread(id))} class="p-2 my-2 text-gray-500 hover:bg-gray-200 rounded-full" title="Reply in thread">
+                                                    <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                                        <line x1="8" y1="6" x2="21" y2="6"></line>
+                                                        <line x1="8" y1="12" x2="21" y2="12"></line>
+                                                        <line x1="8" y1="18" x2="21" y2="18"></line>
+                                                        <line x1="3" y1="6" x2="3.01" y2="6"></line>
+                                                        <line x1="3" y1="12" x2="3.01" y2="12"></line>
+                                                        <line x1="3" y1="18" x2="3.01" y2="18"></line>
+                                                    </svg>
+                                                </button>
+                                                <button onclick={reply_callback} class="p-2 m-2 text-blue-500 hover:bg-blue-100 rounded-full">
+                                                    <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                                        <path d="M21 11.5a8.38 8.38 0 0 1-.9 3.8 8.5 8.5 0 0 1-7.6 4.7 8.38 8.38 0 0 1-3.8-.9L3 21l1.9-5.7a8.38 8.38 0 0 1-.9-3.8 8.5 8.5 0 0 1 4.7-7.6 8.38 8.38 0 0 1 3.8-.9h.5a8.48 8.48 0 0 1 8 8v.5z"></path>
+                                                    </svg>
+                                                </button>
+                                            }
                                         </div>
+                                        if !m.reactions.is_empty() {
+                                            <div class="flex flex-wrap self-start ml-14 mb-2 gap-1">
+                                                {
+                                                    m.reactions.iter().map(|(emoji, users)| {
+                                                        let mine = users.contains(&*self.user.username.borrow());
+                                                        let onclick = ctx.link().callback({
+                                                            let emoji = emoji.clone();
+                                                            move |_| Msg::ToggleReaction(id, emoji.clone())
+                                                        });
+                                                        let class = if mine {
+                                                            "px-2 rounded-full text-xs border border-blue-400 bg-blue-50"
+                                                        } else {
+                                                            "px-2 rounded-full text-xs border border-gray-300 bg-white"
+                                                        };
+                                                        html! {
+                                                            <button {onclick} {class} title={users.join(", ")}>
+                                                                {format!("{} {}", emoji, users.len())}
+                                                            </button>
+                                                        }
+                                                    }).collect::<Html>()
+                                                }
+                                            </div>
+                                        }
+                                        if !readers.is_empty() {
+                                            <div class="flex mr-3 mb-2 -space-x-1">
+                                                {
+                                                    readers.iter().map(|r| html! {
+                                                        <div class="w-4 h-4 rounded-full bg-blue-200 text-blue-700 flex justify-center items-center ring-1 ring-white" style="font-size: 0.5rem" title={format!("Read by {}", r)}>
+                                                            {r.chars().next().unwrap_or('?').to_uppercase().to_string()}
+                                                        </div>
+                                                    }).collect::<Html>()
+                                                }
+                                            </div>
+                                        }
                                     </div>
                                 }
                             }).collect::<Html>()
                         }
 
                     </div>
+                    <div class="w-full h-5 px-4 text-xs text-gray-500 italic">
+                        {self.typing_text().unwrap_or_default()}
+                    </div>
                     <div class="w-full flex flex-col">
                         {
-                            if let Some((_, ref msg)) = self.replying_to {
+                            if self.editing.is_some() {
+                                html! {
+                                    <div class="flex items-center bg-yellow-50 px-4 py-2">
+                                        <div class="flex-grow text-xs text-yellow-700 font-semibold">
+                                            {"Editing message"}
+                                        </div>
+                                        <button onclick={cancel_edit} class="text-gray-500 hover:text-gray-700">
+                                            <svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                                <line x1="18" y1="6" x2="6" y2="18"></line>
+                                                <line x1="6" y1="6" x2="18" y2="18"></line>
+                                            </svg>
+                                        </button>
+                                    </div>
+                                }
+                            } else if let Some((_, ref msg)) = self.replying_to {
                                 html! {
                                     <div class="flex items-center bg-blue-50 px-4 py-2">
                                         <div class="flex-grow">
@@ -302,8 +1730,48 @@ impl Component for Chat {
                                 html! {}
                             }
                         }
-                        <div class="w-full h-14 flex px-3 items-center">
-                            <input ref={self.chat_input.clone()} type="text" placeholder="Message" class="block w-full py-2 pl-4 mx-3 bg-gray-100 rounded-full outline-none focus:text-gray-700" name="message" required=true />
+                        {
+                            if !suggestions.is_empty() {
+                                html! {
+                                    <div class="mx-6 mb-1 bg-white border border-gray-200 rounded-lg shadow-sm">
+                                        {
+                                            suggestions.iter().map(|c| {
+                                                let name = c.name.clone();
+                                                let onclick = ctx.link().callback(move |_| Msg::CompleteCommand(name.clone()));
+                                                html! {
+                                                    <div {onclick} class="flex justify-between px-4 py-1 text-xs cursor-pointer hover:bg-blue-50">
+                                                        <span class="font-mono text-blue-600">{c.usage.clone()}</span>
+                                                        <span class="text-gray-500">{c.description.clone()}</span>
+                                                    </div>
+                                                }
+                                            }).collect::<Html>()
+                                        }
+                                    </div>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        if self.preview && !self.composer.trim().is_empty() {
+                            <div class="mx-6 mb-1 p-2 text-xs text-gray-500 bg-gray-100 border border-gray-200 rounded-lg">
+                                <div class="mb-1 text-gray-400 uppercase" style="font-size: 0.6rem">{"Preview"}</div>
+                                <TextContent text={self.composer.clone()} />
+                            </div>
+                        }
+                        if let Some(ref name) = self.uploading {
+                            <div class="mx-6 mb-1 text-xs text-gray-500 italic">{format!("Uploading {}…", name)}</div>
+                        }
+                        <div class="w-full min-h-[3.5rem] flex px-3 py-2 items-end">
+                            <input ref={self.file_input.clone()} type="file" class="hidden" accept={ALLOWED_TYPES.join(",")} onchange={ctx.link().callback(|_| Msg::FileSelected)} />
+                            <button onclick={ctx.link().callback(|_| Msg::PickFile)} class="p-2 text-gray-500 hover:text-gray-700 rounded-full" title="Attach a file">
+                                <svg xmlns="http://www.w3.org/2000/svg" width="20" height="20" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+                                    <path d="M21.44 11.05l-9.19 9.19a6 6 0 0 1-8.49-8.49l9.19-9.19a4 4 0 0 1 5.66 5.66l-9.2 9.19a2 2 0 0 1-2.83-2.83l8.49-8.48"></path>
+                                </svg>
+                            </button>
+                            <button onclick={ctx.link().callback(|_| Msg::TogglePreview)} class={classes!("p-2", "text-sm", "font-semibold", "rounded-full", if self.preview { "text-blue-600" } else { "text-gray-500" })} title="Preview Markdown">
+                                {"Md"}
+                            </button>
+                            <textarea ref={self.chat_input.clone()} {oninput} {onkeydown} {onblur} rows={composer_rows(&self.composer).to_string()} placeholder="Message (Shift+Enter for a new line)" class="block w-full py-2 px-4 mx-3 bg-gray-100 rounded-2xl outline-none resize-none focus:text-gray-700" name="message" required=true />
                             <button onclick={submit} class="p-3 shadow-sm bg-blue-600 w-10 h-10 rounded-full flex justify-center items-center color-white">
                                 <svg fill="#000000" viewBox="0 0 24 24" xmlns="http://www.w3.org/2000/svg" class="fill-white">
                                     <path d="M0 0h24v24H0z" fill="none"></path><path d="M2.01 21L23 12 2.01 3 2 10l15 2-15 2z"></path>
@@ -312,6 +1780,8 @@ impl Component for Chat {
                         </div>
                     </div>
                 </div>
+                {self.view_thread_panel(ctx)}
+                {self.view_search_panel(ctx)}
             </div>
         }
     }
@@ -0,0 +1,124 @@
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use yew::prelude::*;
+
+use super::highlight::highlight;
+
+// Blocks longer than this are collapsed until expanded
+const COLLAPSED_LINES: usize = 15;
+
+// How long "Copied" stays on the copy button
+const COPIED_MS: i32 = 2000;
+
+#[derive(Properties, PartialEq)]
+pub struct CodeBlockProps {
+    pub lang: Option<String>,
+    pub code: String,
+}
+
+// `navigator.clipboard` is still an unstable API in web-sys, so it is reached through js-sys
+fn copy_to_clipboard(text: &str) -> bool {
+    let Some(window) = web_sys::window() else {
+        return false;
+    };
+    let clipboard = js_sys::Reflect::get(&window.navigator(), &JsValue::from_str("clipboard"))
+        .ok()
+        .filter(|c| !c.is_undefined());
+    let write_text = clipboard.as_ref().and_then(|c| {
+        js_sys::Reflect::get(c, &JsValue::from_str("writeText"))
+            .ok()?
+            .dyn_into::<js_sys::Function>()
+            .ok()
+    });
+    match (clipboard, write_text) {
+        (Some(clipboard), Some(write_text)) => {
+            write_text.call1(&clipboard, &JsValue::from_str(text)).is_ok()
+        }
+        _ => false,
+    }
+}
+
+// The lines of `code` to show and whether the block is long enough to collapse
+fn shown_lines(code: &str, expanded: bool) -> (String, bool) {
+    let collapsible = code.lines().count() > COLLAPSED_LINES;
+    let shown = if collapsible && !expanded {
+        code.lines().take(COLLAPSED_LINES).collect::<Vec<_>>().join("\n")
+    } else {
+        code.to_string()
+    };
+    (shown, collapsible)
+}
+
+// A highlighted code block with a copy button; long blocks start collapsed
+#[function_component(CodeBlock)]
+pub fn code_block(props: &CodeBlockProps) -> Html {
+    let expanded = use_state(|| false);
+    let copied = use_state(|| false);
+
+    let code = props.code.trim_end_matches('\n');
+    let line_count = code.lines().count();
+    let (shown, collapsible) = shown_lines(code, *expanded);
+
+    let copy = {
+        let code = code.to_string();
+        let copied = copied.clone();
+        Callback::from(move |_| {
+            if !copy_to_clipboard(&code) {
+                return;
+            }
+            copied.set(true);
+            let copied = copied.clone();
+            let reset = Closure::once_into_js(move || copied.set(false));
+            if let Some(window) = web_sys::window() {
+                let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                    reset.unchecked_ref(),
+                    COPIED_MS,
+                );
+            }
+        })
+    };
+    let toggle = {
+        let expanded = expanded.clone();
+        Callback::from(move |_| expanded.set(!*expanded))
+    };
+
+    html! {
+        <div class="relative my-1 rounded bg-gray-800 text-gray-100 text-xs">
+            <div class="flex justify-between items-center px-2 pt-1 text-gray-400" style="font-size: 0.65rem">
+                <span>{props.lang.clone().unwrap_or_default()}</span>
+                <button onclick={copy} class="hover:text-gray-100">{if *copied { "Copied" } else { "Copy" }}</button>
+            </div>
+            <pre class="px-2 pb-2 overflow-x-auto">
+                <code>{highlight(props.lang.as_deref(), &shown)}</code>
+            </pre>
+            if collapsible {
+                <button onclick={toggle} class="w-full py-1 text-gray-400 hover:text-gray-100 border-t border-gray-700" style="font-size: 0.65rem">
+                    {if *expanded { "Show less".to_string() } else { format!("Show all {} lines", line_count) }}
+                </button>
+            }
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(count: usize) -> String {
+        (1..=count).map(|n| format!("line {}", n)).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn short_blocks_are_shown_whole() {
+        let code = lines(COLLAPSED_LINES);
+        assert_eq!(shown_lines(&code, false), (code.clone(), false));
+    }
+
+    #[test]
+    fn long_blocks_collapse_until_expanded() {
+        let code = lines(COLLAPSED_LINES + 1);
+        let (shown, collapsible) = shown_lines(&code, false);
+        assert!(collapsible);
+        assert_eq!(shown, lines(COLLAPSED_LINES));
+        assert_eq!(shown_lines(&code, true), (code, true));
+    }
+}
@@ -0,0 +1,126 @@
+use serde::Deserialize;
+use yew::prelude::*;
+
+use super::markdown;
+use crate::services::location;
+
+// How a message's content is shown, as decided by the server
+pub use chat_protocol::ContentKind;
+
+// An `@username` in a message, as byte offsets into its text
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Mention {
+    pub user: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+// An uploaded file; `url` is relative to the HTTP server's base URL
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct Attachment {
+    pub id: String,
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub url: String,
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{:.1} MB", b as f64 / 1024.0 / 1024.0),
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct TextProps {
+    pub text: String,
+    #[prop_or_default]
+    pub mentions: Vec<Mention>,
+    // Current user, whose mentions stand out
+    #[prop_or_default]
+    pub me: String,
+}
+
+// Message text rendered as Markdown, with mentions highlighted
+#[function_component(TextContent)]
+pub fn text_content(props: &TextProps) -> Html {
+    html! {
+        <div class="break-words">{markdown::render(&props.text, &props.mentions, &props.me)}</div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ImageProps {
+    pub url: String,
+}
+
+// A linked image, falling back to the plain link if it fails to load
+#[function_component(ImageContent)]
+pub fn image_content(props: &ImageProps) -> Html {
+    let failed = use_state(|| false);
+    let onerror = {
+        let failed = failed.clone();
+        Callback::from(move |_| failed.set(true))
+    };
+
+    if *failed {
+        return html! {
+            <a href={props.url.clone()} target="_blank" rel="noopener noreferrer" class="text-blue-600 underline break-all">{props.url.clone()}</a>
+        };
+    }
+    html! {
+        <a href={props.url.clone()} target="_blank" rel="noopener noreferrer">
+            <img class="mt-3 max-w-xs max-h-64 rounded-lg" src={props.url.clone()} {onerror}/>
+        </a>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct AttachmentProps {
+    pub attachment: Attachment,
+}
+
+// Uploaded images show as previews, other files as download cards
+#[function_component(AttachmentContent)]
+pub fn attachment_content(props: &AttachmentProps) -> Html {
+    let attachment = &props.attachment;
+    let url = format!("{}{}", location::files_url(), attachment.url);
+    if attachment.mime.starts_with("image/") {
+        let width = attachment.width.map(|w| w.to_string());
+        let height = attachment.height.map(|h| h.to_string());
+        html! {
+            <a href={url.clone()} target="_blank">
+                <img class="mt-2 max-w-xs max-h-64 w-auto h-auto rounded-lg" src={url} alt={attachment.name.clone()} {width} {height}/>
+            </a>
+        }
+    } else {
+        html! {
+            <a href={url} download={attachment.name.clone()} class="mt-2 flex items-center p-2 bg-white border border-gray-200 rounded-lg hover:bg-gray-50">
+                <span class="text-2xl mr-2">{"📎"}</span>
+                <span class="min-w-0">
+                    <span class="block text-sm text-gray-700 truncate">{attachment.name.clone()}</span>
+                    <span class="block text-xs text-gray-400">{format_size(attachment.size)}</span>
+                </span>
+            </a>
+        }
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct SystemProps {
+    pub text: String,
+}
+
+// Notices from the server, shown between messages rather than in a bubble
+#[function_component(SystemContent)]
+pub fn system_content(props: &SystemProps) -> Html {
+    html! {
+        <div class="mx-8 my-2 text-xs text-gray-500 italic whitespace-pre-line">
+            {props.text.clone()}
+        </div>
+    }
+}
@@ -0,0 +1,320 @@
+//! A small syntax highlighter for code blocks. It only tells apart keywords,
+//! strings, comments and numbers, which is enough for chat snippets and keeps
+//! the wasm bundle small.
+
+use yew::prelude::*;
+
+struct Language {
+    names: &'static [&'static str],
+    keywords: &'static [&'static str],
+    // Whether keywords match regardless of case, as in SQL
+    ignore_case: bool,
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        ignore_case: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+    },
+    Language {
+        names: &["javascript", "js", "typescript", "ts", "jsx", "tsx"],
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue", "default",
+            "delete", "do", "else", "export", "extends", "false", "finally", "for", "from",
+            "function", "if", "import", "in", "instanceof", "interface", "let", "new", "null",
+            "return", "switch", "this", "throw", "true", "try", "type", "typeof", "undefined",
+            "var", "void", "while", "yield",
+        ],
+        ignore_case: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+            "True", "try", "while", "with", "yield",
+        ],
+        ignore_case: false,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+    },
+    Language {
+        names: &["go", "golang"],
+        keywords: &[
+            "break", "case", "chan", "const", "continue", "default", "defer", "else", "false",
+            "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+            "range", "return", "select", "struct", "switch", "true", "type", "var",
+        ],
+        ignore_case: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+    },
+    Language {
+        names: &["c", "cpp", "c++", "h", "java", "cs", "csharp", "kotlin"],
+        keywords: &[
+            "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue",
+            "default", "delete", "do", "double", "else", "enum", "extends", "false", "final",
+            "float", "for", "if", "implements", "import", "int", "long", "namespace", "new",
+            "null", "nullptr", "package", "private", "protected", "public", "return", "short",
+            "static", "struct", "switch", "template", "this", "throw", "true", "try", "typedef",
+            "unsigned", "using", "var", "virtual", "void", "while",
+        ],
+        ignore_case: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+    },
+    Language {
+        names: &["bash", "sh", "shell", "zsh"],
+        keywords: &[
+            "case", "do", "done", "echo", "elif", "else", "esac", "export", "fi", "for",
+            "function", "if", "in", "local", "return", "then", "until", "while",
+        ],
+        ignore_case: false,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+    },
+    Language {
+        names: &["sql"],
+        keywords: &[
+            "and", "as", "by", "create", "delete", "desc", "from", "group", "having", "insert",
+            "into", "join", "left", "limit", "not", "null", "on", "or", "order", "select", "set",
+            "table", "update", "values", "where",
+        ],
+        ignore_case: true,
+        line_comment: Some("--"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['\''],
+    },
+    Language {
+        names: &["json"],
+        keywords: &["true", "false", "null"],
+        ignore_case: false,
+        line_comment: None,
+        block_comment: None,
+        quotes: &['"'],
+    },
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Plain,
+    Keyword,
+    Str,
+    Comment,
+    Number,
+}
+
+fn token_class(token: Token) -> Option<&'static str> {
+    match token {
+        Token::Plain => None,
+        Token::Keyword => Some("text-purple-300"),
+        Token::Str => Some("text-green-300"),
+        Token::Comment => Some("text-gray-400 italic"),
+        Token::Number => Some("text-orange-300"),
+    }
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn find_language(lang: &str) -> Option<&'static Language> {
+    let lang = lang.to_ascii_lowercase();
+    LANGUAGES.iter().find(|l| l.names.contains(&lang.as_str()))
+}
+
+/// Highlights `code` written in `lang`; unknown languages are left plain.
+pub fn highlight(lang: Option<&str>, code: &str) -> Html {
+    let Some(language) = lang.and_then(find_language) else {
+        return html! { {code.to_string()} };
+    };
+
+    tokenize(language, code)
+        .into_iter()
+        .map(|(token, text)| match token_class(token) {
+            Some(class) => html! { <span {class}>{text}</span> },
+            None => html! { {text} },
+        })
+        .collect::<Html>()
+}
+
+// Splits `code` into runs of the same token kind
+fn tokenize<'a>(language: &Language, code: &'a str) -> Vec<(Token, &'a str)> {
+    // Token kinds with the byte range they cover
+    let mut tokens: Vec<(Token, usize, usize)> = vec![];
+
+    let mut i = 0;
+    while i < code.len() {
+        let rest = &code[i..];
+        let c = rest.chars().next().unwrap();
+
+        let (token, len) = if let Some(end) = language
+            .line_comment
+            .filter(|start| rest.starts_with(*start))
+            .map(|_| rest.find('\n').unwrap_or(rest.len()))
+        {
+            (Token::Comment, end)
+        } else if let Some((open, close)) = language
+            .block_comment
+            .filter(|(open, _)| rest.starts_with(*open))
+        {
+            let end = rest[open.len()..]
+                .find(close)
+                .map_or(rest.len(), |end| open.len() + end + close.len());
+            (Token::Comment, end)
+        } else if language.quotes.contains(&c) {
+            (Token::Str, string_len(rest, c))
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(is_ident(c) || c == '.'))
+                .unwrap_or(rest.len());
+            (Token::Number, end)
+        } else if is_ident(c) {
+            let end = rest.find(|c: char| !is_ident(c)).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let keyword = if language.ignore_case {
+                language.keywords.iter().any(|k| k.eq_ignore_ascii_case(word))
+            } else {
+                language.keywords.contains(&word)
+            };
+            (if keyword { Token::Keyword } else { Token::Plain }, end)
+        } else {
+            (Token::Plain, c.len_utf8())
+        };
+
+        match tokens.last_mut() {
+            // Merge neighbouring plain runs so the output has fewer nodes
+            Some((Token::Plain, _, end)) if token == Token::Plain => *end += len,
+            _ => tokens.push((token, i, i + len)),
+        }
+        i += len;
+    }
+
+    tokens
+        .into_iter()
+        .map(|(token, start, end)| (token, &code[start..end]))
+        .collect()
+}
+
+// Length of a string literal opened by `quote`, escapes included
+fn string_len(rest: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (index, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            // Only template strings span lines
+            '\n' if quote != '`' => return index,
+            c if c == quote => return index + c.len_utf8(),
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens<'a>(lang: &str, code: &'a str) -> Vec<(Token, &'a str)> {
+        tokenize(find_language(lang).unwrap(), code)
+    }
+
+    #[test]
+    fn finds_languages_by_any_name() {
+        assert!(find_language("RS").is_some());
+        assert!(find_language("ts").is_some());
+        assert!(find_language("brainfuck").is_none());
+    }
+
+    #[test]
+    fn tells_keywords_from_identifiers() {
+        assert_eq!(
+            tokens("rust", "let letter = 42;"),
+            [
+                (Token::Keyword, "let"),
+                (Token::Plain, " letter = "),
+                (Token::Number, "42"),
+                (Token::Plain, ";"),
+            ]
+        );
+        assert_eq!(tokens("sql", "SELECT")[0], (Token::Keyword, "SELECT"));
+    }
+
+    #[test]
+    fn strings_keep_escaped_quotes() {
+        assert_eq!(
+            tokens("rust", r#""say \"hi\"" x"#),
+            [(Token::Str, r#""say \"hi\"""#), (Token::Plain, " x")]
+        );
+    }
+
+    #[test]
+    fn unterminated_strings_end_with_the_line() {
+        assert_eq!(
+            tokens("python", "x = 'open\nif"),
+            [
+                (Token::Plain, "x = "),
+                (Token::Str, "'open"),
+                (Token::Plain, "\n"),
+                (Token::Keyword, "if"),
+            ]
+        );
+        // Template strings span lines, so an open one runs to the end
+        assert_eq!(
+            tokens("js", "`open\nif"),
+            [(Token::Str, "`open\nif")]
+        );
+    }
+
+    #[test]
+    fn comments_end_with_the_line_or_their_closing_mark() {
+        assert_eq!(
+            tokens("sh", "# note\nfi"),
+            [
+                (Token::Comment, "# note"),
+                (Token::Plain, "\n"),
+                (Token::Keyword, "fi"),
+            ]
+        );
+        assert_eq!(
+            tokens("c", "/* a */ int"),
+            [
+                (Token::Comment, "/* a */"),
+                (Token::Plain, " "),
+                (Token::Keyword, "int"),
+            ]
+        );
+        assert_eq!(
+            tokens("go", "/* never closed\nfunc"),
+            [(Token::Comment, "/* never closed\nfunc")]
+        );
+    }
+
+    #[test]
+    fn multibyte_text_is_not_split() {
+        let code = "// héllo\nlet ü = \"ß\"";
+        let joined: String = tokens("rust", code).into_iter().map(|(_, text)| text).collect();
+        assert_eq!(joined, code);
+    }
+}
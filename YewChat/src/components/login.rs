@@ -10,6 +10,7 @@ use crate::User;
 #[function_component(Login)]
 pub fn login() -> Html {
     let username = use_state(|| String::new());
+    let secret = use_state(|| String::new());
     let user = use_context::<User>().expect("No context found.");
 
     let oninput = {
@@ -21,10 +22,23 @@ pub fn login() -> Html {
         })
     };
 
+    let onsecret = {
+        let current_secret = secret.clone();
+
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            current_secret.set(input.value());
+        })
+    };
+
     let onclick = {
         let username = username.clone();
+        let secret = secret.clone();
         let user = user.clone();
-        Callback::from(move |_| *user.username.borrow_mut() = (*username).clone())
+        Callback::from(move |_| {
+            *user.username.borrow_mut() = (*username).clone();
+            *user.secret.borrow_mut() = (*secret).clone();
+        })
     };
 
     html! {
@@ -32,6 +46,7 @@ pub fn login() -> Html {
             <div class="container mx-auto flex flex-col justify-center items-center	">
                 <form class="m-4 flex">
                     <input {oninput} class="rounded-l-lg p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Username"/>
+                    <input oninput={onsecret} type="password" class="p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Moderator secret (optional)"/>
                     <Link<Route> to={Route::Chat}> <button {onclick} disabled={username.len()<1} class="px-8 rounded-r-lg bg-violet-600	  text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r" >{"Go Chatting!"}</button></Link<Route>>
                 </form>
             </div>
@@ -0,0 +1,265 @@
+//! Renders the Markdown subset chat messages may use straight to Yew nodes.
+//! Raw HTML is never interpreted: it shows up as the text it was typed as.
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use yew::prelude::*;
+
+use super::code_block::CodeBlock;
+use super::content::Mention;
+
+// Link schemes that may become clickable; anything else stays plain text
+const SAFE_SCHEMES: &[&str] = &["http://", "https://", "mailto:"];
+
+// Element being built while its children are rendered
+enum Node {
+    Paragraph,
+    Heading,
+    BlockQuote,
+    CodeBlock(Option<String>),
+    List(Option<u64>),
+    Item,
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Link(Option<String>),
+    // Anything outside the subset; only its children are kept
+    Other,
+}
+
+struct Frame {
+    node: Node,
+    children: Vec<Html>,
+    // Raw text of a code block
+    code: String,
+}
+
+fn safe_url(url: &str) -> Option<String> {
+    let lower = url.trim().to_ascii_lowercase();
+    SAFE_SCHEMES
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+        .then(|| url.trim().to_string())
+}
+
+/// Renders `text` as Markdown, highlighting `mentions` (byte offsets into
+/// `text`) and making mentions of `me` stand out.
+pub fn render(text: &str, mentions: &[Mention], me: &str) -> Html {
+    let mut stack = vec![Frame {
+        node: Node::Other,
+        children: vec![],
+        code: String::new(),
+    }];
+
+    for (event, range) in Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH).into_offset_iter() {
+        let frame = stack.last_mut().unwrap();
+        match event {
+            Event::Start(tag) => {
+                let node = match tag {
+                    Tag::Paragraph => Node::Paragraph,
+                    Tag::Heading { .. } => Node::Heading,
+                    Tag::BlockQuote(_) => Node::BlockQuote,
+                    Tag::CodeBlock(CodeBlockKind::Fenced(lang)) if !lang.is_empty() => {
+                        Node::CodeBlock(Some(lang.to_string()))
+                    }
+                    Tag::CodeBlock(_) => Node::CodeBlock(None),
+                    Tag::List(start) => Node::List(start),
+                    Tag::Item => Node::Item,
+                    Tag::Emphasis => Node::Emphasis,
+                    Tag::Strong => Node::Strong,
+                    Tag::Strikethrough => Node::Strikethrough,
+                    Tag::Link { dest_url, .. } => Node::Link(safe_url(&dest_url)),
+                    // Images are shown as links so messages cannot pull in arbitrary content
+                    Tag::Image { dest_url, .. } => Node::Link(safe_url(&dest_url)),
+                    _ => Node::Other,
+                };
+                stack.push(Frame {
+                    node,
+                    children: vec![],
+                    code: String::new(),
+                });
+            }
+            Event::End(_) => {
+                let Some(frame) = stack.pop() else {
+                    break;
+                };
+                let html = close(frame);
+                stack.last_mut().unwrap().children.push(html);
+            }
+            Event::Text(content) if matches!(frame.node, Node::CodeBlock(_)) => {
+                frame.code.push_str(&content);
+            }
+            Event::Text(content) => {
+                // Mention offsets only line up when the text was not unescaped
+                let html = if text.get(range.clone()) == Some(&*content) {
+                    highlight(&content, range.start, mentions, me)
+                } else {
+                    html! { {content.to_string()} }
+                };
+                frame.children.push(html);
+            }
+            Event::Code(code) => frame.children.push(html! {
+                <code class="px-1 rounded bg-gray-200 text-gray-800 font-mono">{code.to_string()}</code>
+            }),
+            Event::Html(raw) | Event::InlineHtml(raw) => {
+                frame.children.push(html! { {raw.to_string()} });
+            }
+            Event::SoftBreak | Event::HardBreak => frame.children.push(html! { <br/> }),
+            Event::Rule => frame.children.push(html! { <hr class="my-2 border-gray-300"/> }),
+            Event::TaskListMarker(done) => {
+                frame.children.push(html! { {if done { "☑ " } else { "☐ " }} });
+            }
+            _ => {}
+        }
+    }
+
+    stack
+        .into_iter()
+        .flat_map(|frame| frame.children)
+        .collect::<Html>()
+}
+
+fn close(frame: Frame) -> Html {
+    let children = frame.children.into_iter().collect::<Html>();
+    match frame.node {
+        Node::Paragraph => html! { <p>{children}</p> },
+        Node::Heading => html! { <p class="font-semibold">{children}</p> },
+        Node::BlockQuote => html! {
+            <blockquote class="pl-2 border-l-4 border-gray-300 text-gray-500">{children}</blockquote>
+        },
+        Node::CodeBlock(lang) => html! { <CodeBlock {lang} code={frame.code} /> },
+        Node::List(Some(start)) => html! {
+            <ol class="pl-5 list-decimal" start={start.to_string()}>{children}</ol>
+        },
+        Node::List(None) => html! { <ul class="pl-5 list-disc">{children}</ul> },
+        Node::Item => html! { <li>{children}</li> },
+        Node::Emphasis => html! { <em>{children}</em> },
+        Node::Strong => html! { <strong>{children}</strong> },
+        Node::Strikethrough => html! { <del>{children}</del> },
+        Node::Link(Some(href)) => html! {
+            <a {href} target="_blank" rel="noopener noreferrer nofollow" class="text-blue-600 underline">{children}</a>
+        },
+        Node::Link(None) | Node::Other => children,
+    }
+}
+
+// Splits a run of text around the mentions that fall inside it
+fn highlight(content: &str, offset: usize, mentions: &[Mention], me: &str) -> Html {
+    let mut parts = vec![];
+    let mut position = 0;
+
+    for mention in mentions {
+        let (Some(start), Some(end)) = (
+            mention.start.checked_sub(offset),
+            mention.end.checked_sub(offset),
+        ) else {
+            continue;
+        };
+        let (Some(before), Some(name)) = (content.get(position..start), content.get(start..end))
+        else {
+            continue;
+        };
+        parts.push(html! { {before.to_string()} });
+        let class = if mention.user == me {
+            "px-1 rounded bg-yellow-200 text-gray-800 font-semibold"
+        } else {
+            "text-blue-600 font-semibold"
+        };
+        parts.push(html! { <span {class}>{name.to_string()}</span> });
+        position = end;
+    }
+    parts.push(html! { {content.get(position..).unwrap_or_default().to_string()} });
+
+    parts.into_iter().collect::<Html>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yew::virtual_dom::VNode;
+
+    // Markup of `html` with text escaped, so typed tags cannot pass for elements
+    fn markup(html: &Html) -> String {
+        match html {
+            VNode::VTag(tag) => {
+                let attributes: String = tag
+                    .attributes
+                    .iter()
+                    .filter(|(name, _)| *name == "href")
+                    .map(|(name, value)| format!(" {}=\"{}\"", name, value))
+                    .collect();
+                let children: String = tag.children().iter().map(markup).collect();
+                format!("<{0}{1}>{2}</{0}>", tag.tag(), attributes, children)
+            }
+            VNode::VText(text) => text.text.replace('&', "&amp;").replace('<', "&lt;"),
+            VNode::VList(list) => list.iter().map(markup).collect(),
+            _ => String::new(),
+        }
+    }
+
+    fn rendered(text: &str) -> String {
+        markup(&render(text, &[], "ann"))
+    }
+
+    #[test]
+    fn only_safe_schemes_make_links() {
+        assert_eq!(safe_url("https://example.com").as_deref(), Some("https://example.com"));
+        assert_eq!(safe_url("  HTTP://Example.com ").as_deref(), Some("HTTP://Example.com"));
+        assert_eq!(safe_url("mailto:ann@example.com").as_deref(), Some("mailto:ann@example.com"));
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "  javascript:alert(1)",
+            "data:text/html;base64,PHNjcmlwdD4=",
+            "DATA:text/html,<script>",
+            "vbscript:msgbox",
+            "//evil.example.com",
+            "/files/abc",
+        ] {
+            assert_eq!(safe_url(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn unsafe_links_keep_only_their_text() {
+        assert_eq!(rendered("[click](javascript:alert(1))"), "<p>click</p>");
+        assert_eq!(rendered("![x](data:image/png;base64,AAAA)"), "<p>x</p>");
+        assert_eq!(
+            rendered("[docs](https://example.com)"),
+            r#"<p><a href="https://example.com">docs</a></p>"#
+        );
+    }
+
+    #[test]
+    fn raw_html_is_shown_as_text() {
+        assert_eq!(
+            rendered("<script>alert(1)</script>"),
+            "&lt;script>alert(1)&lt;/script>"
+        );
+        assert_eq!(
+            rendered(r#"hi <img src=x onerror="alert(1)"> there"#),
+            r#"<p>hi &lt;img src=x onerror="alert(1)"> there</p>"#
+        );
+    }
+
+    #[test]
+    fn renders_the_subset() {
+        assert_eq!(
+            rendered("> **bold** and ~~gone~~\n\n- one\n- `two`"),
+            "<blockquote><p><strong>bold</strong> and <del>gone</del></p></blockquote>\
+             <ul><li>one</li><li><code>two</code></li></ul>"
+        );
+    }
+
+    #[test]
+    fn mentions_are_highlighted_in_place() {
+        let mentions = [Mention {
+            user: "ann".to_string(),
+            start: 3,
+            end: 7,
+        }];
+        assert_eq!(
+            markup(&render("hi @ann!", &mentions, "ann")),
+            "<p>hi <span>@ann</span>!</p>"
+        );
+    }
+}
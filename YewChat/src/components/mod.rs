@@ -1,2 +1,6 @@
 pub mod chat;
-pub mod login;
\ No newline at end of file
+pub mod code_block;
+pub mod content;
+pub mod highlight;
+pub mod login;
+pub mod markdown;
\ No newline at end of file
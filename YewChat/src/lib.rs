@@ -17,6 +17,8 @@ pub type User = Rc<UserInner>;
 #[derive(Debug, PartialEq)]
 pub struct UserInner {
     pub username: RefCell<String>,
+    /// Moderator secret typed at login, empty for everyone else.
+    pub secret: RefCell<String>,
 }
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
@@ -52,6 +54,7 @@ fn main() -> Html {
     let ctx = use_state(|| {
         Rc::new(UserInner {
             username: RefCell::new("initial".into()),
+            secret: RefCell::new(String::new()),
         })
     });
 
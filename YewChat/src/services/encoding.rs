@@ -0,0 +1,31 @@
+//! Wire encodings the client can speak, picked with `?encoding=msgpack` or
+//! `?encoding=cbor` in the page URL. JSON stays the default; the binary
+//! encodings are offered through `Sec-WebSocket-Protocol` and only work with
+//! servers that support them. The codec itself is shared with the server
+//! through `chat_protocol::Encoding`.
+
+use chat_protocol::Encoding;
+use gloo_net::websocket::Message;
+
+use super::location::query_param;
+
+/// Encoding asked for in the page URL.
+pub fn from_location() -> Encoding {
+    match query_param("encoding").as_deref() {
+        Some("msgpack") => Encoding::MessagePack,
+        Some("cbor") => Encoding::Cbor,
+        _ => Encoding::Json,
+    }
+}
+
+/// Converts a JSON text frame on its way to the server. Binary frames are
+/// file chunks and go out as they are.
+pub fn outgoing(encoding: Encoding, message: Message) -> Message {
+    match message {
+        Message::Text(json) if encoding != Encoding::Json => match encoding.encode(&json) {
+            Some(bytes) => Message::Bytes(bytes),
+            None => Message::Text(json),
+        },
+        message => message,
+    }
+}
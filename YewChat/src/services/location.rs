@@ -0,0 +1,70 @@
+//! Where the chat server is, worked out from the page YewChat was loaded
+//! from: the same host on the server's default ports. `?server=ws://...` and
+//! `?files=http://...` in the page URL point YewChat somewhere else.
+
+// Ports the server listens on unless CHAT_ADDR or CHAT_HTTP_ADDR say otherwise
+const SERVER_PORT: u16 = 8080;
+const FILES_PORT: u16 = 8081;
+
+/// Value of `name` in the page URL's query string.
+pub fn query_param(name: &str) -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    let value = find_param(&search, name)?;
+    js_sys::decode_uri_component(value)
+        .ok()
+        .and_then(|decoded| decoded.as_string())
+}
+
+// Raw value of `name` in a `?a=1&b=2` query string
+fn find_param<'a>(search: &'a str, name: &str) -> Option<&'a str> {
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+}
+
+// Host of the page and whether it was loaded over HTTPS
+fn page() -> (String, bool) {
+    let location = web_sys::window().map(|window| window.location());
+    let host = location
+        .as_ref()
+        .and_then(|l| l.hostname().ok())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "127.0.0.1".to_string());
+    let secure = location.and_then(|l| l.protocol().ok()).as_deref() == Some("https:");
+    (host, secure)
+}
+
+/// WebSocket URL of the chat server.
+pub fn server_url() -> String {
+    query_param("server").unwrap_or_else(|| {
+        let (host, secure) = page();
+        let scheme = if secure { "wss" } else { "ws" };
+        format!("{}://{}:{}", scheme, host, SERVER_PORT)
+    })
+}
+
+/// Base URL of the HTTP server uploaded files are served from.
+pub fn files_url() -> String {
+    let url = query_param("files").unwrap_or_else(|| {
+        let (host, secure) = page();
+        let scheme = if secure { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, host, FILES_PORT)
+    });
+    url.trim_end_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_params_by_their_whole_name() {
+        let search = "?encoding=cbor&files=http%3A%2F%2Fchat%3A9000&server=";
+        assert_eq!(find_param(search, "encoding"), Some("cbor"));
+        assert_eq!(find_param(search, "files"), Some("http%3A%2F%2Fchat%3A9000"));
+        assert_eq!(find_param(search, "server"), Some(""));
+        assert_eq!(find_param(search, "enc"), None);
+        assert_eq!(find_param("", "encoding"), None);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod encoding;
+pub mod location;
 pub mod websocket;
 pub mod event_bus;
\ No newline at end of file
@@ -1,27 +1,40 @@
 use futures::{channel::mpsc::Sender, SinkExt, StreamExt};
-use reqwasm::websocket::{futures::WebSocket, Message};
+use gloo_net::websocket::{futures::WebSocket, Message};
 use yew_agent::Dispatched;
+use chat_protocol::Encoding;
+use crate::services::{encoding, location};
 use crate::services::event_bus::{EventBus, Request};
 
 use wasm_bindgen_futures::spawn_local;
 
 pub struct WebsocketService {
-    pub tx: Sender<String>,
+    // Text frames carry JSON, binary frames carry file uploads
+    pub tx: Sender<Message>,
 }
 
 impl WebsocketService {
     pub fn new() -> Self {
-        let ws = WebSocket::open("ws://127.0.0.1:8080").unwrap();
+        // Browsers refuse the connection unless the server accepts the
+        // requested encoding, so once open it is the one in use
+        let encoding = encoding::from_location();
+        let url = location::server_url();
+        let ws = match encoding {
+            Encoding::Json => WebSocket::open(&url),
+            encoding => WebSocket::open_with_protocol(&url, encoding.protocol()),
+        }
+        .unwrap();
 
         let (mut write, mut read) = ws.split();
 
-        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
+        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<Message>(1000);
         let mut event_bus = EventBus::dispatcher();
 
         spawn_local(async move {
-            while let Some(s) = in_rx.next().await {
-                log::debug!("got event from channel! {}", s);
-                write.send(Message::Text(s)).await.unwrap();
+            while let Some(message) = in_rx.next().await {
+                if let Message::Text(s) = &message {
+                    log::debug!("got event from channel! {}", s);
+                }
+                write.send(encoding::outgoing(encoding, message)).await.unwrap();
             }
         });
 
@@ -33,10 +46,9 @@ impl WebsocketService {
                         event_bus.send(Request::EventBusMsg(data));
                     }
                     Ok(Message::Bytes(b)) => {
-                        let decoded = std::str::from_utf8(&b);
-                        if let Ok(val) = decoded {
+                        if let Some(val) = encoding.decode(&b) {
                             log::debug!("from websocket: {}", val);
-                            event_bus.send(Request::EventBusMsg(val.into()));
+                            event_bus.send(Request::EventBusMsg(val));
                         }
                     }
                     Err(e) => {